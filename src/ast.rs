@@ -0,0 +1,608 @@
+use crate::token::Span;
+
+use std::fmt;
+
+/// Integer type names of X, ordered by width
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IntKind {
+    Char,
+    Short,
+    Int,
+    Long,
+}
+
+/// Size in bytes of the builtin types, keyed by their keyword;
+/// `unsigned` on its own is an unsigned int and never changes the size
+pub const TYPE_SIZES: [(&str, usize); 7] = [
+    ("unsigned", 4),
+    ("char", 1),
+    ("short", 2),
+    ("int", 4),
+    ("long", 8),
+    ("float", 4),
+    ("double", 8),
+];
+
+/// Size in bytes of every pointer
+pub const POINTER_SIZE: usize = 8;
+
+fn keyword_size(keyword: &str) -> usize {
+    TYPE_SIZES.iter().find(|(k, _)| *k == keyword).unwrap().1
+}
+
+impl IntKind {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            IntKind::Char => "char",
+            IntKind::Short => "short",
+            IntKind::Int => "int",
+            IntKind::Long => "long",
+        }
+    }
+}
+
+/// Data types of X values
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Void,
+    Integer {
+        kind: IntKind,
+        unsigned: bool,
+    },
+    Float,
+    Double,
+    /// element type and, if known, the number of elements
+    Array(Box<Type>, Option<usize>),
+    Pointer(Box<Type>),
+    /// struct tag, the members are found in the matching StructDeclaration
+    Struct(String),
+}
+
+impl Type {
+    pub fn is_array(&self) -> bool {
+        matches!(self, Type::Array(_, _))
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, Type::Pointer(_))
+    }
+
+    pub fn is_scalar(&self) -> bool {
+        matches!(
+            self,
+            Type::Integer { .. } | Type::Float | Type::Double | Type::Pointer(_)
+        )
+    }
+
+    /// size in bytes as reported by `sizeof`, None for void, arrays of unknown
+    /// size and structs without a definition; `members` looks up a struct tag
+    pub fn size(&self, members: &dyn Fn(&str) -> Option<Vec<Member>>) -> Option<usize> {
+        match self {
+            Type::Void => None,
+            Type::Integer { kind, .. } => Some(keyword_size(kind.keyword())),
+            Type::Float => Some(keyword_size("float")),
+            Type::Double => Some(keyword_size("double")),
+            Type::Pointer(_) => Some(POINTER_SIZE),
+            Type::Array(element, size) => Some(element.size(members)? * (*size)?),
+            Type::Struct(name) => {
+                // members are laid out in order, each at a multiple of its alignment
+                let mut offset: usize = 0;
+                for member in members(name)? {
+                    let align = member.data_type.alignment(members)?;
+                    offset = offset.div_ceil(align) * align + member.data_type.size(members)?;
+                }
+                let align = self.alignment(members)?;
+                Some(offset.div_ceil(align) * align)
+            }
+        }
+    }
+
+    /// alignment in bytes, the size of the largest scalar inside the type
+    pub fn alignment(&self, members: &dyn Fn(&str) -> Option<Vec<Member>>) -> Option<usize> {
+        match self {
+            Type::Array(element, _) => element.alignment(members),
+            Type::Struct(name) => members(name)?
+                .iter()
+                .map(|member| member.data_type.alignment(members))
+                .try_fold(1, |max, align| Some(max.max(align?))),
+            _ => self.size(members),
+        }
+    }
+
+    /// type reached through `*` or `[]`: the target of a pointer or the element of an array
+    pub fn pointee(&self) -> Option<&Type> {
+        match self {
+            Type::Pointer(target) => Some(target),
+            Type::Array(element, _) => Some(element),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Integer { kind, unsigned } => {
+                if *unsigned {
+                    write!(f, "unsigned ")?;
+                }
+                write!(f, "{}", kind.keyword())
+            }
+            Type::Float => write!(f, "float"),
+            Type::Double => write!(f, "double"),
+            Type::Array(element, size) => match size {
+                Some(n) => write!(f, "{}[{}]", element, n),
+                None => write!(f, "{}[]", element),
+            },
+            Type::Pointer(target) => write!(f, "{}*", target),
+            Type::Struct(name) => write!(f, "struct {}", name),
+        }
+    }
+}
+
+/// Where a variable lives: `static` keeps its value between calls,
+/// `extern` only declares a variable defined elsewhere
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StorageClass {
+    #[default]
+    Auto,
+    Static,
+    Extern,
+}
+
+/// `const`, `static` and `extern` written in front of a declaration;
+/// const makes the declared variable itself read-only
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Qualifiers {
+    pub is_const: bool,
+    pub storage: StorageClass,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Negate,
+    /// `*p`
+    Dereference,
+    /// `&x`
+    AddressOf,
+}
+
+impl UnaryOperator {
+    pub fn from_text(s: &str) -> Option<UnaryOperator> {
+        match s {
+            "-" => Some(UnaryOperator::Negate),
+            "*" => Some(UnaryOperator::Dereference),
+            "&" => Some(UnaryOperator::AddressOf),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOperator::Negate => "-",
+            UnaryOperator::Dereference => "*",
+            UnaryOperator::AddressOf => "&",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+}
+
+impl BinaryOperator {
+    pub fn from_text(s: &str) -> Option<BinaryOperator> {
+        match s {
+            "+" => Some(BinaryOperator::Add),
+            "-" => Some(BinaryOperator::Subtract),
+            "*" => Some(BinaryOperator::Multiply),
+            "/" => Some(BinaryOperator::Divide),
+            "==" => Some(BinaryOperator::Equal),
+            "!=" => Some(BinaryOperator::NotEqual),
+            "<" => Some(BinaryOperator::Less),
+            ">" => Some(BinaryOperator::Greater),
+            "<=" => Some(BinaryOperator::LessEqual),
+            ">=" => Some(BinaryOperator::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::GreaterEqual => ">=",
+        }
+    }
+
+    pub fn is_relational(&self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Equal
+                | BinaryOperator::NotEqual
+                | BinaryOperator::Less
+                | BinaryOperator::Greater
+                | BinaryOperator::LessEqual
+                | BinaryOperator::GreaterEqual
+        )
+    }
+
+    /// binding strength: relational operators bind loosest, then + and -, then * and /
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Multiply | BinaryOperator::Divide => 2,
+            BinaryOperator::Add | BinaryOperator::Subtract => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpressionKind {
+    IntConstant(u64),
+    FloatConstant(f64),
+    /// enumerator, already replaced by its value
+    EnumConstant {
+        name: String,
+        value: i64,
+    },
+    /// `"..."`, escape sequences already replaced; only the format of printf
+    StringConstant(String),
+    Variable(String),
+    Index {
+        array: Box<Expression>,
+        index: Box<Expression>,
+    },
+    /// `base.member`
+    Member {
+        base: Box<Expression>,
+        member: String,
+    },
+    Call {
+        name: String,
+        arguments: Vec<Expression>,
+    },
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expression>,
+    },
+    /// `(DataType) operand`
+    Cast {
+        data_type: Type,
+        operand: Box<Expression>,
+    },
+    /// `sizeof(DataType)`
+    SizeOfType(Type),
+    /// `sizeof operand`, the operand is never evaluated
+    SizeOfExpression(Box<Expression>),
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Expression {
+        Expression { kind, span }
+    }
+
+    /// true for expressions that name a storage location: `x`, `a[i]`, `s.m` and `*p`
+    pub fn is_lvalue(&self) -> bool {
+        match &self.kind {
+            ExpressionKind::Variable(_)
+            | ExpressionKind::Index { .. }
+            | ExpressionKind::Member { .. } => true,
+            ExpressionKind::Unary { operator, .. } => operator == &UnaryOperator::Dereference,
+            _ => false,
+        }
+    }
+
+    /// call `f` on the expression and on every expression inside it
+    pub fn visit(&self, f: &mut dyn FnMut(&Expression)) {
+        f(self);
+        match &self.kind {
+            ExpressionKind::Index { array, index } => {
+                array.visit(f);
+                index.visit(f);
+            }
+            ExpressionKind::Member { base, .. } => base.visit(f),
+            ExpressionKind::Call { arguments, .. } => {
+                for argument in arguments {
+                    argument.visit(f);
+                }
+            }
+            ExpressionKind::Unary { operand, .. }
+            | ExpressionKind::Cast { operand, .. }
+            | ExpressionKind::SizeOfExpression(operand) => operand.visit(f),
+            ExpressionKind::Binary { left, right, .. } => {
+                left.visit(f);
+                right.visit(f);
+            }
+            _ => {}
+        }
+    }
+
+    /// value of an integer literal, looking through unary minus
+    pub fn as_int_constant(&self) -> Option<i128> {
+        match &self.kind {
+            ExpressionKind::IntConstant(n) => Some(*n as i128),
+            ExpressionKind::EnumConstant { value, .. } => Some(*value as i128),
+            ExpressionKind::Unary {
+                operator: UnaryOperator::Negate,
+                operand,
+            } => operand.as_int_constant().map(|n| -n),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the expression back as X source, with parentheses only where needed
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // operands that are binary expressions need parentheses when they bind looser
+        let operand = |f: &mut fmt::Formatter, expr: &Expression, min_precedence: u8| match &expr
+            .kind
+        {
+            ExpressionKind::Binary { operator, .. } if operator.precedence() < min_precedence => {
+                write!(f, "({})", expr)
+            }
+            _ => write!(f, "{}", expr),
+        };
+        match &self.kind {
+            ExpressionKind::IntConstant(n) => write!(f, "{}", n),
+            ExpressionKind::FloatConstant(x) => write!(f, "{:?}", x),
+            ExpressionKind::EnumConstant { name, .. } => write!(f, "{}", name),
+            ExpressionKind::StringConstant(text) => write!(f, "{:?}", text),
+            ExpressionKind::Variable(name) => write!(f, "{}", name),
+            ExpressionKind::Index { array, index } => {
+                operand(f, array, 3)?;
+                write!(f, "[{}]", index)
+            }
+            ExpressionKind::Member { base, member } => {
+                operand(f, base, 3)?;
+                write!(f, ".{}", member)
+            }
+            ExpressionKind::Call { name, arguments } => {
+                write!(f, "{}(", name)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            }
+            ExpressionKind::Unary {
+                operator,
+                operand: inner,
+            } => {
+                write!(f, "{}", operator.as_str())?;
+                operand(f, inner, 3)
+            }
+            ExpressionKind::Cast {
+                data_type,
+                operand: inner,
+            } => {
+                write!(f, "({}) ", data_type)?;
+                operand(f, inner, 3)
+            }
+            ExpressionKind::SizeOfType(data_type) => write!(f, "sizeof({})", data_type),
+            ExpressionKind::SizeOfExpression(inner) => write!(f, "sizeof({})", inner),
+            ExpressionKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                // operators are left associative, so only the right operand
+                // needs parentheses at the same precedence
+                operand(f, left, operator.precedence())?;
+                write!(f, " {} ", operator.as_str())?;
+                operand(f, right, operator.precedence() + 1)
+            }
+        }
+    }
+}
+
+/// Right hand side of a variable declaration, `= 1.4` or `= {1, 2, 3}`,
+/// lists initialise arrays element by element and structs member by member
+#[derive(Clone, Debug, PartialEq)]
+pub enum Initializer {
+    Expression(Expression),
+    List(Vec<Initializer>, Span),
+}
+
+impl fmt::Display for Initializer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Initializer::Expression(expr) => write!(f, "{}", expr),
+            Initializer::List(items, _) => {
+                write!(f, "{{")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariableDeclaration {
+    pub name: String,
+    pub data_type: Type,
+    pub initializer: Option<Initializer>,
+    pub qualifiers: Qualifiers,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub data_type: Type,
+    pub qualifiers: Qualifiers,
+    pub span: Span,
+}
+
+/// Function prototype: return type, name and parameters
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub return_type: Type,
+    pub parameters: Vec<Parameter>,
+    pub qualifiers: Qualifiers,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub data_type: Type,
+    pub span: Span,
+}
+
+/// `struct Name { ... };`
+#[derive(Clone, Debug, PartialEq)]
+pub struct StructDeclaration {
+    pub name: String,
+    pub members: Vec<Member>,
+    pub span: Span,
+}
+
+/// `typedef DataType Name;`, uses of Name are already replaced by the parser
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypedefDeclaration {
+    pub name: String,
+    pub data_type: Type,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Enumerator {
+    pub name: String,
+    pub value: i64,
+    pub span: Span,
+}
+
+/// `enum [Name] { A, B = 5, C };`, a variable of an enum type is an int
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnumDeclaration {
+    pub name: Option<String>,
+    pub enumerators: Vec<Enumerator>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Declaration {
+    Variable(VariableDeclaration),
+    Function(FunctionDeclaration),
+    Struct(StructDeclaration),
+    Typedef(TypedefDeclaration),
+    Enum(EnumDeclaration),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatementKind {
+    /// `a = b = Expression;`, every target is a variable, an indexed array or a dereference
+    Assignment {
+        targets: Vec<Expression>,
+        value: Expression,
+    },
+    While {
+        condition: Expression,
+        body: Block,
+    },
+    /// `for (init; condition; step) { ... }`, where init and step are an
+    /// assignment or a call and any of the three may be left out, a missing
+    /// condition being always true
+    For {
+        init: Option<Box<Statement>>,
+        condition: Option<Expression>,
+        step: Option<Box<Statement>>,
+        body: Block,
+    },
+    If {
+        condition: Expression,
+        body: Block,
+    },
+    /// `switch (value) { case 1: ... default: ... }`, control enters the body
+    /// at the statement after the matching label and falls through the rest
+    Switch {
+        value: Expression,
+        body: Block,
+        cases: Vec<Case>,
+    },
+    Return(Option<Expression>),
+    /// `break;`, leaving the innermost loop or switch
+    Break,
+    /// `F(x);`, a call made for what it does, any value it returns is dropped
+    Call(Expression),
+    /// declarations may appear anywhere in a block, visible until its end
+    Declaration(Declaration),
+    /// `{ ... }` nested in another block, opening a new scope
+    Block(Block),
+}
+
+/// a `case` or `default` label of a switch
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    /// constant integer expression, None for `default`
+    pub label: Option<Expression>,
+    /// index in the switch body of the first statement after the label
+    pub position: usize,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub span: Span,
+}
+
+/// Function definition: a prototype with a body
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub declaration: FunctionDeclaration,
+    pub body: Block,
+}
+
+/// Program := {Declaration} MainDeclaration {FunctionDefinition}
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub declarations: Vec<Declaration>,
+    pub main: Function,
+    pub functions: Vec<Function>,
+}
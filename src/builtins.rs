@@ -0,0 +1,333 @@
+use crate::ast::*;
+use crate::token::Span;
+use crate::value::{integer_range, Number};
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+/// `int printf(char *format, ...)`, the only builtin taking a variable number
+/// of arguments after the ones it declares
+pub const PRINTF: &str = "printf";
+
+/// Functions every X program may call without declaring them; the
+/// interpreter provides them, so the program never defines them
+pub fn declarations() -> Vec<FunctionDeclaration> {
+    let int = Type::Integer {
+        kind: IntKind::Int,
+        unsigned: false,
+    };
+    let char_pointer = Type::Pointer(Box::new(Type::Integer {
+        kind: IntKind::Char,
+        unsigned: false,
+    }));
+    let function =
+        |name: &str, return_type: Type, parameters: Vec<(&str, Type)>| FunctionDeclaration {
+            name: String::from(name),
+            return_type,
+            parameters: parameters
+                .into_iter()
+                .map(|(name, data_type)| Parameter {
+                    name: String::from(name),
+                    data_type,
+                    qualifiers: Qualifiers::default(),
+                    span: Span::default(),
+                })
+                .collect(),
+            qualifiers: Qualifiers::default(),
+            span: Span::default(),
+        };
+    vec![
+        function("print_int", Type::Void, vec![("value", int.clone())]),
+        function("print_float", Type::Void, vec![("value", Type::Double)]),
+        function("print_char", Type::Void, vec![("c", int.clone())]),
+        function("read_int", int.clone(), vec![]),
+        function(PRINTF, int, vec![("format", char_pointer)]),
+    ]
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    declarations().iter().any(|function| function.name == name)
+}
+
+/// Part of a printf format: text written as it is, or a conversion that
+/// writes the next argument
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormatPiece {
+    Text(String),
+    /// `%d`, an integer in decimal
+    Integer,
+    /// `%f`, a floating value with six decimals
+    Floating,
+    /// `%c`, the character whose code is an integer
+    Character,
+}
+
+/// Split a printf format into text and conversions; X only supports `%d`,
+/// `%f`, `%c` and `%%`, without flags, widths or precisions
+///
+/// # Arguments
+///
+/// * `format` - the format string, escape sequences already replaced
+///
+pub fn parse_format(format: &str) -> Result<Vec<FormatPiece>, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            text.push(ch);
+            continue;
+        }
+        let conversion = match chars.next() {
+            Some('%') => {
+                text.push('%');
+                continue;
+            }
+            Some('d') => FormatPiece::Integer,
+            Some('f') => FormatPiece::Floating,
+            Some('c') => FormatPiece::Character,
+            Some(other) => {
+                return Err(format!(
+                    "unsupported conversion `%{}` in the format of `printf`, only %d, %f, %c and %% are allowed",
+                    other
+                ))
+            }
+            None => return Err(String::from("the format of `printf` ends with a lone `%`")),
+        };
+        if !text.is_empty() {
+            pieces.push(FormatPiece::Text(std::mem::take(&mut text)));
+        }
+        pieces.push(conversion);
+    }
+    if !text.is_empty() {
+        pieces.push(FormatPiece::Text(text));
+    }
+    Ok(pieces)
+}
+
+/// number of arguments a parsed format takes
+pub fn conversions(pieces: &[FormatPiece]) -> usize {
+    pieces
+        .iter()
+        .filter(|piece| !matches!(piece, FormatPiece::Text(_)))
+        .count()
+}
+
+/// a floating value as `%f` writes it, with six decimals
+pub fn format_float(x: f64) -> String {
+    match x.is_nan() {
+        true => String::from("nan"),
+        false => format!("{:.6}", x),
+    }
+}
+
+/// Streams the builtin functions read from and write to, the standard ones
+/// unless others are given, so that a byte slice can stand for the keyboard
+/// and a `Vec<u8>` for the screen
+pub struct Io<'a> {
+    input: Box<dyn BufRead + 'a>,
+    output: Box<dyn Write + 'a>,
+    /// words of the last input line that read_int has not used yet
+    pending: VecDeque<String>,
+}
+
+impl<'a> Io<'a> {
+    /// # Arguments
+    ///
+    /// * `input` - where read_int reads from
+    /// * `output` - where the print functions and printf write to
+    ///
+    pub fn new(input: Box<dyn BufRead + 'a>, output: Box<dyn Write + 'a>) -> Io<'a> {
+        Io {
+            input,
+            output,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn standard() -> Io<'a> {
+        Io::new(Box::new(io::stdin().lock()), Box::new(io::stdout()))
+    }
+
+    /// Run a builtin other than printf
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the builtin
+    /// * `arguments` - its arguments, converted to the types of its parameters
+    ///
+    pub fn call(&mut self, name: &str, arguments: &[Number]) -> Result<Option<Number>, String> {
+        match name {
+            "print_int" => self.write(arguments[0].to_string().as_bytes())?,
+            "print_float" => self.write(format_float(arguments[0].as_f64()).as_bytes())?,
+            // the low byte of the code, as putchar writes it
+            "print_char" => self.write(&[arguments[0].as_integer().unwrap_or(0) as u8])?,
+            "read_int" => return self.read_int().map(Some),
+            _ => return Err(format!("`{}` is not a builtin function", name)),
+        }
+        Ok(None)
+    }
+
+    /// printf with the conversions `%d`, `%f` and `%c`, returning the number
+    /// of bytes written
+    ///
+    /// # Arguments
+    ///
+    /// * `pieces` - the parsed format
+    /// * `arguments` - the values after the format, one per conversion
+    ///
+    pub fn printf(
+        &mut self,
+        pieces: &[FormatPiece],
+        arguments: &[Number],
+    ) -> Result<Number, String> {
+        let mut rest = arguments.iter();
+        let mut bytes = Vec::new();
+        for piece in pieces {
+            if let FormatPiece::Text(text) = piece {
+                bytes.extend(text.as_bytes());
+                continue;
+            }
+            let number = match rest.next() {
+                Some(number) => number,
+                None => return Err(String::from("too few arguments for the format of `printf`")),
+            };
+            match piece {
+                FormatPiece::Floating => bytes.extend(format_float(number.as_f64()).as_bytes()),
+                FormatPiece::Character => bytes.push(number.as_integer().unwrap_or(0) as u8),
+                _ => bytes.extend(number.to_string().as_bytes()),
+            }
+        }
+        self.write(&bytes)?;
+        Ok(Number::int(bytes.len() as i128))
+    }
+
+    /// write what is still buffered, so that what was printed before a fault
+    /// is still shown
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.output
+            .flush()
+            .map_err(|error| format!("cannot write the output: {}", error))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.output
+            .write_all(bytes)
+            .map_err(|error| format!("cannot write the output: {}", error))
+    }
+
+    /// the next whitespace separated word of the input, as an int
+    fn read_int(&mut self) -> Result<Number, String> {
+        loop {
+            if let Some(word) = self.pending.pop_front() {
+                let (min, max) = integer_range(IntKind::Int, false);
+                return match word.parse::<i128>() {
+                    Ok(value) if value >= min && value <= max => Ok(Number::int(value)),
+                    _ => Err(format!("`read_int` expected an int, found `{}`", word)),
+                };
+            }
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) => return Err(String::from("`read_int` reached the end of the input")),
+                Ok(_) => self
+                    .pending
+                    .extend(line.split_whitespace().map(String::from)),
+                Err(error) => return Err(format!("cannot read the input: {}", error)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // run `f` on an Io reading `input`, returning what it wrote
+    fn output_of(input: &str, f: impl FnOnce(&mut Io)) -> String {
+        let mut output = Vec::new();
+        {
+            let mut io = Io::new(Box::new(input.as_bytes()), Box::new(&mut output));
+            f(&mut io);
+            io.flush().unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn read_int_takes_words_across_lines() {
+        output_of("12 -3\n\n  7\nx\n2147483648\n", |io| {
+            for expected in [12, -3, 7] {
+                assert_eq!(io.call("read_int", &[]), Ok(Some(Number::int(expected))));
+            }
+            assert_eq!(
+                io.call("read_int", &[]),
+                Err(String::from("`read_int` expected an int, found `x`"))
+            );
+            assert_eq!(
+                io.call("read_int", &[]),
+                Err(String::from(
+                    "`read_int` expected an int, found `2147483648`"
+                ))
+            );
+            assert_eq!(
+                io.call("read_int", &[]),
+                Err(String::from("`read_int` reached the end of the input"))
+            );
+        });
+    }
+
+    #[test]
+    fn print_functions() {
+        let output = output_of("", |io| {
+            io.call("print_int", &[Number::int(-42)]).unwrap();
+            io.call("print_char", &[Number::int(' ' as i128)]).unwrap();
+            io.call("print_float", &[Number::Double(2.5)]).unwrap();
+            // only the low byte of the code is written
+            io.call("print_char", &[Number::int(256 + '\n' as i128)])
+                .unwrap();
+            io.call("print_float", &[Number::Double(f64::NAN)]).unwrap();
+        });
+        assert_eq!(output, "-42 2.500000\nnan");
+    }
+
+    #[test]
+    fn printf_conversions() {
+        let output = output_of("", |io| {
+            let pieces = parse_format("%d%% of %c is %f\n").unwrap();
+            let written = io.printf(
+                &pieces,
+                &[
+                    Number::int(50),
+                    Number::int('x' as i128),
+                    Number::Double(0.5),
+                ],
+            );
+            assert_eq!(written, Ok(Number::int(21)));
+            let pieces = parse_format("%d and %d\n").unwrap();
+            assert_eq!(
+                io.printf(&pieces, &[Number::int(1)]),
+                Err(String::from("too few arguments for the format of `printf`"))
+            );
+        });
+        // nothing of a printf that fails is written
+        assert_eq!(output, "50% of x is 0.500000\n");
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        assert_eq!(
+            parse_format("%x"),
+            Err(String::from(
+                "unsupported conversion `%x` in the format of `printf`, only %d, %f, %c and %% are allowed"
+            ))
+        );
+        assert_eq!(
+            parse_format("100%"),
+            Err(String::from("the format of `printf` ends with a lone `%`"))
+        );
+        assert_eq!(
+            parse_format("%%"),
+            Ok(vec![FormatPiece::Text(String::from("%"))])
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use crate::ast::*;
+use crate::token::Span;
+use crate::value::Number;
+
+use std::fmt;
+
+/// A value of the constant pool
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constant {
+    Number(Number),
+    /// the null pointer
+    Null,
+    /// format of a printf call, escape sequences already replaced
+    Text(String),
+}
+
+impl Constant {
+    /// same bits, so that -0.0 and 0.0, or two NaNs, are told apart and
+    /// matched as the pool needs, unlike with `==`
+    fn identical(&self, other: &Constant) -> bool {
+        match (self, other) {
+            (Constant::Number(Number::Float(a)), Constant::Number(Number::Float(b))) => {
+                a.to_bits() == b.to_bits()
+            }
+            (Constant::Number(Number::Double(a)), Constant::Number(Number::Double(b))) => {
+                a.to_bits() == b.to_bits()
+            }
+            _ => self == other,
+        }
+    }
+}
+
+/// index of a constant in the pool, added to it unless already there
+pub fn intern(constants: &mut Vec<Constant>, constant: Constant) -> usize {
+    match constants.iter().position(|c| c.identical(&constant)) {
+        Some(index) => index,
+        None => {
+            constants.push(constant);
+            constants.len() - 1
+        }
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::Number(number) => write!(f, "{} {}", number.data_type(), number),
+            Constant::Null => write!(f, "NULL"),
+            Constant::Text(text) => write!(f, "{:?}", text),
+        }
+    }
+}
+
+/// Type a scalar is converted to, pointers all alike since their targets
+/// only matter to the compiler
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scalar {
+    Integer { kind: IntKind, unsigned: bool },
+    Float,
+    Double,
+    Pointer,
+}
+
+impl Scalar {
+    /// None for arrays, structs and void
+    pub fn of(data_type: &Type) -> Option<Scalar> {
+        match data_type {
+            Type::Integer { kind, unsigned } => Some(Scalar::Integer {
+                kind: *kind,
+                unsigned: *unsigned,
+            }),
+            Type::Float => Some(Scalar::Float),
+            Type::Double => Some(Scalar::Double),
+            Type::Pointer(_) => Some(Scalar::Pointer),
+            _ => None,
+        }
+    }
+
+    /// the arithmetic type, None for pointers
+    pub fn arithmetic(&self) -> Option<Type> {
+        match self {
+            Scalar::Integer { kind, unsigned } => Some(Type::Integer {
+                kind: *kind,
+                unsigned: *unsigned,
+            }),
+            Scalar::Float => Some(Type::Float),
+            Scalar::Double => Some(Type::Double),
+            Scalar::Pointer => None,
+        }
+    }
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.arithmetic() {
+            Some(data_type) => write!(f, "{}", data_type),
+            None => write!(f, "pointer"),
+        }
+    }
+}
+
+/// One instruction of the stack machine
+///
+/// Operands are popped from and results pushed to the operand stack, one
+/// stack entry per memory cell. Counts are in cells, jump targets are
+/// indices into the code of the same function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    /// push a constant of the pool
+    Push(usize),
+    /// push the address of a cell of the data segment
+    Global(usize),
+    /// push the address of a cell of the current frame
+    Local(usize),
+    /// pop an address, push the scalar stored there, which must be assigned
+    Load,
+    /// pop an address, push the cells of the struct stored there
+    Copy(usize),
+    /// pop an address, then pop the cells to store there
+    Store(usize),
+    /// pop an address, mark the cells there as never assigned
+    Clear(usize),
+    /// push a copy of the cells on top of the stack
+    Dup(usize),
+    /// discard the cells on top of the stack
+    Pop(usize),
+    /// convert the scalar on top as an assignment or a cast does
+    Convert(Scalar),
+    Negate,
+    /// arithmetic on two numbers, or a comparison of numbers and pointers
+    Binary(BinaryOperator),
+    /// pop a pointer and an integer in either order, push the pointer moved
+    /// forward by that many elements of the given number of cells
+    PointerAdd(usize),
+    /// pop an integer, then a pointer, and move the pointer back
+    PointerSubtract(usize),
+    /// pop two pointers, push the number of elements between them
+    PointerDifference(usize),
+    /// pop an index, then a pointer, push the address of that element
+    Index(usize),
+    /// fail unless the integer on top is an index of an array of this size
+    Bounds(usize),
+    /// add a member offset to the address on top
+    Offset(usize),
+    Jump(usize),
+    /// pop a condition, jump when it is zero or null
+    JumpIfFalse(usize),
+    /// call a function of the module, its arguments on top of the stack
+    Call(usize),
+    /// call the builtin at this index of `builtins::declarations`
+    Builtin(usize),
+    /// call printf with a text constant as the format and the given number
+    /// of arguments on top of the stack
+    Printf {
+        format: usize,
+        arguments: usize,
+    },
+    /// leave the function, its result on top of the stack
+    Return,
+    /// fail, the end of a function returning a value was reached
+    NoReturn,
+    /// fail with the message of a text constant
+    Fail(usize),
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Push(index) => write!(f, "push #{}", index),
+            Instruction::Global(address) => write!(f, "global {}", address),
+            Instruction::Local(offset) => write!(f, "local {}", offset),
+            Instruction::Load => write!(f, "load"),
+            Instruction::Copy(cells) => write!(f, "copy {}", cells),
+            Instruction::Store(cells) => write!(f, "store {}", cells),
+            Instruction::Clear(cells) => write!(f, "clear {}", cells),
+            Instruction::Dup(cells) => write!(f, "dup {}", cells),
+            Instruction::Pop(cells) => write!(f, "pop {}", cells),
+            Instruction::Convert(scalar) => write!(f, "convert {}", scalar),
+            Instruction::Negate => write!(f, "negate"),
+            Instruction::Binary(operator) => write!(f, "binary {}", operator.as_str()),
+            Instruction::PointerAdd(stride) => write!(f, "ptradd {}", stride),
+            Instruction::PointerSubtract(stride) => write!(f, "ptrsub {}", stride),
+            Instruction::PointerDifference(stride) => write!(f, "ptrdiff {}", stride),
+            Instruction::Index(stride) => write!(f, "index {}", stride),
+            Instruction::Bounds(size) => write!(f, "bounds {}", size),
+            Instruction::Offset(offset) => write!(f, "offset {}", offset),
+            Instruction::Jump(target) => write!(f, "jump {}", target),
+            Instruction::JumpIfFalse(target) => write!(f, "jumpfalse {}", target),
+            Instruction::Call(function) => write!(f, "call @{}", function),
+            Instruction::Builtin(builtin) => write!(f, "builtin {}", builtin),
+            Instruction::Printf { format, arguments } => {
+                write!(f, "printf #{} {}", format, arguments)
+            }
+            Instruction::Return => write!(f, "return"),
+            Instruction::NoReturn => write!(f, "noreturn"),
+            Instruction::Fail(message) => write!(f, "fail #{}", message),
+        }
+    }
+}
+
+/// Where a variable is stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Location {
+    /// cell offset from the start of the frame
+    Frame(usize),
+    /// address in the data segment
+    Global(usize),
+}
+
+/// Debugging information about a variable: where it lives and the
+/// instructions during which its name is in scope
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub data_type: Type,
+    pub location: Location,
+    /// first instruction of the scope and the one after its end
+    pub scope: (usize, usize),
+    /// number of blocks around the declaration inside the function body, 0
+    /// for parameters and the locals of the body itself
+    pub depth: usize,
+}
+
+/// A compiled function
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub code: Vec<Instruction>,
+    /// token each instruction was compiled from, the debug line table
+    pub spans: Vec<Span>,
+    /// cells taken by the parameters, the first cells of the frame
+    pub parameters: usize,
+    /// cells taken by the parameters and every local
+    pub frame: usize,
+    /// cells of the result
+    pub returns: usize,
+    pub variables: Vec<Variable>,
+}
+
+/// A compiled program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub constants: Vec<Constant>,
+    /// initial value of each cell of the globals and static locals, before
+    /// their initialisers run; cell 0 is the null pointer and never used
+    pub data: Vec<Constant>,
+    pub globals: Vec<Variable>,
+    /// struct definitions, for showing the values of variables
+    pub structs: Vec<(String, Vec<Member>)>,
+    /// function that runs the initialisers of the globals, then calls main
+    pub entry: usize,
+    pub main: usize,
+}
+
+impl Module {
+    /// Listing of the constant pool and the code of each function, every
+    /// instruction followed by the line and column it was compiled from
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::from("constants:\n");
+        for (i, constant) in self.constants.iter().enumerate() {
+            listing.push_str(&format!("    #{:<4} {}\n", i, constant));
+        }
+        listing.push_str(&format!("data: {} cells\n", self.data.len()));
+        for variable in &self.globals {
+            listing.push_str(&format!(
+                "    {} {} at {}\n",
+                variable.data_type,
+                variable.name,
+                location(variable.location)
+            ));
+        }
+        for (i, function) in self.functions.iter().enumerate() {
+            listing.push_str(&format!(
+                "\n@{} {}: {} parameter cells, {} frame cells, returns {} cells\n",
+                i, function.name, function.parameters, function.frame, function.returns
+            ));
+            for variable in &function.variables {
+                listing.push_str(&format!(
+                    "    {} {} at {}, in scope {}..{}\n",
+                    variable.data_type,
+                    variable.name,
+                    location(variable.location),
+                    variable.scope.0,
+                    variable.scope.1
+                ));
+            }
+            for (ip, (instruction, span)) in function.code.iter().zip(&function.spans).enumerate() {
+                let text = instruction.to_string();
+                let comment = match instruction {
+                    Instruction::Push(index)
+                    | Instruction::Fail(index)
+                    | Instruction::Printf { format: index, .. } => {
+                        format!("  {}", self.constants[*index])
+                    }
+                    Instruction::Call(index) => format!("  {}", self.functions[*index].name),
+                    _ => String::new(),
+                };
+                listing.push_str(&format!(
+                    "{:>6}  {:<20} ; {}:{}{}\n",
+                    ip, text, span.line, span.column, comment
+                ));
+            }
+        }
+        listing
+    }
+}
+
+fn location(location: Location) -> String {
+    match location {
+        Location::Frame(offset) => format!("frame {}", offset),
+        Location::Global(address) => format!("data {}", address),
+    }
+}
@@ -0,0 +1,749 @@
+use crate::ast::*;
+use crate::token::Span;
+
+use std::collections::HashMap;
+
+/// Index of a local variable or parameter in `Cfg::variables`
+pub type VariableId = usize;
+
+/// Index of a basic block in `Cfg::blocks`
+pub type BlockId = usize;
+
+/// Parameter or local variable of a function; locals shadowing each other
+/// are different variables
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalVariable {
+    pub name: String,
+    pub data_type: Type,
+    pub qualifiers: Qualifiers,
+    pub is_parameter: bool,
+    pub span: Span,
+}
+
+/// Straight line statement inside a basic block
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    /// `a = b = value;`
+    Assign {
+        targets: Vec<Expression>,
+        value: Expression,
+        span: Span,
+    },
+    /// declaration of a local variable, with its initializer if it has one
+    Declare {
+        variable: VariableId,
+        initializer: Option<Initializer>,
+        span: Span,
+    },
+    /// `F(x);`, a call whose value is dropped
+    Call { call: Expression, span: Span },
+}
+
+impl Instruction {
+    pub fn span(&self) -> Span {
+        match self {
+            Instruction::Assign { span, .. }
+            | Instruction::Declare { span, .. }
+            | Instruction::Call { span, .. } => *span,
+        }
+    }
+}
+
+/// How control leaves a basic block
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Goto(BlockId),
+    /// `if`, `while` and `for` conditions
+    Branch {
+        condition: Expression,
+        if_true: BlockId,
+        if_false: BlockId,
+    },
+    /// `switch`, going to the block of the first case label equal to the
+    /// value, or to `default` when none is
+    Switch {
+        value: Expression,
+        cases: Vec<(Expression, BlockId)>,
+        default: BlockId,
+    },
+    /// `return`, continuing at the exit block
+    Return(Option<Expression>, Span),
+    /// the exit block, the only one without successors
+    Exit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+/// Control flow graph of one function: basic blocks of straight line
+/// instructions joined by the terminators of `if`, `while`, `for`, `switch`,
+/// `break` and `return`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cfg {
+    pub name: String,
+    pub blocks: Vec<BasicBlock>,
+    pub entry: BlockId,
+    pub exit: BlockId,
+    pub variables: Vec<LocalVariable>,
+    /// local variable each variable expression refers to, by the span of the
+    /// expression; names not found here are globals
+    pub resolutions: HashMap<Span, VariableId>,
+}
+
+impl Cfg {
+    /// Build the control flow graph of a function
+    ///
+    /// # Arguments
+    ///
+    /// * `function` - function definition from the syntax tree
+    ///
+    pub fn build(function: &Function) -> Cfg {
+        let mut builder = Builder {
+            blocks: Vec::new(),
+            current: 0,
+            scopes: vec![HashMap::new()],
+            variables: Vec::new(),
+            resolutions: HashMap::new(),
+            breaks: Vec::new(),
+        };
+        let entry = builder.new_block();
+        let exit = builder.new_block();
+        builder.blocks[exit].1 = Some(Terminator::Exit);
+        builder.current = entry;
+
+        for parameter in &function.declaration.parameters {
+            builder.declare(LocalVariable {
+                name: parameter.name.clone(),
+                data_type: parameter.data_type.clone(),
+                qualifiers: parameter.qualifiers,
+                is_parameter: true,
+                span: parameter.span,
+            });
+        }
+        for statement in &function.body.statements {
+            builder.statement(statement);
+        }
+        builder.terminate(Terminator::Goto(exit));
+
+        let mut cfg = Cfg {
+            name: function.declaration.name.clone(),
+            blocks: builder
+                .blocks
+                .into_iter()
+                .map(|(instructions, terminator)| BasicBlock {
+                    instructions,
+                    terminator: terminator.unwrap(),
+                })
+                .collect(),
+            entry,
+            exit,
+            variables: builder.variables,
+            resolutions: builder.resolutions,
+        };
+        cfg.remove_empty_unreachable();
+        cfg
+    }
+
+    pub fn successors(&self, block: BlockId) -> Vec<BlockId> {
+        match &self.blocks[block].terminator {
+            Terminator::Goto(target) => vec![*target],
+            Terminator::Branch {
+                if_true, if_false, ..
+            } => vec![*if_true, *if_false],
+            Terminator::Switch { cases, default, .. } => cases
+                .iter()
+                .map(|(_, target)| *target)
+                .chain(std::iter::once(*default))
+                .collect(),
+            Terminator::Return(_, _) => vec![self.exit],
+            Terminator::Exit => Vec::new(),
+        }
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for block in 0..self.blocks.len() {
+            for successor in self.successors(block) {
+                predecessors[successor].push(block);
+            }
+        }
+        predecessors
+    }
+
+    /// blocks control can reach from the entry
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut work = vec![self.entry];
+        while let Some(block) = work.pop() {
+            if !reachable[block] {
+                reachable[block] = true;
+                work.extend(self.successors(block));
+            }
+        }
+        reachable
+    }
+
+    /// the local variable a variable expression refers to
+    pub fn resolve(&self, expr: &Expression) -> Option<VariableId> {
+        match &expr.kind {
+            ExpressionKind::Variable(_) => self.resolutions.get(&expr.span).copied(),
+            _ => None,
+        }
+    }
+
+    // the builder opens a block after every return and loop; drop those that
+    // stayed empty and can never run, keeping dead code for the picture
+    fn remove_empty_unreachable(&mut self) {
+        let reachable = self.reachable();
+        let removable: Vec<bool> = (0..self.blocks.len())
+            .map(|b| {
+                !reachable[b]
+                    && b != self.exit
+                    && self.blocks[b].instructions.is_empty()
+                    && matches!(self.blocks[b].terminator, Terminator::Goto(_))
+            })
+            .collect();
+        // edges into a removed block go on to where it jumps
+        let forward = |mut block: BlockId| {
+            while removable[block] {
+                if let Terminator::Goto(target) = self.blocks[block].terminator {
+                    block = target;
+                }
+            }
+            block
+        };
+        let mut new_ids = vec![0; self.blocks.len()];
+        let mut next = 0;
+        for (block, removed) in removable.iter().enumerate() {
+            if !removed {
+                new_ids[block] = next;
+                next += 1;
+            }
+        }
+        let new_ids: Vec<BlockId> = (0..self.blocks.len())
+            .map(|block| new_ids[forward(block)])
+            .collect();
+
+        let blocks = std::mem::take(&mut self.blocks);
+        for (block, removed) in blocks.into_iter().zip(&removable) {
+            if *removed {
+                continue;
+            }
+            let terminator = match block.terminator {
+                Terminator::Goto(target) => Terminator::Goto(new_ids[target]),
+                Terminator::Branch {
+                    condition,
+                    if_true,
+                    if_false,
+                } => Terminator::Branch {
+                    condition,
+                    if_true: new_ids[if_true],
+                    if_false: new_ids[if_false],
+                },
+                Terminator::Switch {
+                    value,
+                    cases,
+                    default,
+                } => Terminator::Switch {
+                    value,
+                    cases: cases
+                        .into_iter()
+                        .map(|(label, target)| (label, new_ids[target]))
+                        .collect(),
+                    default: new_ids[default],
+                },
+                terminator => terminator,
+            };
+            self.blocks.push(BasicBlock {
+                instructions: block.instructions,
+                terminator,
+            });
+        }
+        self.entry = new_ids[self.entry];
+        self.exit = new_ids[self.exit];
+    }
+
+    /// Export the graph to Graphviz DOT, one box per basic block
+    pub fn to_dot(&self) -> String {
+        let mut res = format!("digraph \"{}\" {{\n", escape(&self.name));
+        res.push_str(&self.dot_body(""));
+        res.push_str("}\n");
+        res
+    }
+
+    /// nodes and edges of the graph, node names starting with `prefix` so
+    /// several functions fit in one DOT file
+    fn dot_body(&self, prefix: &str) -> String {
+        let mut res = String::from("    node [shape=box, fontname=\"Courier\"];\n");
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut label = match id {
+                _ if id == self.entry => format!("B{} (entry)\\l", id),
+                _ if id == self.exit => format!("B{} (exit)\\l", id),
+                _ => format!("B{}\\l", id),
+            };
+            for instruction in &block.instructions {
+                label.push_str(&escape(&self.instruction_text(instruction)));
+                label.push_str("\\l");
+            }
+            match &block.terminator {
+                Terminator::Branch { condition, .. } => {
+                    label.push_str(&escape(&format!("if ({})", condition)));
+                    label.push_str("\\l");
+                }
+                Terminator::Switch { value, .. } => {
+                    label.push_str(&escape(&format!("switch ({})", value)));
+                    label.push_str("\\l");
+                }
+                Terminator::Return(value, _) => {
+                    let text = match value {
+                        Some(value) => format!("return {};", value),
+                        None => String::from("return;"),
+                    };
+                    label.push_str(&escape(&text));
+                    label.push_str("\\l");
+                }
+                _ => {}
+            }
+            res.push_str(&format!("    {}B{} [label=\"{}\"];\n", prefix, id, label));
+        }
+        for id in 0..self.blocks.len() {
+            match &self.blocks[id].terminator {
+                Terminator::Branch {
+                    if_true, if_false, ..
+                } => {
+                    res.push_str(&format!(
+                        "    {p}B{} -> {p}B{} [label=\"true\"];\n",
+                        id,
+                        if_true,
+                        p = prefix
+                    ));
+                    res.push_str(&format!(
+                        "    {p}B{} -> {p}B{} [label=\"false\"];\n",
+                        id,
+                        if_false,
+                        p = prefix
+                    ));
+                }
+                Terminator::Switch { cases, default, .. } => {
+                    for (value, target) in cases {
+                        res.push_str(&format!(
+                            "    {p}B{} -> {p}B{} [label=\"{}\"];\n",
+                            id,
+                            target,
+                            escape(&format!("case {}", value)),
+                            p = prefix
+                        ));
+                    }
+                    res.push_str(&format!(
+                        "    {p}B{} -> {p}B{} [label=\"default\"];\n",
+                        id,
+                        default,
+                        p = prefix
+                    ));
+                }
+                _ => {
+                    for successor in self.successors(id) {
+                        res.push_str(&format!(
+                            "    {p}B{} -> {p}B{};\n",
+                            id,
+                            successor,
+                            p = prefix
+                        ));
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// an instruction written back as X source
+    pub fn instruction_text(&self, instruction: &Instruction) -> String {
+        match instruction {
+            Instruction::Assign { targets, value, .. } => {
+                let mut res = String::new();
+                for target in targets {
+                    res.push_str(&format!("{} = ", target));
+                }
+                format!("{}{};", res, value)
+            }
+            Instruction::Declare {
+                variable,
+                initializer,
+                ..
+            } => {
+                let variable = &self.variables[*variable];
+                match initializer {
+                    Some(initializer) => format!(
+                        "{} {} = {};",
+                        variable.data_type, variable.name, initializer
+                    ),
+                    None => format!("{} {};", variable.data_type, variable.name),
+                }
+            }
+            Instruction::Call { call, .. } => format!("{};", call),
+        }
+    }
+}
+
+/// Export the graphs of several functions to one Graphviz DOT file, a cluster per function
+pub fn program_to_dot(cfgs: &[Cfg]) -> String {
+    let mut res = String::from("digraph program {\n");
+    for (i, cfg) in cfgs.iter().enumerate() {
+        res.push_str(&format!("  subgraph cluster_{} {{\n", i));
+        res.push_str(&format!("    label=\"{}\";\n", escape(&cfg.name)));
+        res.push_str(&cfg.dot_body(&format!("F{}_", i)));
+        res.push_str("  }\n");
+    }
+    res.push_str("}\n");
+    res
+}
+
+/// Build the graphs of main and every function defined after it
+pub fn build_program(program: &Program) -> Vec<Cfg> {
+    std::iter::once(&program.main)
+        .chain(&program.functions)
+        .map(Cfg::build)
+        .collect()
+}
+
+// quote the characters with a meaning inside a DOT string
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// blocks under construction keep their terminator open until control leaves them
+struct Builder {
+    blocks: Vec<(Vec<Instruction>, Option<Terminator>)>,
+    current: BlockId,
+    scopes: Vec<HashMap<String, VariableId>>,
+    variables: Vec<LocalVariable>,
+    resolutions: HashMap<Span, VariableId>,
+    /// block after each open loop or switch, where its `break`s go, innermost last
+    breaks: Vec<BlockId>,
+}
+
+impl Builder {
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push((Vec::new(), None));
+        self.blocks.len() - 1
+    }
+
+    // end the current block, unless a return already did
+    fn terminate(&mut self, terminator: Terminator) {
+        let block = &mut self.blocks[self.current];
+        if block.1.is_none() {
+            block.1 = Some(terminator);
+        }
+    }
+
+    fn declare(&mut self, variable: LocalVariable) -> VariableId {
+        let id = self.variables.len();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(variable.name.clone(), id);
+        self.variables.push(variable);
+        id
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Assignment { targets, value } => {
+                for target in targets {
+                    self.resolve(target);
+                }
+                self.resolve(value);
+                self.blocks[self.current].0.push(Instruction::Assign {
+                    targets: targets.clone(),
+                    value: value.clone(),
+                    span: statement.span,
+                });
+            }
+            StatementKind::Declaration(Declaration::Variable(variable)) => {
+                if let Some(initializer) = &variable.initializer {
+                    self.resolve_initializer(initializer);
+                }
+                let id = self.declare(LocalVariable {
+                    name: variable.name.clone(),
+                    data_type: variable.data_type.clone(),
+                    qualifiers: variable.qualifiers,
+                    is_parameter: false,
+                    span: variable.span,
+                });
+                self.blocks[self.current].0.push(Instruction::Declare {
+                    variable: id,
+                    initializer: variable.initializer.clone(),
+                    span: statement.span,
+                });
+            }
+            StatementKind::Call(call) => {
+                self.resolve(call);
+                self.blocks[self.current].0.push(Instruction::Call {
+                    call: call.clone(),
+                    span: statement.span,
+                });
+            }
+            // types and prototypes generate no code
+            StatementKind::Declaration(_) => {}
+            StatementKind::If { condition, body } => {
+                self.resolve(condition);
+                let then = self.new_block();
+                let after = self.new_block();
+                self.terminate(Terminator::Branch {
+                    condition: condition.clone(),
+                    if_true: then,
+                    if_false: after,
+                });
+                self.current = then;
+                self.block(body);
+                self.terminate(Terminator::Goto(after));
+                self.current = after;
+            }
+            StatementKind::While { condition, body } => {
+                self.resolve(condition);
+                let header = self.new_block();
+                self.terminate(Terminator::Goto(header));
+                let loop_body = self.new_block();
+                let after = self.new_block();
+                self.current = header;
+                self.terminate(Terminator::Branch {
+                    condition: condition.clone(),
+                    if_true: loop_body,
+                    if_false: after,
+                });
+                self.current = loop_body;
+                self.breaks.push(after);
+                self.block(body);
+                self.breaks.pop();
+                self.terminate(Terminator::Goto(header));
+                self.current = after;
+            }
+            // init, then the condition in a header block as for `while`; the
+            // step gets a block of its own, which the body runs into
+            StatementKind::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                let header = self.new_block();
+                self.terminate(Terminator::Goto(header));
+                let loop_body = self.new_block();
+                let next = self.new_block();
+                let after = self.new_block();
+                self.current = header;
+                match condition {
+                    Some(condition) => {
+                        self.resolve(condition);
+                        self.terminate(Terminator::Branch {
+                            condition: condition.clone(),
+                            if_true: loop_body,
+                            if_false: after,
+                        });
+                    }
+                    None => self.terminate(Terminator::Goto(loop_body)),
+                }
+                self.current = loop_body;
+                self.breaks.push(after);
+                self.block(body);
+                self.breaks.pop();
+                self.terminate(Terminator::Goto(next));
+                self.current = next;
+                if let Some(step) = step {
+                    self.statement(step);
+                }
+                self.terminate(Terminator::Goto(header));
+                self.current = after;
+            }
+            // a block per labelled position of the body, each running into
+            // the next one; with no default an unmatched value goes past it
+            StatementKind::Switch { value, body, cases } => {
+                self.resolve(value);
+                let mut targets: HashMap<usize, BlockId> = HashMap::new();
+                for case in cases {
+                    targets
+                        .entry(case.position)
+                        .or_insert_with(|| self.new_block());
+                }
+                let after = self.new_block();
+                let default = cases
+                    .iter()
+                    .find(|case| case.label.is_none())
+                    .map_or(after, |case| targets[&case.position]);
+                self.terminate(Terminator::Switch {
+                    value: value.clone(),
+                    cases: cases
+                        .iter()
+                        .filter_map(|case| Some((case.label.clone()?, targets[&case.position])))
+                        .collect(),
+                    default,
+                });
+                self.current = self.new_block();
+                self.scopes.push(HashMap::new());
+                self.breaks.push(after);
+                for position in 0..=body.statements.len() {
+                    if let Some(&target) = targets.get(&position) {
+                        self.terminate(Terminator::Goto(target));
+                        self.current = target;
+                    }
+                    if let Some(statement) = body.statements.get(position) {
+                        self.statement(statement);
+                    }
+                }
+                self.breaks.pop();
+                self.scopes.pop();
+                self.terminate(Terminator::Goto(after));
+                self.current = after;
+            }
+            StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    self.resolve(value);
+                }
+                self.terminate(Terminator::Return(value.clone(), statement.span));
+                // whatever follows is unreachable, but still gets a block
+                self.current = self.new_block();
+            }
+            StatementKind::Break => {
+                let after = *self.breaks.last().unwrap();
+                self.terminate(Terminator::Goto(after));
+                self.current = self.new_block();
+            }
+            StatementKind::Block(block) => self.block(block),
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.scopes.push(HashMap::new());
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve_initializer(&mut self, initializer: &Initializer) {
+        match initializer {
+            Initializer::Expression(expr) => self.resolve(expr),
+            Initializer::List(items, _) => {
+                for item in items {
+                    self.resolve_initializer(item);
+                }
+            }
+        }
+    }
+
+    // record the local every variable inside the expression refers to
+    fn resolve(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Variable(name) => {
+                if let Some(id) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+                    self.resolutions.insert(expr.span, *id);
+                }
+            }
+            ExpressionKind::Index { array, index } => {
+                self.resolve(array);
+                self.resolve(index);
+            }
+            ExpressionKind::Member { base, .. } => self.resolve(base),
+            ExpressionKind::Call { arguments, .. } => {
+                for argument in arguments {
+                    self.resolve(argument);
+                }
+            }
+            ExpressionKind::Unary { operand, .. }
+            | ExpressionKind::Cast { operand, .. }
+            | ExpressionKind::SizeOfExpression(operand) => self.resolve(operand),
+            ExpressionKind::Binary { left, right, .. } => {
+                self.resolve(left);
+                self.resolve(right);
+            }
+            ExpressionKind::IntConstant(_)
+            | ExpressionKind::FloatConstant(_)
+            | ExpressionKind::EnumConstant { .. }
+            | ExpressionKind::StringConstant(_)
+            | ExpressionKind::SizeOfType(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    // graph of the main function of a program
+    fn main_cfg(source: &str) -> Cfg {
+        let program = Parser::from_source(source, "format.csv").parse();
+        Cfg::build(&program.main)
+    }
+
+    #[test]
+    fn while_loop_exports_to_dot() {
+        let cfg = main_cfg(
+            "void main(){
+                int i;
+                i = 0;
+                while (i < 3) { i = i + 1; }
+                return;
+            }",
+        );
+        let expected = "digraph \"main\" {
+    node [shape=box, fontname=\"Courier\"];
+    B0 [label=\"B0 (entry)\\lint i;\\li = 0;\\l\"];
+    B1 [label=\"B1 (exit)\\l\"];
+    B2 [label=\"B2\\lif (i < 3)\\l\"];
+    B3 [label=\"B3\\li = i + 1;\\l\"];
+    B4 [label=\"B4\\lreturn;\\l\"];
+    B0 -> B2;
+    B2 -> B3 [label=\"true\"];
+    B2 -> B4 [label=\"false\"];
+    B3 -> B2;
+    B4 -> B1;
+}
+";
+        assert_eq!(cfg.to_dot(), expected);
+    }
+
+    #[test]
+    fn switch_break_and_dead_code() {
+        let cfg = main_cfg(
+            "void main(){
+                int n;
+                n = read_int();
+                switch (n) {
+                    case 1: { int n; n = 2; } break;
+                    default: print_int(n);
+                }
+                return;
+                n = 3;
+            }",
+        );
+        let switch = cfg
+            .blocks
+            .iter()
+            .position(|block| matches!(block.terminator, Terminator::Switch { .. }))
+            .unwrap();
+        let successors = cfg.successors(switch);
+        assert_eq!(successors.len(), 2);
+
+        // the case breaks out and the default falls off the end, both to the return
+        let reachable = cfg.reachable();
+        assert!(successors.iter().all(|block| reachable[*block]));
+        assert!(reachable[cfg.exit]);
+        // the assignment after the return stays in the graph, never reached
+        let dead: Vec<usize> = (0..cfg.blocks.len())
+            .filter(|block| !reachable[*block])
+            .collect();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(cfg.blocks[dead[0]].instructions.len(), 1);
+
+        // the n inside the case is a variable of its own
+        let names: Vec<&str> = cfg.variables.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["n", "n"]);
+    }
+}
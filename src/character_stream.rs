@@ -12,7 +12,7 @@ impl CharStream {
 			Ok(mut file) => {
 				let mut contents = String::new();
 				file.read_to_string(&mut contents).unwrap();
-				CharStream { contents: contents }
+				CharStream { contents }
 			}
 			Err(_) => {
 				panic!("Error opening file {}", f);
@@ -20,13 +20,9 @@ impl CharStream {
 		}
 	}
 
-	pub fn get_contents(&self) -> &String {
-		&self.contents
-	}
-
-	// Returns true if more characters are available, false otherwise.
-	pub fn more_available(&self) -> bool {
-		!self.contents.is_empty()
+	// A stream over text already in memory, such as a line typed at the REPL.
+	pub fn from_string(contents: String) -> CharStream {
+		CharStream { contents }
 	}
 
 	// Returns the next character without consuming it.
@@ -42,10 +38,7 @@ impl CharStream {
 	pub fn peek_ahead_char(&self, k: i32) -> Option<char> {
 		match k < 0 {
 			true => None,
-			false => {
-				let ch = self.contents.chars().nth(k as usize).unwrap();
-				Some(ch)
-			}
+			false => self.contents.chars().nth(k as usize),
 		}
 	}
 
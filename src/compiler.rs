@@ -0,0 +1,771 @@
+use crate::ast::*;
+use crate::builtins;
+use crate::bytecode::{self, Constant, Instruction, Location, Module, Scalar, Variable};
+use crate::const_eval;
+use crate::layout::Layout;
+use crate::token::Span;
+use crate::value::Number;
+
+use std::collections::HashMap;
+
+/// name of the function that initialises the globals, then calls main
+pub const ENTRY: &str = "<start>";
+
+/// A variable in scope while compiling
+#[derive(Clone, Debug)]
+struct Binding {
+    location: Location,
+    data_type: Type,
+}
+
+/// State of the function being compiled
+#[derive(Default)]
+struct Emitter {
+    code: Vec<Instruction>,
+    spans: Vec<Span>,
+    /// variables of each open block, innermost last, as indices of `variables`
+    scopes: Vec<Vec<usize>>,
+    variables: Vec<Variable>,
+    /// first free cell of the frame; a block gives its cells back at its end
+    top: usize,
+    frame: usize,
+    /// jumps of the `break`s of each open loop, innermost last, patched at
+    /// its end
+    breaks: Vec<Vec<usize>>,
+}
+
+/// Compiles a checked program to bytecode for the stack machine
+///
+/// Variables are laid out as the interpreter lays them out: globals and
+/// static locals in the data segment, locals in the frame of their call,
+/// where the cells of a block are reused by the blocks after it.
+struct Compiler<'a> {
+    program: &'a Program,
+    layout: Layout,
+    functions: HashMap<&'a str, usize>,
+    constants: Vec<Constant>,
+    data: Vec<Constant>,
+    globals: HashMap<String, Binding>,
+    global_variables: Vec<Variable>,
+    /// address of each static local, keyed by its declaration
+    statics: HashMap<Span, usize>,
+    emitter: Emitter,
+}
+
+/// Compile a program that passed every check without errors
+///
+/// # Arguments
+///
+/// * `program` - syntax tree from the parser
+///
+pub fn compile(program: &Program) -> Module {
+    let mut compiler = Compiler {
+        program,
+        layout: Layout::new(program),
+        functions: HashMap::new(),
+        constants: Vec::new(),
+        data: vec![Constant::Null],
+        globals: HashMap::new(),
+        global_variables: Vec::new(),
+        statics: HashMap::new(),
+        emitter: Emitter::default(),
+    };
+    let definitions: Vec<&Function> = std::iter::once(&program.main)
+        .chain(&program.functions)
+        .collect();
+    for (i, function) in definitions.iter().enumerate() {
+        compiler.functions.insert(&function.declaration.name, i);
+    }
+    for declaration in &program.declarations {
+        if let Declaration::Variable(variable) = declaration {
+            compiler.global(variable);
+        }
+    }
+    for function in &definitions {
+        compiler.allocate_statics(&function.body);
+    }
+
+    let mut functions: Vec<bytecode::Function> = definitions
+        .iter()
+        .map(|function| compiler.function(function))
+        .collect();
+    functions.push(compiler.entry());
+    Module {
+        entry: functions.len() - 1,
+        main: 0,
+        functions,
+        constants: compiler.constants,
+        data: compiler.data,
+        globals: compiler.global_variables,
+        structs: compiler.layout.structs(),
+    }
+}
+
+impl<'a> Compiler<'a> {
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.emitter.code.push(instruction);
+        self.emitter.spans.push(span);
+        self.emitter.code.len() - 1
+    }
+
+    /// point a jump emitted before its target was known at the next instruction
+    fn patch(&mut self, jump: usize) {
+        let target = self.emitter.code.len();
+        match &mut self.emitter.code[jump] {
+            Instruction::Jump(to) | Instruction::JumpIfFalse(to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn constant(&mut self, constant: Constant) -> usize {
+        bytecode::intern(&mut self.constants, constant)
+    }
+
+    fn fail(&mut self, message: String, span: Span) {
+        let message = self.constant(Constant::Text(message));
+        self.emit(Instruction::Fail(message), span);
+    }
+
+    /// zero of a scalar type, the initial value of globals
+    fn zero(data_type: &Type) -> Constant {
+        match Number::zero(data_type) {
+            Some(zero) => Constant::Number(zero),
+            None => Constant::Null,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        self.emitter
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .map(|&i| &self.emitter.variables[i])
+            .find(|variable| variable.name == name)
+            .map(|variable| Binding {
+                location: variable.location,
+                data_type: variable.data_type.clone(),
+            })
+            .or_else(|| self.globals.get(name).cloned())
+    }
+
+    fn type_of(&self, expr: &Expression) -> Option<Type> {
+        let variable = |name: &str| self.lookup(name).map(|binding| binding.data_type);
+        self.layout.type_of(expr, &variable)
+    }
+
+    /// bring a variable into scope in the current block
+    fn bind(&mut self, name: &str, location: Location, data_type: Type) {
+        let emitter = &mut self.emitter;
+        let depth = emitter.scopes.len() - 1;
+        emitter.variables.push(Variable {
+            name: String::from(name),
+            data_type,
+            location,
+            scope: (emitter.code.len(), emitter.code.len()),
+            depth,
+        });
+        let index = emitter.variables.len() - 1;
+        emitter.scopes.last_mut().unwrap().push(index);
+    }
+
+    /// cells in the data segment for a global or a static local, zero
+    fn allocate_data(&mut self, data_type: &Type) -> usize {
+        let address = self.data.len();
+        for scalar in self.layout.scalars(data_type) {
+            self.data.push(Compiler::zero(&scalar));
+        }
+        address
+    }
+
+    /// the function that initialises the globals and static locals in the
+    /// order the interpreter does, then calls main
+    fn entry(&mut self) -> bytecode::Function {
+        self.emitter = Emitter::default();
+        self.emitter.scopes.push(Vec::new());
+        let program = self.program;
+        for declaration in &program.declarations {
+            if let Declaration::Variable(VariableDeclaration {
+                name,
+                data_type,
+                initializer: Some(initializer),
+                ..
+            }) = declaration
+            {
+                let location = self.globals[name].location;
+                self.initialize(location, data_type, initializer);
+            }
+        }
+        for function in std::iter::once(&program.main).chain(&program.functions) {
+            self.static_initializers(&function.body);
+        }
+        let span = program.main.declaration.span;
+        self.emit(Instruction::Call(0), span);
+        let returns = self.layout.cells(&program.main.declaration.return_type);
+        self.emit(Instruction::Pop(returns), span);
+        self.emit(Instruction::Return, span);
+        let emitter = std::mem::take(&mut self.emitter);
+        bytecode::Function {
+            name: String::from(ENTRY),
+            code: emitter.code,
+            spans: emitter.spans,
+            parameters: 0,
+            frame: 0,
+            returns: 0,
+            variables: Vec::new(),
+        }
+    }
+
+    fn global(&mut self, variable: &VariableDeclaration) {
+        // an extern declaration and the definition share one variable
+        if !self.globals.contains_key(&variable.name) {
+            let address = self.allocate_data(&variable.data_type);
+            self.globals.insert(
+                variable.name.clone(),
+                Binding {
+                    location: Location::Global(address),
+                    data_type: variable.data_type.clone(),
+                },
+            );
+            self.global_variables.push(Variable {
+                name: variable.name.clone(),
+                data_type: variable.data_type.clone(),
+                location: Location::Global(address),
+                scope: (0, 0),
+                depth: 0,
+            });
+        }
+    }
+
+    /// static locals are created once, before main runs, like globals
+    fn allocate_statics(&mut self, block: &Block) {
+        for statement in &block.statements {
+            match &statement.kind {
+                StatementKind::Declaration(Declaration::Variable(variable))
+                    if variable.qualifiers.storage == StorageClass::Static =>
+                {
+                    let address = self.allocate_data(&variable.data_type);
+                    self.statics.insert(variable.span, address);
+                }
+                StatementKind::Block(body)
+                | StatementKind::If { body, .. }
+                | StatementKind::While { body, .. }
+                | StatementKind::For { body, .. }
+                | StatementKind::Switch { body, .. } => self.allocate_statics(body),
+                _ => {}
+            }
+        }
+    }
+
+    fn static_initializers(&mut self, block: &Block) {
+        for statement in &block.statements {
+            match &statement.kind {
+                StatementKind::Declaration(Declaration::Variable(VariableDeclaration {
+                    qualifiers,
+                    data_type,
+                    initializer: Some(initializer),
+                    span,
+                    ..
+                })) if qualifiers.storage == StorageClass::Static => {
+                    let address = self.statics[span];
+                    self.initialize(Location::Global(address), data_type, initializer);
+                }
+                StatementKind::Block(body)
+                | StatementKind::If { body, .. }
+                | StatementKind::While { body, .. }
+                | StatementKind::For { body, .. }
+                | StatementKind::Switch { body, .. } => self.static_initializers(body),
+                _ => {}
+            }
+        }
+    }
+
+    fn function(&mut self, function: &Function) -> bytecode::Function {
+        self.emitter = Emitter::default();
+        self.emitter.scopes.push(Vec::new());
+        for parameter in &function.declaration.parameters {
+            // `int a[]` receives a pointer to the caller's array
+            let data_type = match &parameter.data_type {
+                Type::Array(element, _) => Type::Pointer(element.clone()),
+                data_type => data_type.clone(),
+            };
+            let offset = self.reserve(&data_type);
+            self.bind(&parameter.name, Location::Frame(offset), data_type);
+        }
+        let parameters = self.emitter.top;
+
+        self.statements(&function.body.statements, &function.declaration);
+        let instruction = match function.declaration.return_type {
+            Type::Void => Instruction::Return,
+            _ => Instruction::NoReturn,
+        };
+        self.emit(instruction, function.body.span);
+        self.close_scope();
+
+        let emitter = std::mem::take(&mut self.emitter);
+        bytecode::Function {
+            name: function.declaration.name.clone(),
+            code: emitter.code,
+            spans: emitter.spans,
+            parameters,
+            frame: emitter.frame,
+            returns: self.layout.cells(&function.declaration.return_type),
+            variables: emitter.variables,
+        }
+    }
+
+    /// cells of the frame for a local
+    fn reserve(&mut self, data_type: &Type) -> usize {
+        let offset = self.emitter.top;
+        self.emitter.top += self.layout.cells(data_type);
+        self.emitter.frame = self.emitter.frame.max(self.emitter.top);
+        offset
+    }
+
+    /// end the scope of the variables of the innermost block
+    fn close_scope(&mut self) {
+        let end = self.emitter.code.len();
+        for i in self.emitter.scopes.pop().unwrap() {
+            self.emitter.variables[i].scope.1 = end;
+        }
+    }
+
+    fn block(&mut self, block: &Block, function: &FunctionDeclaration) {
+        let top = self.emitter.top;
+        self.emitter.scopes.push(Vec::new());
+        self.statements(&block.statements, function);
+        self.close_scope();
+        self.emitter.top = top;
+    }
+
+    fn statements(&mut self, statements: &[Statement], function: &FunctionDeclaration) {
+        for statement in statements {
+            self.statement(statement, function);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, function: &FunctionDeclaration) {
+        match &statement.kind {
+            StatementKind::Assignment { targets, value } => {
+                self.expression(value);
+                // `a = b = v` stores into b first, then stores b's new value into a
+                for (i, target) in targets.iter().rev().enumerate() {
+                    let data_type = self.type_of(target).unwrap_or(Type::Void);
+                    self.convert(&data_type, target.span);
+                    let cells = self.layout.cells(&data_type);
+                    if i + 1 < targets.len() {
+                        self.emit(Instruction::Dup(cells), target.span);
+                    }
+                    self.address(target);
+                    self.emit(Instruction::Store(cells), target.span);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                let start = self.emitter.code.len();
+                self.expression(condition);
+                let exit = self.emit(Instruction::JumpIfFalse(0), condition.span);
+                self.emitter.breaks.push(Vec::new());
+                self.block(body, function);
+                self.emit(Instruction::Jump(start), body.span);
+                self.patch(exit);
+                for jump in self.emitter.breaks.pop().unwrap() {
+                    self.patch(jump);
+                }
+            }
+            StatementKind::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                if let Some(init) = init {
+                    self.statement(init, function);
+                }
+                let start = self.emitter.code.len();
+                let exit = condition.as_ref().map(|condition| {
+                    self.expression(condition);
+                    self.emit(Instruction::JumpIfFalse(0), condition.span)
+                });
+                self.emitter.breaks.push(Vec::new());
+                self.block(body, function);
+                if let Some(step) = step {
+                    self.statement(step, function);
+                }
+                self.emit(Instruction::Jump(start), body.span);
+                if let Some(exit) = exit {
+                    self.patch(exit);
+                }
+                for jump in self.emitter.breaks.pop().unwrap() {
+                    self.patch(jump);
+                }
+            }
+            StatementKind::If { condition, body } => {
+                self.expression(condition);
+                let skip = self.emit(Instruction::JumpIfFalse(0), condition.span);
+                self.block(body, function);
+                self.patch(skip);
+            }
+            // compare the value with each label in turn, then jump into the
+            // body, which runs straight through from one label to the next
+            StatementKind::Switch { value, body, cases } => {
+                self.expression(value);
+                let mut entries = Vec::new();
+                for case in cases {
+                    if let Some(label) = &case.label {
+                        self.emit(Instruction::Dup(1), case.span);
+                        self.expression(label);
+                        self.emit(Instruction::Binary(BinaryOperator::Equal), case.span);
+                        let next = self.emit(Instruction::JumpIfFalse(0), case.span);
+                        self.emit(Instruction::Pop(1), case.span);
+                        entries.push((self.emit(Instruction::Jump(0), case.span), case.position));
+                        self.patch(next);
+                    }
+                }
+                self.emit(Instruction::Pop(1), value.span);
+                let otherwise = self.emit(Instruction::Jump(0), value.span);
+                // without a default an unmatched value leaves like a `break`
+                let mut breaks = Vec::new();
+                match cases.iter().find(|case| case.label.is_none()) {
+                    Some(default) => entries.push((otherwise, default.position)),
+                    None => breaks.push(otherwise),
+                }
+                self.emitter.breaks.push(breaks);
+                let top = self.emitter.top;
+                self.emitter.scopes.push(Vec::new());
+                for position in 0..=body.statements.len() {
+                    for (jump, _) in entries.iter().filter(|(_, p)| *p == position) {
+                        self.patch(*jump);
+                    }
+                    if let Some(statement) = body.statements.get(position) {
+                        self.statement(statement, function);
+                    }
+                }
+                self.close_scope();
+                self.emitter.top = top;
+                for jump in self.emitter.breaks.pop().unwrap() {
+                    self.patch(jump);
+                }
+            }
+            StatementKind::Return(value) => {
+                if let Some(expr) = value {
+                    self.expression(expr);
+                    self.convert(&function.return_type, expr.span);
+                }
+                self.emit(Instruction::Return, statement.span);
+            }
+            StatementKind::Break => {
+                let jump = self.emit(Instruction::Jump(0), statement.span);
+                self.emitter.breaks.last_mut().unwrap().push(jump);
+            }
+            StatementKind::Call(call) => {
+                self.expression(call);
+                let cells = self
+                    .type_of(call)
+                    .map_or(0, |data_type| self.layout.cells(&data_type));
+                if cells > 0 {
+                    self.emit(Instruction::Pop(cells), call.span);
+                }
+            }
+            StatementKind::Block(block) => self.block(block, function),
+            StatementKind::Declaration(Declaration::Variable(variable)) => self.local(variable),
+            StatementKind::Declaration(_) => {}
+        }
+    }
+
+    /// a local comes into scope at its declaration, with its initialiser
+    /// evaluated each time control passes it
+    fn local(&mut self, variable: &VariableDeclaration) {
+        let data_type = variable.data_type.clone();
+        match variable.qualifiers.storage {
+            StorageClass::Static => {
+                let address = self.statics[&variable.span];
+                self.bind(&variable.name, Location::Global(address), data_type);
+            }
+            StorageClass::Extern => match self.globals.get(&variable.name) {
+                Some(binding) => {
+                    let location = binding.location;
+                    self.bind(&variable.name, location, data_type);
+                }
+                None => self.fail(
+                    format!("extern variable `{}` is never defined", variable.name),
+                    variable.span,
+                ),
+            },
+            StorageClass::Auto => {
+                let offset = self.reserve(&data_type);
+                self.bind(&variable.name, Location::Frame(offset), data_type.clone());
+                match &variable.initializer {
+                    Some(initializer) => {
+                        self.initialize(Location::Frame(offset), &data_type, initializer)
+                    }
+                    // a local is unassigned each time control passes its declaration
+                    None => {
+                        self.push_address(Location::Frame(offset), variable.span);
+                        let cells = self.layout.cells(&data_type);
+                        self.emit(Instruction::Clear(cells), variable.span);
+                    }
+                }
+            }
+        }
+    }
+
+    fn push_address(&mut self, location: Location, span: Span) {
+        let instruction = match location {
+            Location::Frame(offset) => Instruction::Local(offset),
+            Location::Global(address) => Instruction::Global(address),
+        };
+        self.emit(instruction, span);
+    }
+
+    /// store an initialiser, elements and members a list leaves out are zero
+    fn initialize(&mut self, location: Location, data_type: &Type, initializer: &Initializer) {
+        let at = |offset: usize| match location {
+            Location::Frame(base) => Location::Frame(base + offset),
+            Location::Global(base) => Location::Global(base + offset),
+        };
+        match (initializer, data_type) {
+            (Initializer::Expression(expr), _) => {
+                self.expression(expr);
+                self.convert(data_type, expr.span);
+                self.push_address(location, expr.span);
+                let cells = self.layout.cells(data_type);
+                self.emit(Instruction::Store(cells), expr.span);
+            }
+            (Initializer::List(items, span), Type::Array(element, _)) => {
+                self.zero_fill(location, data_type, *span);
+                let stride = self.layout.cells(element);
+                for (i, item) in items.iter().enumerate() {
+                    self.initialize(at(i * stride), element, item);
+                }
+            }
+            (Initializer::List(items, span), Type::Struct(name)) => {
+                self.zero_fill(location, data_type, *span);
+                let mut offset = 0;
+                for (member, item) in self
+                    .layout
+                    .members(name)
+                    .unwrap_or_default()
+                    .iter()
+                    .zip(items)
+                {
+                    self.initialize(at(offset), &member.data_type, item);
+                    offset += self.layout.cells(&member.data_type);
+                }
+            }
+            // `int x = {1};` initialises a scalar with its first item
+            (Initializer::List(items, span), _) => match items.first() {
+                Some(item) => self.initialize(location, data_type, item),
+                None => self.zero_fill(location, data_type, *span),
+            },
+        }
+    }
+
+    fn zero_fill(&mut self, location: Location, data_type: &Type, span: Span) {
+        let scalars = self.layout.scalars(data_type);
+        for scalar in &scalars {
+            let zero = self.constant(Compiler::zero(scalar));
+            self.emit(Instruction::Push(zero), span);
+        }
+        self.push_address(location, span);
+        self.emit(Instruction::Store(scalars.len()), span);
+    }
+
+    /// the conversion of an assignment, nothing for structs
+    fn convert(&mut self, data_type: &Type, span: Span) {
+        if let Some(scalar) = Scalar::of(data_type) {
+            self.emit(Instruction::Convert(scalar), span);
+        }
+    }
+
+    /// push the value of an expression; arrays decay to the address of their
+    /// first element and structs take one stack entry per cell
+    fn expression(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::IntConstant(_)
+            | ExpressionKind::FloatConstant(_)
+            | ExpressionKind::EnumConstant { .. }
+            | ExpressionKind::SizeOfType(_)
+            | ExpressionKind::SizeOfExpression(_) => {
+                let variable = |name: &str| self.lookup(name).map(|binding| binding.data_type);
+                let size_of = |expr: &Expression| self.layout.size_of(expr, &variable);
+                match const_eval::evaluate(expr, &size_of) {
+                    Ok(number) => {
+                        let number = self.constant(Constant::Number(number));
+                        self.emit(Instruction::Push(number), expr.span);
+                    }
+                    Err(error) => self.fail(error.message(), error.span),
+                }
+            }
+            ExpressionKind::StringConstant(_) => self.fail(
+                String::from("a string literal can only be the format of `printf`"),
+                expr.span,
+            ),
+            ExpressionKind::Variable(_)
+            | ExpressionKind::Index { .. }
+            | ExpressionKind::Member { .. }
+            | ExpressionKind::Unary {
+                operator: UnaryOperator::Dereference,
+                ..
+            } => {
+                self.address(expr);
+                match self.type_of(expr) {
+                    Some(Type::Array(..)) => {}
+                    Some(data_type @ Type::Struct(_)) => {
+                        let cells = self.layout.cells(&data_type);
+                        self.emit(Instruction::Copy(cells), expr.span);
+                    }
+                    _ => {
+                        self.emit(Instruction::Load, expr.span);
+                    }
+                }
+            }
+            ExpressionKind::Unary {
+                operator: UnaryOperator::AddressOf,
+                operand,
+            } => self.address(operand),
+            ExpressionKind::Unary {
+                operator: UnaryOperator::Negate,
+                operand,
+            } => {
+                self.expression(operand);
+                self.emit(Instruction::Negate, expr.span);
+            }
+            ExpressionKind::Cast { data_type, operand } => {
+                self.expression(operand);
+                self.convert(data_type, expr.span);
+            }
+            ExpressionKind::Call { name, arguments } => self.call(name, arguments, expr.span),
+            ExpressionKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let left_type = self.type_of(left);
+                let right_type = self.type_of(right);
+                let target = |data_type: &Option<Type>| {
+                    data_type
+                        .as_ref()
+                        .and_then(|data_type| data_type.pointee().cloned())
+                };
+                let (left_target, right_target) = (target(&left_type), target(&right_type));
+                self.expression(left);
+                self.expression(right);
+                let stride = |target: &Type| self.layout.cells(target).max(1);
+                let instruction = match (left_target, right_target) {
+                    _ if operator.is_relational() => Instruction::Binary(*operator),
+                    (Some(target), Some(_)) => Instruction::PointerDifference(stride(&target)),
+                    (Some(target), None) if *operator == BinaryOperator::Subtract => {
+                        Instruction::PointerSubtract(stride(&target))
+                    }
+                    (Some(target), None) | (None, Some(target)) => {
+                        Instruction::PointerAdd(stride(&target))
+                    }
+                    (None, None) => Instruction::Binary(*operator),
+                };
+                self.emit(instruction, expr.span);
+            }
+        }
+    }
+
+    /// push the address of the storage an lvalue names
+    fn address(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Variable(name) => match self.lookup(name) {
+                Some(binding) => self.push_address(binding.location, expr.span),
+                None => self.fail(format!("`{}` is not declared", name), expr.span),
+            },
+            ExpressionKind::Index { array, index } => {
+                let array_type = self.type_of(array);
+                self.expression(array);
+                self.expression(index);
+                if let Some(Type::Array(_, Some(size))) = array_type {
+                    self.emit(Instruction::Bounds(size), index.span);
+                }
+                let element = array_type
+                    .as_ref()
+                    .and_then(|data_type| data_type.pointee())
+                    .map_or(0, |element| self.layout.cells(element));
+                self.emit(Instruction::Index(element), expr.span);
+            }
+            ExpressionKind::Member { base, member } => {
+                let found = match self.type_of(base) {
+                    Some(Type::Struct(name)) => self.layout.member(&name, member),
+                    _ => None,
+                };
+                self.address(base);
+                match found {
+                    Some((offset, _)) => {
+                        self.emit(Instruction::Offset(offset), expr.span);
+                    }
+                    None => self.fail(format!("no member `{}`", member), expr.span),
+                }
+            }
+            ExpressionKind::Unary {
+                operator: UnaryOperator::Dereference,
+                operand,
+            } => self.expression(operand),
+            _ => self.fail(
+                String::from("expression does not name a variable"),
+                expr.span,
+            ),
+        }
+    }
+
+    fn call(&mut self, name: &str, arguments: &[Expression], span: Span) {
+        if name == builtins::PRINTF {
+            let format = match arguments.first().map(|argument| &argument.kind) {
+                Some(ExpressionKind::StringConstant(format)) => format.clone(),
+                _ => {
+                    return self.fail(
+                        String::from("the format of `printf` must be a string literal"),
+                        span,
+                    )
+                }
+            };
+            for argument in &arguments[1..] {
+                self.expression(argument);
+            }
+            let format = self.constant(Constant::Text(format));
+            self.emit(
+                Instruction::Printf {
+                    format,
+                    arguments: arguments.len() - 1,
+                },
+                span,
+            );
+            return;
+        }
+
+        let (instruction, parameters) = match self.functions.get(name) {
+            Some(&index) => {
+                let function = match index {
+                    0 => &self.program.main,
+                    _ => &self.program.functions[index - 1],
+                };
+                (
+                    Instruction::Call(index),
+                    function.declaration.parameters.clone(),
+                )
+            }
+            None => match builtins::declarations()
+                .into_iter()
+                .position(|function| function.name == name)
+            {
+                Some(index) => (
+                    Instruction::Builtin(index),
+                    builtins::declarations()[index].parameters.clone(),
+                ),
+                None => return self.fail(format!("`{}` is called but never defined", name), span),
+            },
+        };
+        for (argument, parameter) in arguments.iter().zip(&parameters) {
+            self.expression(argument);
+            let data_type = match &parameter.data_type {
+                Type::Array(element, _) => Type::Pointer(element.clone()),
+                data_type => data_type.clone(),
+            };
+            self.convert(&data_type, argument.span);
+        }
+        self.emit(instruction, span);
+    }
+}
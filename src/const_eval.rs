@@ -0,0 +1,332 @@
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::token::Span;
+use crate::value::{int_literal_type, ArithmeticError, Number};
+
+/// Why an expression has no constant value
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstErrorKind {
+    /// reads a variable, calls a function or takes an address
+    NotConstant,
+    DivisionByZero,
+    /// signed result that does not fit its type
+    Overflow(Type),
+    /// floating value converted to an integer type too narrow for it
+    OutOfRange(Type),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConstError {
+    pub kind: ConstErrorKind,
+    pub span: Span,
+}
+
+impl ConstError {
+    pub fn message(&self) -> String {
+        match &self.kind {
+            ConstErrorKind::NotConstant => String::from("not a constant expression"),
+            ConstErrorKind::DivisionByZero => {
+                String::from("division by zero in constant expression")
+            }
+            ConstErrorKind::Overflow(data_type) => {
+                format!(
+                    "integer overflow in constant expression of type `{}`",
+                    data_type
+                )
+            }
+            ConstErrorKind::OutOfRange(data_type) => {
+                format!("constant value does not fit in `{}`", data_type)
+            }
+        }
+    }
+}
+
+impl From<ArithmeticError> for ConstErrorKind {
+    fn from(error: ArithmeticError) -> ConstErrorKind {
+        match error {
+            ArithmeticError::DivisionByZero => ConstErrorKind::DivisionByZero,
+            ArithmeticError::Overflow(data_type) => ConstErrorKind::Overflow(data_type),
+            ArithmeticError::OutOfRange(data_type) => ConstErrorKind::OutOfRange(data_type),
+        }
+    }
+}
+
+/// Evaluate a constant expression the way a C compiler folds it
+///
+/// # Arguments
+///
+/// * `expr` - the expression to fold
+/// * `size_of` - size in bytes of a `sizeof` expression, None when it is unknown
+///
+pub fn evaluate(
+    expr: &Expression,
+    size_of: &dyn Fn(&Expression) -> Option<usize>,
+) -> Result<Number, ConstError> {
+    let error = |kind: ConstErrorKind| ConstError {
+        kind,
+        span: expr.span,
+    };
+    let arithmetic_error = |kind: ArithmeticError| error(kind.into());
+    match &expr.kind {
+        ExpressionKind::IntConstant(n) => Number::int(*n as i128)
+            .convert(&int_literal_type(*n))
+            .map_err(arithmetic_error),
+        ExpressionKind::FloatConstant(x) => Ok(Number::Double(*x)),
+        ExpressionKind::EnumConstant { value, .. } => Ok(Number::int(*value as i128)),
+        ExpressionKind::SizeOfType(_) | ExpressionKind::SizeOfExpression(_) => {
+            match size_of(expr) {
+                Some(size) => Ok(Number::Integer {
+                    value: size as i128,
+                    kind: IntKind::Long,
+                    unsigned: true,
+                }),
+                None => Err(error(ConstErrorKind::NotConstant)),
+            }
+        }
+        // a cast to a pointer makes an address, which is never constant
+        ExpressionKind::Cast { data_type, operand } if Number::zero(data_type).is_some() => {
+            evaluate(operand, size_of)?
+                .convert(data_type)
+                .map_err(arithmetic_error)
+        }
+        ExpressionKind::Unary {
+            operator: UnaryOperator::Negate,
+            operand,
+        } => evaluate(operand, size_of)?
+            .negate()
+            .map_err(arithmetic_error),
+        ExpressionKind::Binary {
+            operator,
+            left,
+            right,
+        } => {
+            let left = evaluate(left, size_of)?;
+            let right = evaluate(right, size_of)?;
+            left.binary(*operator, right).map_err(arithmetic_error)
+        }
+        _ => Err(error(ConstErrorKind::NotConstant)),
+    }
+}
+
+/// Warn about divisions by a constant zero and constant subexpressions that
+/// overflow, anywhere in the program
+///
+/// # Arguments
+///
+/// * `program` - syntax tree from the parser
+///
+pub fn analyze(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for declaration in &program.declarations {
+        declaration_warnings(declaration, &mut diagnostics);
+    }
+    for function in std::iter::once(&program.main).chain(&program.functions) {
+        block_warnings(&function.body, &mut diagnostics);
+    }
+    diagnostics
+}
+
+fn declaration_warnings(declaration: &Declaration, diagnostics: &mut Vec<Diagnostic>) {
+    if let Declaration::Variable(variable) = declaration {
+        if let Some(initializer) = &variable.initializer {
+            initializer_warnings(initializer, diagnostics);
+        }
+    }
+}
+
+fn initializer_warnings(initializer: &Initializer, diagnostics: &mut Vec<Diagnostic>) {
+    match initializer {
+        Initializer::Expression(expr) => expression_warnings(expr, diagnostics),
+        Initializer::List(items, _) => {
+            for item in items {
+                initializer_warnings(item, diagnostics);
+            }
+        }
+    }
+}
+
+fn block_warnings(block: &Block, diagnostics: &mut Vec<Diagnostic>) {
+    for statement in &block.statements {
+        statement_warnings(statement, diagnostics);
+    }
+}
+
+fn statement_warnings(statement: &Statement, diagnostics: &mut Vec<Diagnostic>) {
+    match &statement.kind {
+        StatementKind::Assignment { targets, value } => {
+            for target in targets {
+                expression_warnings(target, diagnostics);
+            }
+            expression_warnings(value, diagnostics);
+        }
+        StatementKind::While { condition, body } | StatementKind::If { condition, body } => {
+            expression_warnings(condition, diagnostics);
+            block_warnings(body, diagnostics);
+        }
+        StatementKind::For {
+            init,
+            condition,
+            step,
+            body,
+        } => {
+            if let Some(init) = init {
+                statement_warnings(init, diagnostics);
+            }
+            if let Some(condition) = condition {
+                expression_warnings(condition, diagnostics);
+            }
+            if let Some(step) = step {
+                statement_warnings(step, diagnostics);
+            }
+            block_warnings(body, diagnostics);
+        }
+        StatementKind::Switch { value, body, cases } => {
+            expression_warnings(value, diagnostics);
+            for label in cases.iter().filter_map(|case| case.label.as_ref()) {
+                expression_warnings(label, diagnostics);
+            }
+            block_warnings(body, diagnostics);
+        }
+        StatementKind::Return(Some(value)) | StatementKind::Call(value) => {
+            expression_warnings(value, diagnostics)
+        }
+        StatementKind::Return(None) | StatementKind::Break => {}
+        StatementKind::Declaration(declaration) => declaration_warnings(declaration, diagnostics),
+        StatementKind::Block(block) => block_warnings(block, diagnostics),
+    }
+}
+
+fn expression_warnings(expr: &Expression, diagnostics: &mut Vec<Diagnostic>) {
+    let no_sizes = |_: &Expression| None;
+    let operands: Vec<&Expression> = match &expr.kind {
+        ExpressionKind::Index { array, index } => vec![array, index],
+        ExpressionKind::Member { base, .. } => vec![base],
+        ExpressionKind::Call { arguments, .. } => arguments.iter().collect(),
+        ExpressionKind::Unary { operand, .. } | ExpressionKind::Cast { operand, .. } => {
+            vec![operand]
+        }
+        ExpressionKind::Binary { left, right, .. } => vec![left, right],
+        _ => Vec::new(),
+    };
+
+    if let ExpressionKind::Binary {
+        operator: BinaryOperator::Divide,
+        right,
+        ..
+    } = &expr.kind
+    {
+        if matches!(evaluate(right, &no_sizes), Ok(divisor) if divisor.is_zero()) {
+            diagnostics.push(Diagnostic::warning(
+                String::from("division by zero"),
+                expr.span,
+            ));
+        }
+    }
+    // report an overflow where it happens, not again in the expressions around it
+    let operands_constant = operands
+        .iter()
+        .all(|operand| evaluate(operand, &no_sizes).is_ok());
+    if !operands.is_empty() && operands_constant {
+        if let Err(error) = evaluate(expr, &no_sizes) {
+            if matches!(
+                error.kind,
+                ConstErrorKind::Overflow(_) | ConstErrorKind::OutOfRange(_)
+            ) {
+                diagnostics.push(Diagnostic::warning(error.message(), error.span));
+            }
+        }
+    }
+
+    for operand in operands {
+        expression_warnings(operand, diagnostics);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse(expr: &str) -> Expression {
+        let parser = Parser::from_source("void main(){}", "format.csv");
+        let variables = [(
+            String::from("x"),
+            Type::Integer {
+                kind: IntKind::Int,
+                unsigned: false,
+            },
+        )];
+        parser.try_parse_expression(expr, &variables).unwrap()
+    }
+
+    // each diagnostic of the analysis with the line and column it points at
+    fn diagnostics(source: &str) -> Vec<(String, i32, i32)> {
+        let program = Parser::from_source(source, "format.csv").parse();
+        analyze(&program)
+            .into_iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.message,
+                    diagnostic.span.line,
+                    diagnostic.span.column,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn folds_like_a_c_compiler() {
+        let sizes = |_: &Expression| Some(8);
+        let cases = [
+            ("-7 / 2", Number::int(-3)),
+            ("(char) 300 + 1", Number::int(45)),
+            (
+                "sizeof(long) * 2",
+                // sizeof gives an unsigned long
+                Number::Integer {
+                    value: 16,
+                    kind: IntKind::Long,
+                    unsigned: true,
+                },
+            ),
+            ("1 < 2.5", Number::int(1)),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(evaluate(&parse(source), &sizes), Ok(expected), "{}", source);
+        }
+        let error = evaluate(&parse("x + 1"), &sizes).unwrap_err();
+        assert_eq!(error.kind, ConstErrorKind::NotConstant);
+    }
+
+    #[test]
+    fn quiet_on_constants_in_range() {
+        let source = "void main(){
+                int a;
+                a = 2147483646 + 1;
+                a = a / (3 - 2);
+                print_int(a);
+            }";
+        assert_eq!(diagnostics(source), vec![]);
+    }
+
+    #[test]
+    fn reports_division_by_zero_and_overflow() {
+        let source = "void main(){
+                int a;
+                a = 1 / (2 - 2);
+                a = (char) 300.0;
+                a = 2147483647 + 1;
+                print_int(a);
+            }";
+        let expected = vec![
+            (String::from("division by zero"), 3, 23),
+            (String::from("constant value does not fit in `char`"), 4, 21),
+            (
+                String::from("integer overflow in constant expression of type `int`"),
+                5,
+                32,
+            ),
+        ];
+        assert_eq!(diagnostics(source), expected);
+    }
+}
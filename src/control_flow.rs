@@ -0,0 +1,221 @@
+use crate::ast::*;
+use crate::const_eval::evaluate;
+use crate::diagnostic::Diagnostic;
+
+/// Find non-void functions that can end without a return, statements that can
+/// never run and loops whose body never runs
+///
+/// # Arguments
+///
+/// * `program` - syntax tree from the parser
+///
+pub fn analyze(program: &Program) -> Vec<Diagnostic> {
+    let mut analyzer = FlowAnalyzer {
+        diagnostics: Vec::new(),
+        breakable: Vec::new(),
+    };
+    for function in std::iter::once(&program.main).chain(&program.functions) {
+        analyzer.function(function);
+    }
+    analyzer.diagnostics
+}
+
+/// value of a condition known before the program runs
+fn constant_condition(condition: &Expression) -> Option<bool> {
+    evaluate(condition, &|_| None)
+        .ok()
+        .map(|constant| !constant.is_zero())
+}
+
+/// Walks every block in order, tracking whether the end of each statement can
+/// be reached; a loop is left through its condition, a `break` or a `return`,
+/// and every case label of a switch can be reached again
+struct FlowAnalyzer {
+    diagnostics: Vec<Diagnostic>,
+    /// whether a reachable `break` leaves each open loop or switch, innermost last
+    breakable: Vec<bool>,
+}
+
+impl FlowAnalyzer {
+    fn function(&mut self, function: &Function) {
+        let falls_through = self.block(&function.body);
+        let declaration = &function.declaration;
+        if falls_through && declaration.return_type != Type::Void {
+            let message = format!(
+                "control reaches the end of non-void function `{}` without a return",
+                declaration.name
+            );
+            self.diagnostics
+                .push(Diagnostic::error(message, function.body.span));
+        }
+    }
+
+    /// check the statements of a block, true when control can leave it at the end
+    fn block(&mut self, block: &Block) -> bool {
+        let mut reachable = true;
+        for statement in &block.statements {
+            if !reachable {
+                // one report per block, for its first dead statement
+                self.diagnostics.push(Diagnostic::warning(
+                    String::from("unreachable statement"),
+                    statement.span,
+                ));
+                return false;
+            }
+            reachable = self.statement(statement);
+        }
+        reachable
+    }
+
+    /// check a loop, true when control can continue after it; a missing
+    /// condition is always true
+    fn loop_statement(&mut self, condition: Option<&Expression>, body: &Block) -> bool {
+        match condition.map_or(Some(true), constant_condition) {
+            Some(false) => {
+                self.diagnostics.push(Diagnostic::warning(
+                    String::from("loop condition is always false, the body never runs"),
+                    condition.unwrap().span,
+                ));
+                true
+            }
+            // only a break or a return leaves `while (1)`
+            Some(true) => self.loop_body(body),
+            None => {
+                self.loop_body(body);
+                true
+            }
+        }
+    }
+
+    /// check the body of a loop, true when a `break` in it can run
+    fn loop_body(&mut self, body: &Block) -> bool {
+        self.breakable.push(false);
+        self.block(body);
+        self.breakable.pop().unwrap()
+    }
+
+    /// check a switch, true when control can continue after it: no default
+    /// label, a `break` that can run or a body that falls off its end
+    fn switch_statement(&mut self, body: &Block, cases: &[Case]) -> bool {
+        self.breakable.push(false);
+        let mut reachable = true;
+        let mut reported = false;
+        for (position, statement) in body.statements.iter().enumerate() {
+            if cases.iter().any(|case| case.position == position) {
+                reachable = true;
+            } else if !reachable && !reported {
+                // one report per run of dead statements
+                self.diagnostics.push(Diagnostic::warning(
+                    String::from("unreachable statement"),
+                    statement.span,
+                ));
+                reported = true;
+            }
+            if reachable {
+                reported = false;
+                reachable = self.statement(statement);
+            }
+        }
+        let broken = self.breakable.pop().unwrap();
+        let has_default = cases.iter().any(|case| case.label.is_none());
+        !has_default || broken || reachable
+    }
+
+    /// check a statement, true when control can continue after it
+    fn statement(&mut self, statement: &Statement) -> bool {
+        match &statement.kind {
+            StatementKind::Return(_) => false,
+            StatementKind::Break => {
+                if let Some(broken) = self.breakable.last_mut() {
+                    *broken = true;
+                }
+                false
+            }
+            StatementKind::Block(block) => self.block(block),
+            StatementKind::If { condition, body } => {
+                let body_falls_through = self.block(body);
+                // an always true condition makes the if as good as its body
+                constant_condition(condition) != Some(true) || body_falls_through
+            }
+            StatementKind::While { condition, body } => self.loop_statement(Some(condition), body),
+            StatementKind::For {
+                condition, body, ..
+            } => self.loop_statement(condition.as_ref(), body),
+            StatementKind::Switch { body, cases, .. } => self.switch_statement(body, cases),
+            StatementKind::Assignment { .. }
+            | StatementKind::Call(_)
+            | StatementKind::Declaration(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    // each diagnostic of the analysis with the line and column it points at
+    fn diagnostics(source: &str) -> Vec<(String, i32, i32)> {
+        let program = Parser::from_source(source, "format.csv").parse();
+        analyze(&program)
+            .into_iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.message,
+                    diagnostic.span.line,
+                    diagnostic.span.column,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_path_returns() {
+        let source = "int Sign(int n);
+            int Forever(int n);
+            int Pick(int n);
+            void main(){ print_int(Sign(1) + Forever(2) + Pick(3)); }
+            int Sign(int n){
+                if (n < 0) { return -1; }
+                return 1;
+            }
+            int Forever(int n){
+                while (1) { if (n > 0) { return n; } n = n + 1; }
+            }
+            int Pick(int n){
+                switch (n) { case 1: return 10; default: return 0; }
+            }";
+        assert_eq!(diagnostics(source), vec![]);
+    }
+
+    #[test]
+    fn reports_missing_returns_and_dead_code() {
+        let source = "int F(int n);
+            void main(){
+                int x;
+                x = F(1);
+                while (1 == 0) { x = 3; }
+                for (;;) { break; x = 4; }
+                return;
+                x = 2;
+            }
+            int F(int n){
+                if (n == 0) { return 1; }
+            }";
+        let expected = vec![
+            (
+                String::from("loop condition is always false, the body never runs"),
+                5,
+                26,
+            ),
+            (String::from("unreachable statement"), 6, 35),
+            (String::from("unreachable statement"), 8, 17),
+            (
+                String::from("control reaches the end of non-void function `F` without a return"),
+                10,
+                25,
+            ),
+        ];
+        assert_eq!(diagnostics(source), expected);
+    }
+}
@@ -0,0 +1,683 @@
+use crate::ast::*;
+use crate::cfg::{BlockId, Cfg, Instruction, Terminator, VariableId};
+use crate::diagnostic::Diagnostic;
+use crate::token::Span;
+
+use std::collections::{BTreeSet, HashSet};
+
+/// Which way facts flow through the control flow graph
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A dataflow problem over a control flow graph: a lattice of facts, how
+/// facts meet where paths join and how each statement changes them
+pub trait Analysis {
+    type Fact: Clone + PartialEq;
+
+    fn direction(&self) -> Direction;
+
+    /// fact at the entry of a forward analysis, or the exit of a backward one
+    fn boundary(&self, cfg: &Cfg) -> Self::Fact;
+
+    /// starting fact of every other block, the identity of `join`
+    fn initial(&self, cfg: &Cfg) -> Self::Fact;
+
+    /// merge the fact of another path into `fact`
+    fn join(&self, fact: &mut Self::Fact, other: &Self::Fact);
+
+    /// update the fact across the instruction `index` of `block`
+    fn transfer_instruction(&self, cfg: &Cfg, block: BlockId, index: usize, fact: &mut Self::Fact);
+
+    /// update the fact across the terminator of `block`
+    fn transfer_terminator(&self, _cfg: &Cfg, _block: BlockId, _fact: &mut Self::Fact) {}
+
+    /// update the fact across a whole block, in the direction of the analysis
+    fn transfer_block(&self, cfg: &Cfg, block: BlockId, fact: &mut Self::Fact) {
+        let length = cfg.blocks[block].instructions.len();
+        match self.direction() {
+            Direction::Forward => {
+                for index in 0..length {
+                    self.transfer_instruction(cfg, block, index, fact);
+                }
+                self.transfer_terminator(cfg, block, fact);
+            }
+            Direction::Backward => {
+                self.transfer_terminator(cfg, block, fact);
+                for index in (0..length).rev() {
+                    self.transfer_instruction(cfg, block, index, fact);
+                }
+            }
+        }
+    }
+}
+
+/// Facts at the start and the end of every block, in program order
+/// whatever the direction of the analysis
+#[derive(Clone, Debug)]
+pub struct Results<F> {
+    pub before: Vec<F>,
+    pub after: Vec<F>,
+}
+
+/// Solve a dataflow problem with a worklist, iterating until no fact changes
+///
+/// # Arguments
+///
+/// * `cfg` - control flow graph of a function
+/// * `analysis` - the problem to solve
+///
+pub fn solve<A: Analysis>(cfg: &Cfg, analysis: &A) -> Results<A::Fact> {
+    let count = cfg.blocks.len();
+    let mut before = vec![analysis.initial(cfg); count];
+    let mut after = vec![analysis.initial(cfg); count];
+    let predecessors = cfg.predecessors();
+    let successors: Vec<Vec<BlockId>> = (0..count).map(|b| cfg.successors(b)).collect();
+
+    // for a backward analysis the graph is walked with its edges reversed
+    let (start, inputs, outputs) = match analysis.direction() {
+        Direction::Forward => (cfg.entry, &predecessors, &successors),
+        Direction::Backward => (cfg.exit, &successors, &predecessors),
+    };
+
+    let mut work: Vec<BlockId> = (0..count).rev().collect();
+    let mut queued = vec![true; count];
+    while let Some(block) = work.pop() {
+        queued[block] = false;
+        let mut fact = match block == start {
+            true => analysis.boundary(cfg),
+            false => analysis.initial(cfg),
+        };
+        for input in &inputs[block] {
+            let input_fact = match analysis.direction() {
+                Direction::Forward => &after[*input],
+                Direction::Backward => &before[*input],
+            };
+            analysis.join(&mut fact, input_fact);
+        }
+
+        let mut output = fact.clone();
+        analysis.transfer_block(cfg, block, &mut output);
+        let (input_slot, output_slot) = match analysis.direction() {
+            Direction::Forward => (&mut before[block], &mut after[block]),
+            Direction::Backward => (&mut after[block], &mut before[block]),
+        };
+        *input_slot = fact;
+        if *output_slot != output {
+            *output_slot = output;
+            for next in &outputs[block] {
+                if !queued[*next] {
+                    queued[*next] = true;
+                    work.push(*next);
+                }
+            }
+        }
+    }
+    Results { before, after }
+}
+
+/// Locals the analyses follow: scalars that are not static and whose address
+/// is never taken, so that every change to them is an assignment by name
+pub fn tracked_variables(cfg: &Cfg) -> Vec<bool> {
+    let mut tracked: Vec<bool> = cfg
+        .variables
+        .iter()
+        .map(|v| v.data_type.is_scalar() && v.qualifiers.storage == StorageClass::Auto)
+        .collect();
+    let mut address_taken = Vec::new();
+    for block in &cfg.blocks {
+        for instruction in &block.instructions {
+            match instruction {
+                Instruction::Assign { targets, value, .. } => {
+                    for target in targets {
+                        find_address_taken(cfg, target, &mut address_taken);
+                    }
+                    find_address_taken(cfg, value, &mut address_taken);
+                }
+                Instruction::Call { call, .. } => find_address_taken(cfg, call, &mut address_taken),
+                Instruction::Declare { .. } => {}
+            }
+        }
+        match &block.terminator {
+            Terminator::Branch {
+                condition: value, ..
+            }
+            | Terminator::Switch { value, .. } => {
+                find_address_taken(cfg, value, &mut address_taken)
+            }
+            Terminator::Return(Some(value), _) => {
+                find_address_taken(cfg, value, &mut address_taken)
+            }
+            _ => {}
+        }
+    }
+    for variable in address_taken {
+        tracked[variable] = false;
+    }
+    tracked
+}
+
+fn find_address_taken(cfg: &Cfg, expr: &Expression, found: &mut Vec<VariableId>) {
+    expr.visit(&mut |e| {
+        if let ExpressionKind::Unary {
+            operator: UnaryOperator::AddressOf,
+            operand,
+        } = &e.kind
+        {
+            if let Some(variable) = root_variable(cfg, operand) {
+                found.push(variable);
+            }
+        }
+    });
+}
+
+// the local whose storage an lvalue is part of: `a` for `a`, `a[i]` and `a.m`,
+// but not `p` for `p[i]`, which is stored where p points
+fn root_variable(cfg: &Cfg, expr: &Expression) -> Option<VariableId> {
+    match &expr.kind {
+        ExpressionKind::Variable(_) => cfg.resolve(expr),
+        ExpressionKind::Index { array, .. } => match &array.kind {
+            ExpressionKind::Variable(_) => cfg
+                .resolve(array)
+                .filter(|v| cfg.variables[*v].data_type.is_array()),
+            _ => root_variable(cfg, array),
+        },
+        ExpressionKind::Member { base, .. } => root_variable(cfg, base),
+        _ => None,
+    }
+}
+
+/// Reads of locals while evaluating an expression as a value
+pub fn expression_reads(cfg: &Cfg, expr: &Expression, reads: &mut Vec<(VariableId, Span)>) {
+    match &expr.kind {
+        ExpressionKind::Variable(_) => {
+            if let Some(variable) = cfg.resolve(expr) {
+                reads.push((variable, expr.span));
+            }
+        }
+        // taking the address reads nothing, the operand is only located
+        ExpressionKind::Unary {
+            operator: UnaryOperator::AddressOf,
+            operand,
+        } => target_reads(cfg, operand, reads),
+        // sizeof never evaluates its operand
+        ExpressionKind::SizeOfExpression(_) => {}
+        ExpressionKind::Index { array, index } => {
+            expression_reads(cfg, array, reads);
+            expression_reads(cfg, index, reads);
+        }
+        ExpressionKind::Member { base, .. } => expression_reads(cfg, base, reads),
+        ExpressionKind::Call { arguments, .. } => {
+            for argument in arguments {
+                expression_reads(cfg, argument, reads);
+            }
+        }
+        ExpressionKind::Unary { operand, .. } | ExpressionKind::Cast { operand, .. } => {
+            expression_reads(cfg, operand, reads)
+        }
+        ExpressionKind::Binary { left, right, .. } => {
+            expression_reads(cfg, left, reads);
+            expression_reads(cfg, right, reads);
+        }
+        ExpressionKind::IntConstant(_)
+        | ExpressionKind::FloatConstant(_)
+        | ExpressionKind::EnumConstant { .. }
+        | ExpressionKind::StringConstant(_)
+        | ExpressionKind::SizeOfType(_) => {}
+    }
+}
+
+/// Reads of locals needed to find where an assignment target is stored:
+/// the index of `a[i]` and the pointer of `*p`, but not a variable assigned by name
+pub fn target_reads(cfg: &Cfg, target: &Expression, reads: &mut Vec<(VariableId, Span)>) {
+    match &target.kind {
+        ExpressionKind::Variable(_) => {}
+        ExpressionKind::Index { array, index } => {
+            match &array.kind {
+                // an array variable is located, a pointer variable is read
+                ExpressionKind::Variable(_) if root_variable(cfg, target).is_some() => {}
+                ExpressionKind::Index { .. } | ExpressionKind::Member { .. } => {
+                    target_reads(cfg, array, reads)
+                }
+                _ => expression_reads(cfg, array, reads),
+            }
+            expression_reads(cfg, index, reads);
+        }
+        ExpressionKind::Member { base, .. } => target_reads(cfg, base, reads),
+        _ => expression_reads(cfg, target, reads),
+    }
+}
+
+/// The local an assignment target replaces as a whole, if any
+pub fn target_definition(cfg: &Cfg, target: &Expression) -> Option<VariableId> {
+    match &target.kind {
+        ExpressionKind::Variable(_) => cfg.resolve(target),
+        _ => None,
+    }
+}
+
+/// Reads of locals by an instruction, in evaluation order
+pub fn instruction_reads(cfg: &Cfg, instruction: &Instruction) -> Vec<(VariableId, Span)> {
+    let mut reads = Vec::new();
+    match instruction {
+        Instruction::Assign { targets, value, .. } => {
+            expression_reads(cfg, value, &mut reads);
+            for target in targets {
+                target_reads(cfg, target, &mut reads);
+            }
+        }
+        Instruction::Declare {
+            initializer: Some(initializer),
+            ..
+        } => initializer_reads(cfg, initializer, &mut reads),
+        Instruction::Declare { .. } => {}
+        Instruction::Call { call, .. } => expression_reads(cfg, call, &mut reads),
+    }
+    reads
+}
+
+fn initializer_reads(cfg: &Cfg, initializer: &Initializer, reads: &mut Vec<(VariableId, Span)>) {
+    match initializer {
+        Initializer::Expression(expr) => expression_reads(cfg, expr, reads),
+        Initializer::List(items, _) => {
+            for item in items {
+                initializer_reads(cfg, item, reads);
+            }
+        }
+    }
+}
+
+/// Locals an instruction gives a value to; a declaration without initializer
+/// gives none, unless the variable is static and so starts at zero
+pub fn instruction_definitions(cfg: &Cfg, instruction: &Instruction) -> Vec<VariableId> {
+    match instruction {
+        Instruction::Assign { targets, .. } => targets
+            .iter()
+            .filter_map(|target| target_definition(cfg, target))
+            .collect(),
+        Instruction::Declare {
+            variable,
+            initializer,
+            ..
+        } => {
+            let is_static = cfg.variables[*variable].qualifiers.storage != StorageClass::Auto;
+            match initializer.is_some() || is_static {
+                true => vec![*variable],
+                false => Vec::new(),
+            }
+        }
+        Instruction::Call { .. } => Vec::new(),
+    }
+}
+
+/// Reads of locals by the terminator of a block
+pub fn terminator_reads(cfg: &Cfg, terminator: &Terminator) -> Vec<(VariableId, Span)> {
+    let mut reads = Vec::new();
+    match terminator {
+        Terminator::Branch { condition, .. } => expression_reads(cfg, condition, &mut reads),
+        Terminator::Switch { value, .. } => expression_reads(cfg, value, &mut reads),
+        Terminator::Return(Some(value), _) => expression_reads(cfg, value, &mut reads),
+        _ => {}
+    }
+    reads
+}
+
+/// A place where a local may get its value: a parameter, a declaration or
+/// an assignment; declarations without a value are definitions too, of an
+/// unknown value
+#[derive(Clone, Debug, PartialEq)]
+pub struct Definition {
+    pub variable: VariableId,
+    pub span: Span,
+    pub initialized: bool,
+}
+
+/// Reaching definitions: the definitions that may have given each local its
+/// current value, a forward analysis joining by union
+pub struct ReachingDefinitions {
+    pub definitions: Vec<Definition>,
+    // definitions made by each instruction, indexed by block then instruction
+    made: Vec<Vec<Vec<usize>>>,
+    parameters: BTreeSet<usize>,
+}
+
+impl ReachingDefinitions {
+    pub fn new(cfg: &Cfg) -> ReachingDefinitions {
+        let mut definitions = Vec::new();
+        let mut parameters = BTreeSet::new();
+        for (variable, local) in cfg.variables.iter().enumerate() {
+            if local.is_parameter {
+                parameters.insert(definitions.len());
+                definitions.push(Definition {
+                    variable,
+                    span: local.span,
+                    initialized: true,
+                });
+            }
+        }
+        let mut made = Vec::new();
+        for block in &cfg.blocks {
+            let mut block_made = Vec::new();
+            for instruction in &block.instructions {
+                let mut instruction_made = Vec::new();
+                let defined = instruction_definitions(cfg, instruction);
+                let declared = match instruction {
+                    Instruction::Declare { variable, .. } if defined.is_empty() => Some(*variable),
+                    _ => None,
+                };
+                for variable in defined.iter().copied().chain(declared) {
+                    instruction_made.push(definitions.len());
+                    definitions.push(Definition {
+                        variable,
+                        span: instruction.span(),
+                        initialized: declared.is_none(),
+                    });
+                }
+                block_made.push(instruction_made);
+            }
+            made.push(block_made);
+        }
+        ReachingDefinitions {
+            definitions,
+            made,
+            parameters,
+        }
+    }
+}
+
+impl Analysis for ReachingDefinitions {
+    type Fact = BTreeSet<usize>;
+
+    fn direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    fn boundary(&self, _cfg: &Cfg) -> Self::Fact {
+        self.parameters.clone()
+    }
+
+    fn initial(&self, _cfg: &Cfg) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn join(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        fact.extend(other.iter().copied());
+    }
+
+    fn transfer_instruction(
+        &self,
+        _cfg: &Cfg,
+        block: BlockId,
+        index: usize,
+        fact: &mut Self::Fact,
+    ) {
+        for made in &self.made[block][index] {
+            let variable = self.definitions[*made].variable;
+            fact.retain(|d| self.definitions[*d].variable != variable);
+        }
+        fact.extend(self.made[block][index].iter().copied());
+    }
+}
+
+/// Live variables: the locals whose current value may still be read, a
+/// backward analysis joining by union
+pub struct LiveVariables {
+    tracked: Vec<bool>,
+}
+
+impl LiveVariables {
+    pub fn new(cfg: &Cfg) -> LiveVariables {
+        LiveVariables {
+            tracked: tracked_variables(cfg),
+        }
+    }
+
+    fn gen(&self, reads: Vec<(VariableId, Span)>, fact: &mut BTreeSet<VariableId>) {
+        fact.extend(
+            reads
+                .into_iter()
+                .map(|(v, _)| v)
+                .filter(|v| self.tracked[*v]),
+        );
+    }
+}
+
+impl Analysis for LiveVariables {
+    type Fact = BTreeSet<VariableId>;
+
+    fn direction(&self) -> Direction {
+        Direction::Backward
+    }
+
+    fn boundary(&self, _cfg: &Cfg) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn initial(&self, _cfg: &Cfg) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn join(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        fact.extend(other.iter().copied());
+    }
+
+    fn transfer_instruction(&self, cfg: &Cfg, block: BlockId, index: usize, fact: &mut Self::Fact) {
+        let instruction = &cfg.blocks[block].instructions[index];
+        for variable in instruction_definitions(cfg, instruction) {
+            fact.remove(&variable);
+        }
+        // no value survives a declaration, it starts a new variable
+        if let Instruction::Declare { variable, .. } = instruction {
+            fact.remove(variable);
+        }
+        self.gen(instruction_reads(cfg, instruction), fact);
+    }
+
+    fn transfer_terminator(&self, cfg: &Cfg, block: BlockId, fact: &mut Self::Fact) {
+        self.gen(terminator_reads(cfg, &cfg.blocks[block].terminator), fact);
+    }
+}
+
+/// Definite assignment: the locals given a value on every path, a forward
+/// analysis joining by intersection
+pub struct DefiniteAssignment;
+
+impl Analysis for DefiniteAssignment {
+    type Fact = BTreeSet<VariableId>;
+
+    fn direction(&self) -> Direction {
+        Direction::Forward
+    }
+
+    fn boundary(&self, cfg: &Cfg) -> Self::Fact {
+        (0..cfg.variables.len())
+            .filter(|v| cfg.variables[*v].is_parameter)
+            .collect()
+    }
+
+    // every variable, so that a path not yet seen takes nothing away
+    fn initial(&self, cfg: &Cfg) -> Self::Fact {
+        (0..cfg.variables.len()).collect()
+    }
+
+    fn join(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        fact.retain(|v| other.contains(v));
+    }
+
+    fn transfer_instruction(&self, cfg: &Cfg, block: BlockId, index: usize, fact: &mut Self::Fact) {
+        let instruction = &cfg.blocks[block].instructions[index];
+        if let Instruction::Declare { variable, .. } = instruction {
+            // a declaration in a loop starts the variable over on every pass
+            fact.remove(variable);
+        }
+        fact.extend(instruction_definitions(cfg, instruction));
+    }
+}
+
+/// Report reads of locals that may not have a value yet and assignments
+/// whose value is never read
+///
+/// # Arguments
+///
+/// * `program` - syntax tree from the parser
+///
+pub fn analyze(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for function in std::iter::once(&program.main).chain(&program.functions) {
+        let cfg = Cfg::build(function);
+        uninitialized_reads(&cfg, &mut diagnostics);
+        dead_stores(&cfg, &mut diagnostics);
+    }
+    diagnostics
+}
+
+fn uninitialized_reads(cfg: &Cfg, diagnostics: &mut Vec<Diagnostic>) {
+    let tracked = tracked_variables(cfg);
+    let reaching = ReachingDefinitions::new(cfg);
+    let reaching_results = solve(cfg, &reaching);
+    let assigned_results = solve(cfg, &DefiniteAssignment);
+    let reachable = cfg.reachable();
+    let mut reported = HashSet::new();
+
+    let mut check = |reads: Vec<(VariableId, Span)>,
+                     assigned: &BTreeSet<VariableId>,
+                     reaching_now: &BTreeSet<usize>| {
+        for (variable, span) in reads {
+            if !tracked[variable] || assigned.contains(&variable) || !reported.insert(variable) {
+                continue;
+            }
+            // with no definition that gives a value, no path assigns it
+            let name = &cfg.variables[variable].name;
+            let assigned_somewhere = reaching_now.iter().any(|d| {
+                let definition = &reaching.definitions[*d];
+                definition.variable == variable && definition.initialized
+            });
+            let message = match assigned_somewhere {
+                true => format!("`{}` may be used before it is assigned", name),
+                false => format!("`{}` is used before it is assigned", name),
+            };
+            diagnostics.push(Diagnostic::warning(message, span));
+        }
+    };
+
+    for block in (0..cfg.blocks.len()).filter(|b| reachable[*b]) {
+        let mut assigned = assigned_results.before[block].clone();
+        let mut reaching_now = reaching_results.before[block].clone();
+        for (index, instruction) in cfg.blocks[block].instructions.iter().enumerate() {
+            check(
+                instruction_reads(cfg, instruction),
+                &assigned,
+                &reaching_now,
+            );
+            DefiniteAssignment.transfer_instruction(cfg, block, index, &mut assigned);
+            reaching.transfer_instruction(cfg, block, index, &mut reaching_now);
+        }
+        check(
+            terminator_reads(cfg, &cfg.blocks[block].terminator),
+            &assigned,
+            &reaching_now,
+        );
+    }
+}
+
+fn dead_stores(cfg: &Cfg, diagnostics: &mut Vec<Diagnostic>) {
+    let liveness = LiveVariables::new(cfg);
+    let results = solve(cfg, &liveness);
+    let reachable = cfg.reachable();
+
+    for block in (0..cfg.blocks.len()).filter(|b| reachable[*b]) {
+        // walk backward from the end of the block, live holding what is read later
+        let mut live = results.after[block].clone();
+        liveness.transfer_terminator(cfg, block, &mut live);
+        let instructions = &cfg.blocks[block].instructions;
+        let mut found = Vec::new();
+        for index in (0..instructions.len()).rev() {
+            let instruction = &instructions[index];
+            let stored = match instruction {
+                Instruction::Assign { targets, .. } => targets
+                    .iter()
+                    .filter_map(|target| target_definition(cfg, target).map(|v| (v, target.span)))
+                    .collect(),
+                Instruction::Declare {
+                    variable,
+                    initializer: Some(_),
+                    span,
+                } => vec![(*variable, *span)],
+                Instruction::Declare { .. } | Instruction::Call { .. } => Vec::new(),
+            };
+            for (variable, span) in stored {
+                if liveness.tracked[variable] && !live.contains(&variable) {
+                    let message = format!(
+                        "value assigned to `{}` is never read",
+                        cfg.variables[variable].name
+                    );
+                    found.push(Diagnostic::warning(message, span));
+                }
+            }
+            liveness.transfer_instruction(cfg, block, index, &mut live);
+        }
+        diagnostics.extend(found.into_iter().rev());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    // each diagnostic of the analyses with the line and column it points at
+    fn diagnostics(source: &str) -> Vec<(String, i32, i32)> {
+        let program = Parser::from_source(source, "format.csv").parse();
+        analyze(&program)
+            .into_iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.message,
+                    diagnostic.span.line,
+                    diagnostic.span.column,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn assigned_before_read_on_every_path() {
+        let source = "int g;
+            void main(){
+                int a;
+                int i;
+                a = 2;
+                if (g > 0) { a = 1; }
+                for (i = 0; i < a; i = i + 1) { g = g + i; }
+                print_int(g);
+            }";
+        assert_eq!(diagnostics(source), vec![]);
+    }
+
+    #[test]
+    fn reports_unassigned_reads_and_dead_stores() {
+        let source = "void main(){
+                int a;
+                int b;
+                int c;
+                int d;
+                b = a;
+                c = 1;
+                c = 2;
+                if (b > 0) { d = 1; }
+                print_int(c + d);
+            }";
+        let expected = vec![
+            (String::from("`a` is used before it is assigned"), 6, 21),
+            (
+                String::from("`d` may be used before it is assigned"),
+                10,
+                31,
+            ),
+            (String::from("value assigned to `c` is never read"), 7, 17),
+        ];
+        assert_eq!(diagnostics(source), expected);
+    }
+}
@@ -0,0 +1,557 @@
+use crate::ast::*;
+use crate::diagnostic;
+use crate::interpreter::{Debugger, Interpreter, Limits, RuntimeError};
+use crate::parser::Parser;
+use crate::token::Span;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::process;
+
+const PROMPT: &str = "(xdb) ";
+/// file name positions in expressions typed at the prompt are given with
+const EXPRESSION: &str = "<expression>";
+/// lines shown by `list` on each side of the current one
+const CONTEXT: i32 = 5;
+
+const HELP: &str = "\
+The program stops before its first statement, at every breakpoint and after
+each step. An empty line repeats the last step, next, finish or continue.
+
+break LINE | FUNCTION   stop at a line, or when a function is called (b)
+break                   list the breakpoints
+delete [N]              remove breakpoint N, or all of them (d)
+step                    run to the next line, into calls (s)
+next                    run to the next line, over calls (n)
+finish                  run until the current function returns (f)
+continue                run to the next breakpoint (c)
+print EXPR              value and type of an expression (p)
+locals                  variables in scope in the current function
+globals                 global variables
+watch EXPR              show an expression at every stop (w)
+unwatch [N]             remove watch N, or all of them
+backtrace               the calls that led here (bt)
+list                    the source around the current line (l)
+help                    this help (h)
+quit                    stop the program and leave (q)";
+
+/// Run main under the control of commands read from the keyboard, then
+/// return the variables it left behind as `Interpreter::main_variables` does
+///
+/// The program reads its input from the same keyboard, one line at a time, so
+/// that the commands and the lines for read_int can follow each other.
+///
+/// # Arguments
+///
+/// * `program` - syntax tree from the parser
+/// * `parser` - the parser of the program, for the expressions typed at the prompt
+/// * `file` - path of the source, for the positions shown
+/// * `source` - content of the file
+/// * `limits` - bounds of the run
+///
+pub fn debug(
+    program: &Program,
+    parser: &Parser,
+    file: &str,
+    source: &str,
+    limits: Limits,
+) -> Result<Vec<String>, RuntimeError> {
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        println!("X debugger, help lists the commands");
+    }
+    let session = Session::new(program, parser, file, source, interactive);
+    let mut interpreter = Interpreter::with_io(
+        program,
+        Box::new(Keyboard {
+            line: Vec::new(),
+            offset: 0,
+        }),
+        Box::new(io::stdout()),
+    );
+    interpreter.set_limits(limits);
+    interpreter.set_debugger(Box::new(session));
+    interpreter.run().map(|()| interpreter.main_variables())
+}
+
+/// The input of the program: the keyboard, locked only while a line is read
+/// so that the prompt can read from it between two reads of the program
+struct Keyboard {
+    line: Vec<u8>,
+    offset: usize,
+}
+
+impl Read for Keyboard {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let length = available.len().min(buffer.len());
+        buffer[..length].copy_from_slice(&available[..length]);
+        self.consume(length);
+        Ok(length)
+    }
+}
+
+impl BufRead for Keyboard {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.offset == self.line.len() {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            self.line = line.into_bytes();
+            self.offset = 0;
+        }
+        Ok(&self.line[self.offset..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.offset += amount;
+    }
+}
+
+/// How far the program runs before it stops again
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// to the next line, in whatever function it is
+    Step,
+    /// to the next line in a call at most this deep
+    Next(usize),
+    /// until the call this deep returns
+    Finish(usize),
+    /// to the next breakpoint
+    Continue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Breakpoint {
+    Line(i32),
+    /// the first statement of each call of the function
+    Function(String),
+}
+
+struct Session<'a> {
+    parser: &'a Parser,
+    file: &'a str,
+    source: &'a str,
+    interactive: bool,
+    mode: Mode,
+    /// numbered from 1 in the order they were set, numbers are not reused
+    breakpoints: Vec<(usize, Breakpoint)>,
+    watches: Vec<(usize, String)>,
+    /// numbers given so far to breakpoints and to watches
+    numbered: (usize, usize),
+    /// lines a statement starts on, where a line breakpoint can stop
+    lines: HashSet<i32>,
+    /// position of the first statement of each function with any
+    entries: HashMap<String, Span>,
+    /// depth and position of the statement before the current one
+    last: Option<(usize, Span)>,
+    /// the step command an empty line repeats
+    repeat: String,
+    /// no more stops, the keyboard has ended
+    detached: bool,
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        program: &Program,
+        parser: &'a Parser,
+        file: &'a str,
+        source: &'a str,
+        interactive: bool,
+    ) -> Session<'a> {
+        let mut lines = HashSet::new();
+        let mut entries = HashMap::new();
+        for function in std::iter::once(&program.main).chain(&program.functions) {
+            collect_lines(&function.body, &mut lines);
+            if let Some(first) = function.body.statements.first() {
+                entries.insert(function.declaration.name.clone(), first.span);
+            }
+        }
+        Session {
+            parser,
+            file,
+            source,
+            interactive,
+            mode: Mode::Step,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            numbered: (0, 0),
+            lines,
+            entries,
+            last: None,
+            repeat: String::new(),
+            detached: false,
+        }
+    }
+
+    /// Show where the program stopped, then carry out commands until one of
+    /// them lets it run again
+    fn pause(&mut self, interpreter: &mut Interpreter, span: Span, hit: Option<usize>) {
+        let function = interpreter.function().to_string();
+        match hit {
+            Some(number) => println!(
+                "breakpoint {}, `{}` at {}:{}:{}",
+                number, function, self.file, span.line, span.column
+            ),
+            None => println!(
+                "`{}` at {}:{}:{}",
+                function, self.file, span.line, span.column
+            ),
+        }
+        if let Some(snippet) = diagnostic::snippet(self.source, span) {
+            println!("{}", snippet);
+        }
+        for (number, watch) in &self.watches {
+            self.show_watch(interpreter, *number, watch);
+        }
+        loop {
+            let line = match self.read() {
+                Some(line) => line,
+                None => {
+                    self.detached = true;
+                    return;
+                }
+            };
+            let line = match line.trim() {
+                "" => self.repeat.clone(),
+                line => String::from(line),
+            };
+            let (name, argument) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+            let argument = argument.trim();
+            let mode = match name {
+                "step" | "s" => Some(Mode::Step),
+                "next" | "n" => Some(Mode::Next(interpreter.depth())),
+                "finish" | "f" if interpreter.depth() == 1 => {
+                    eprintln!("error: `main` is not called by any function");
+                    None
+                }
+                "finish" | "f" => Some(Mode::Finish(interpreter.depth())),
+                "continue" | "c" => Some(Mode::Continue),
+                _ => None,
+            };
+            if let Some(mode) = mode {
+                self.mode = mode;
+                self.repeat = line.clone();
+                return;
+            }
+            match name {
+                "" | "finish" | "f" => {}
+                "break" | "b" => self.set_breakpoint(argument),
+                "delete" | "d" => self.delete(argument, true),
+                "print" | "p" if argument.is_empty() => {
+                    eprintln!("error: print needs an expression")
+                }
+                "print" | "p" => match self.value(interpreter, argument) {
+                    Ok(value) => println!("{}", value),
+                    Err(error) => eprintln!("{}", error),
+                },
+                "locals" => {
+                    for variable in interpreter.locals() {
+                        println!("{}", variable);
+                    }
+                }
+                "globals" => {
+                    for variable in interpreter.globals() {
+                        println!("{}", variable);
+                    }
+                }
+                "watch" | "w" if argument.is_empty() => {
+                    eprintln!("error: watch needs an expression")
+                }
+                "watch" | "w" => {
+                    self.numbered.1 += 1;
+                    self.watches.push((self.numbered.1, String::from(argument)));
+                    self.show_watch(interpreter, self.numbered.1, argument);
+                }
+                "unwatch" => self.delete(argument, false),
+                "backtrace" | "bt" => self.backtrace(interpreter, span),
+                "list" | "l" => self.list(span),
+                "help" | "h" => println!("{}", HELP),
+                "quit" | "q" => {
+                    io::stdout().flush().ok();
+                    process::exit(0);
+                }
+                _ => eprintln!("error: unknown command `{}`, help lists them", name),
+            }
+        }
+    }
+
+    fn read(&self) -> Option<String> {
+        if self.interactive {
+            print!("{}", PROMPT);
+        }
+        // what the program printed shows before the prompt waits
+        io::stdout().flush().ok()?;
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        }
+    }
+
+    /// `expression = (type) value`, or why it has none
+    fn value(&self, interpreter: &mut Interpreter, expression: &str) -> Result<String, String> {
+        let parsed = self
+            .parser
+            .try_parse_expression(expression, &interpreter.local_types())
+            .map_err(|error| {
+                let mut text = error.render(EXPRESSION);
+                if let Some(snippet) = diagnostic::snippet(expression, error.span) {
+                    text.push('\n');
+                    text.push_str(&snippet);
+                }
+                text
+            })?;
+        match interpreter.inspect(&parsed) {
+            Ok(Some((data_type, value))) => {
+                Ok(format!("{} = ({}) {}", expression, data_type, value))
+            }
+            Ok(None) => Ok(format!("{} has no value", expression)),
+            Err(error) => Err(format!("error: {}", error.message)),
+        }
+    }
+
+    /// a watch shows its errors where its value would be
+    fn show_watch(&self, interpreter: &mut Interpreter, number: usize, expression: &str) {
+        match self.value(interpreter, expression) {
+            Ok(value) => println!("watch {}: {}", number, value),
+            Err(error) => println!(
+                "watch {}: {}: {}",
+                number,
+                expression,
+                error.lines().next().unwrap_or_default()
+            ),
+        }
+    }
+
+    fn set_breakpoint(&mut self, argument: &str) {
+        if argument.is_empty() {
+            for (number, breakpoint) in &self.breakpoints {
+                match breakpoint {
+                    Breakpoint::Line(line) => {
+                        println!("breakpoint {} at {}:{}", number, self.file, line)
+                    }
+                    Breakpoint::Function(name) => println!("breakpoint {} in `{}`", number, name),
+                }
+            }
+            return;
+        }
+        let breakpoint = match argument.parse::<i32>() {
+            Ok(line) if self.lines.contains(&line) => Breakpoint::Line(line),
+            Ok(line) => {
+                eprintln!("error: no statement starts on line {}", line);
+                return;
+            }
+            Err(_) if self.entries.contains_key(argument) => {
+                Breakpoint::Function(String::from(argument))
+            }
+            Err(_) => {
+                eprintln!(
+                    "error: no function `{}` with statements to stop at",
+                    argument
+                );
+                return;
+            }
+        };
+        if let Some((number, _)) = self.breakpoints.iter().find(|(_, b)| *b == breakpoint) {
+            eprintln!("error: breakpoint {} is already there", number);
+            return;
+        }
+        self.numbered.0 += 1;
+        println!("breakpoint {} set", self.numbered.0);
+        self.breakpoints.push((self.numbered.0, breakpoint));
+    }
+
+    /// remove a breakpoint, or a watch, by number; all of them without one
+    fn delete(&mut self, argument: &str, breakpoint: bool) {
+        let kind = if breakpoint { "breakpoint" } else { "watch" };
+        if argument.is_empty() {
+            match breakpoint {
+                true => self.breakpoints.clear(),
+                false => self.watches.clear(),
+            }
+            return;
+        }
+        let number = match argument.parse::<usize>() {
+            Ok(number) => number,
+            Err(_) => {
+                eprintln!("error: `{}` is not the number of a {}", argument, kind);
+                return;
+            }
+        };
+        let found = match breakpoint {
+            true => remove(&mut self.breakpoints, number),
+            false => remove(&mut self.watches, number),
+        };
+        if !found {
+            eprintln!("error: no {} {}", kind, number);
+        }
+    }
+
+    /// the active calls, innermost first, each at the line it has reached
+    fn backtrace(&self, interpreter: &Interpreter, span: Span) {
+        let calls = interpreter.calls();
+        let mut position = span;
+        for (i, call) in calls.iter().enumerate() {
+            println!(
+                "#{} `{}` at {}:{}:{}",
+                i, call.function, self.file, position.line, position.column
+            );
+            position = call.span;
+        }
+    }
+
+    fn list(&self, span: Span) {
+        let width = (span.line + CONTEXT).to_string().len();
+        let first = (span.line - CONTEXT).max(1);
+        for (i, text) in self
+            .source
+            .lines()
+            .enumerate()
+            .skip(first as usize - 1)
+            .take((span.line + CONTEXT - first + 1) as usize)
+        {
+            let number = i as i32 + 1;
+            let marker = if number == span.line { "=>" } else { "  " };
+            println!("{} {:>width$} | {}", marker, number, text, width = width);
+        }
+    }
+}
+
+impl<'a> Debugger<'a> for Session<'a> {
+    fn statement(&mut self, interpreter: &mut Interpreter<'a>, statement: &Statement) {
+        let depth = interpreter.depth();
+        let span = statement.span;
+        // statements further along the line of the one before are part of
+        // the same line; a loop coming back to the start of it is not
+        let new_line = match self.last {
+            Some((last_depth, last)) => {
+                last_depth != depth || last.line != span.line || last.column >= span.column
+            }
+            None => true,
+        };
+        self.last = Some((depth, span));
+        if self.detached {
+            return;
+        }
+        let entered = self.entries.get(interpreter.function()) == Some(&span);
+        let hit = self
+            .breakpoints
+            .iter()
+            .find(|(_, breakpoint)| match breakpoint {
+                Breakpoint::Line(line) => *line == span.line && new_line,
+                Breakpoint::Function(name) => entered && name == interpreter.function(),
+            })
+            .map(|(number, _)| *number);
+        let stop = hit.is_some()
+            || match self.mode {
+                Mode::Step => new_line,
+                Mode::Next(call) => depth <= call && new_line,
+                Mode::Finish(call) => depth < call,
+                Mode::Continue => false,
+            };
+        if stop {
+            self.pause(interpreter, span, hit);
+        }
+    }
+}
+
+fn collect_lines(block: &Block, lines: &mut HashSet<i32>) {
+    for statement in &block.statements {
+        lines.insert(statement.span.line);
+        match &statement.kind {
+            StatementKind::While { body, .. }
+            | StatementKind::For { body, .. }
+            | StatementKind::If { body, .. }
+            | StatementKind::Switch { body, .. } => collect_lines(body, lines),
+            StatementKind::Block(block) => collect_lines(block, lines),
+            _ => {}
+        }
+    }
+}
+
+/// remove the entry with this number, false when there is none
+fn remove<T>(entries: &mut Vec<(usize, T)>, number: usize) -> bool {
+    let length = entries.len();
+    entries.retain(|(n, _)| *n != number);
+    entries.len() != length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const SOURCE: &str = "int Boom(int n);
+void main(){
+    int X;
+    X = 5;
+    print_int(X);
+}
+int Boom(int n){
+    int a[3];
+    int X;
+    X = 99;
+    a[n] = 1;
+    return X;
+}";
+
+    /// evaluates expressions at one line, as `print` does there
+    struct Probe<'a> {
+        session: Session<'a>,
+        line: i32,
+        expressions: Vec<&'static str>,
+        results: Rc<RefCell<Vec<Result<String, String>>>>,
+    }
+
+    impl<'a> Debugger<'a> for Probe<'a> {
+        fn statement(&mut self, interpreter: &mut Interpreter<'a>, statement: &Statement) {
+            if statement.span.line != self.line {
+                return;
+            }
+            for expression in &self.expressions {
+                let result = self.session.value(interpreter, expression);
+                self.results.borrow_mut().push(result);
+            }
+            let calls: Vec<String> = interpreter
+                .calls()
+                .into_iter()
+                .map(|call| call.function)
+                .collect();
+            self.results.borrow_mut().push(Ok(calls.join(" ")));
+        }
+    }
+
+    #[test]
+    fn a_failing_call_leaves_the_paused_run_as_it_was() {
+        let mut parser = Parser::from_source(SOURCE, "format.csv");
+        let program = parser.parse();
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let mut output = Vec::new();
+        let result = {
+            let mut interpreter =
+                Interpreter::with_io(&program, Box::new(io::empty()), Box::new(&mut output));
+            interpreter.set_debugger(Box::new(Probe {
+                session: Session::new(&program, &parser, "boom.x", SOURCE, false),
+                line: 5,
+                expressions: vec!["Boom(7)", "X", "Boom(1)", "X + 1"],
+                results: results.clone(),
+            }));
+            interpreter.run().map(|()| interpreter.main_variables())
+        };
+        assert_eq!(
+            *results.borrow(),
+            vec![
+                Err(String::from(
+                    "error: index 7 is out of bounds for an array of 3 elements"
+                )),
+                Ok(String::from("X = (int) 5")),
+                Ok(String::from("Boom(1) = (int) 99")),
+                Ok(String::from("X + 1 = (int) 6")),
+                Ok(String::from("main")),
+            ]
+        );
+        assert_eq!(result, Ok(vec![String::from("int X = 5")]));
+        assert_eq!(output, b"5");
+    }
+}
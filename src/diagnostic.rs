@@ -0,0 +1,97 @@
+use crate::token::Span;
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A problem found in an X program, pointing at the token it is about
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            span,
+        }
+    }
+
+    pub fn warning(message: String, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            message,
+            span,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// format the diagnostic with the path of the file it was found in
+    pub fn render(&self, file: &str) -> String {
+        format!(
+            "{}: {}\n  --> {}:{}:{}",
+            self.severity.as_str(),
+            self.message,
+            file,
+            self.span.line,
+            self.span.column
+        )
+    }
+}
+
+/// The source line of a span with a caret under its column, after a gutter
+/// holding the line number; None when the file has no such line
+///
+/// # Arguments
+///
+/// * `source` - content of the file
+/// * `span` - position to show
+///
+pub fn snippet(source: &str, span: Span) -> Option<String> {
+    let text = source.lines().nth((span.line as usize).checked_sub(1)?)?;
+    let number = span.line.to_string();
+    let gutter = " ".repeat(number.len());
+    // tabs are kept so that the caret lines up however wide they are shown
+    let indent: String = text
+        .chars()
+        .take((span.column as usize).saturating_sub(1))
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    Some(format!(
+        "{} |\n{} | {}\n{} | {}^",
+        gutter, number, text, gutter, indent
+    ))
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.span.line,
+            self.span.column,
+            self.severity.as_str(),
+            self.message
+        )
+    }
+}
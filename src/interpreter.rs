@@ -0,0 +1,1552 @@
+use crate::ast::*;
+use crate::builtins::{self, Io};
+use crate::const_eval;
+use crate::diagnostic::{self, Diagnostic};
+use crate::layout::Layout;
+use crate::token::Span;
+use crate::value::{ArithmeticError, Number};
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// What a memory cell holds, or what an expression evaluates to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(Number),
+    /// address of the first cell pointed to, 0 for the null pointer,
+    /// and the type found there
+    Pointer(usize, Type),
+    /// copy of the cells of a struct, member after member
+    Aggregate(Vec<Value>),
+    /// result of calling a void function
+    Void,
+    /// cell that was never assigned
+    Uninitialized,
+}
+
+/// A resource whose use a run can bound
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// steps taken: statements and expressions for the interpreter,
+    /// instructions for the virtual machine
+    Fuel,
+    /// calls active at once, main included
+    CallDepth,
+    /// memory cells, globals included
+    Memory,
+}
+
+impl Limit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Limit::Fuel => "fuel",
+            Limit::CallDepth => "call depth",
+            Limit::Memory => "memory",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Limit::Fuel => "steps",
+            Limit::CallDepth => "calls",
+            Limit::Memory => "cells",
+        }
+    }
+}
+
+/// Bounds on the resources of a run, None for no bound
+///
+/// The default bounds every resource, so that an endless loop or a runaway
+/// recursion stops with an error instead of hanging or crashing the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub fuel: Option<u64>,
+    pub call_depth: Option<usize>,
+    pub memory: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            fuel: Some(10_000_000),
+            call_depth: Some(1000),
+            memory: Some(1 << 22),
+        }
+    }
+}
+
+/// Deepest the calls of the interpreter go, whatever the call depth limit:
+/// each X call nests several Rust calls on the native stack, which cannot
+/// grow without bound
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+impl Limits {
+    /// the limits with the call depth bounded by `MAX_CALL_DEPTH`, the
+    /// bounds the interpreter runs with
+    pub fn for_interpreter(&self) -> Limits {
+        let depth = self
+            .call_depth
+            .map_or(MAX_CALL_DEPTH, |depth| depth.min(MAX_CALL_DEPTH));
+        Limits {
+            call_depth: Some(depth),
+            ..*self
+        }
+    }
+
+    /// bytes of native stack the interpreter needs to reach its call depth
+    /// limit
+    pub fn stack_size(&self) -> usize {
+        const PER_CALL: usize = 64 * 1024;
+        let depth = self.for_interpreter().call_depth.unwrap();
+        (depth + 16) * PER_CALL
+    }
+
+    fn value(&self, limit: Limit) -> Option<u64> {
+        match limit {
+            Limit::Fuel => self.fuel,
+            Limit::CallDepth => self.call_depth.map(|depth| depth as u64),
+            Limit::Memory => self.memory.map(|cells| cells as u64),
+        }
+    }
+
+    /// an error when `used` goes beyond the bound of the limit
+    pub fn check(&self, limit: Limit, used: u64, span: Span) -> Result<()> {
+        match self.value(limit) {
+            Some(bound) if used > bound => Err(RuntimeError {
+                message: format!(
+                    "{} limit of {} {} exceeded",
+                    limit.as_str(),
+                    bound,
+                    limit.unit()
+                ),
+                span,
+                limit: Some(limit),
+                stack: Vec::new(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A call active when a program stopped
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub function: String,
+    /// where the function was called from, the declaration for main
+    pub span: Span,
+}
+
+/// A fault that stops the program, such as a division by zero, or a limit
+/// it went beyond
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Span,
+    /// the limit that stopped the program, None for a fault of the program
+    pub limit: Option<Limit>,
+    /// calls active at the fault, innermost first
+    pub stack: Vec<Call>,
+}
+
+/// calls shown at each end of a longer stack, the ones between are counted
+const SHOWN_CALLS: usize = 8;
+
+impl RuntimeError {
+    pub fn new(message: String, span: Span) -> RuntimeError {
+        RuntimeError {
+            message,
+            span,
+            limit: None,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Format the error as a diagnostic followed by where each active call
+    /// was made, with the source line of each position when the source is
+    /// given
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - path of the source file
+    /// * `source` - its content
+    ///
+    pub fn render(&self, file: &str, source: Option<&str>) -> String {
+        self.render_with(file, &|span| {
+            (
+                span,
+                source.and_then(|source| diagnostic::snippet(source, span)),
+            )
+        })
+    }
+
+    /// render like `render`, with `locate` giving the position to show for
+    /// each span and the snippet under it, for sources made of several texts
+    pub fn render_with(
+        &self,
+        file: &str,
+        locate: &dyn Fn(Span) -> (Span, Option<String>),
+    ) -> String {
+        let with_snippet = |text: String, snippet: Option<String>| match snippet {
+            Some(snippet) => format!("{}\n{}", text, snippet),
+            None => text,
+        };
+        let (span, snippet) = locate(self.span);
+        let diagnostic = Diagnostic {
+            span,
+            ..self.to_diagnostic()
+        };
+        let mut text = with_snippet(diagnostic.render(file), snippet);
+        // the last call is the one of main, made by nothing in the program
+        let calls = self.stack.len().saturating_sub(1);
+        for (i, pair) in self.stack.windows(2).enumerate() {
+            if calls > 2 * SHOWN_CALLS && i >= SHOWN_CALLS && i < calls - SHOWN_CALLS {
+                if i == SHOWN_CALLS {
+                    text.push_str(&format!("\nnote: {} more calls", calls - 2 * SHOWN_CALLS));
+                }
+                continue;
+            }
+            let (call, caller) = (&pair[0], &pair[1]);
+            let (span, snippet) = locate(call.span);
+            let note = format!(
+                "note: `{}` called from `{}`\n  --> {}:{}:{}",
+                call.function, caller.function, file, span.line, span.column
+            );
+            text.push('\n');
+            text.push_str(&with_snippet(note, snippet));
+        }
+        text
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.message.clone(), self.span)
+    }
+}
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Where a variable lives in memory
+#[derive(Clone, Debug)]
+struct Binding {
+    address: usize,
+    data_type: Type,
+}
+
+/// Variables of one call, innermost scope last
+struct Frame {
+    function: String,
+    /// where the function was called from
+    call: Span,
+    scopes: Vec<Vec<(String, Binding)>>,
+}
+
+/// Looks at a run before each of its statements, and may pause it there to
+/// inspect it through the interpreter; see `Interpreter::set_debugger`
+pub trait Debugger<'a> {
+    /// called with the statement about to run, in the scope it runs in
+    fn statement(&mut self, interpreter: &mut Interpreter<'a>, statement: &Statement);
+}
+
+/// How control leaves a statement
+enum Flow {
+    Next,
+    /// leave the innermost loop or switch
+    Break,
+    Return(Option<Value>),
+}
+
+/// Runs an X program by walking its syntax tree
+///
+/// Memory is a flat list of cells, one per scalar: arrays and structs take
+/// consecutive cells and a pointer is the index of a cell. Cell 0 is never
+/// used so that address 0 is the null pointer. Globals and static locals come
+/// first, followed by a stack that grows with every call and block.
+///
+/// The builtin functions read from and write to the standard streams unless
+/// others are given to `with_io`. A run stops with an error when it goes
+/// beyond its `Limits`, and a `Debugger` can look at it before every statement.
+pub struct Interpreter<'a> {
+    functions: HashMap<&'a str, &'a Function>,
+    layout: Layout,
+    program: &'a Program,
+    memory: Vec<Value>,
+    globals: HashMap<String, Binding>,
+    /// address of each static local, keyed by its declaration
+    statics: HashMap<Span, usize>,
+    frames: Vec<Frame>,
+    /// variables of main when it returned
+    main_variables: Vec<(String, Binding)>,
+    io: Io<'a>,
+    limits: Limits,
+    /// steps taken so far, the fuel used
+    steps: u64,
+    debugger: Option<Box<dyn Debugger<'a> + 'a>>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Prepare to run a program that passed every check without errors
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - syntax tree from the parser
+    ///
+    pub fn new(program: &'a Program) -> Interpreter<'a> {
+        Interpreter::with(program, Io::standard())
+    }
+
+    /// Prepare to run a program reading and writing through the given streams,
+    /// so that a byte slice can stand for the keyboard and a `Vec<u8>` for the screen
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - syntax tree from the parser
+    /// * `input` - where read_int reads from
+    /// * `output` - where the print functions and printf write to
+    ///
+    pub fn with_io(
+        program: &'a Program,
+        input: Box<dyn BufRead + 'a>,
+        output: Box<dyn Write + 'a>,
+    ) -> Interpreter<'a> {
+        Interpreter::with(program, Io::new(input, output))
+    }
+
+    fn with(program: &'a Program, io: Io<'a>) -> Interpreter<'a> {
+        let mut interpreter = Interpreter {
+            functions: HashMap::new(),
+            layout: Layout::new(program),
+            program,
+            memory: vec![Value::Uninitialized],
+            globals: HashMap::new(),
+            statics: HashMap::new(),
+            frames: Vec::new(),
+            main_variables: Vec::new(),
+            io,
+            limits: Limits::default(),
+            steps: 0,
+            debugger: None,
+        };
+        for function in std::iter::once(&program.main).chain(&program.functions) {
+            interpreter
+                .functions
+                .insert(&function.declaration.name, function);
+        }
+        interpreter
+    }
+
+    /// Bound the resources of the next run, instead of the default limits;
+    /// the call depth never goes beyond `MAX_CALL_DEPTH`, even unlimited
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits.for_interpreter();
+    }
+
+    /// Call a debugger before every statement of the next run
+    pub fn set_debugger(&mut self, debugger: Box<dyn Debugger<'a> + 'a>) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Initialise the globals and static locals, then call `main`
+    pub fn run(&mut self) -> Result<()> {
+        self.steps = 0;
+        for declaration in &self.program.declarations {
+            if let Declaration::Variable(variable) = declaration {
+                self.global(variable)?;
+            }
+        }
+        for function in std::iter::once(&self.program.main).chain(&self.program.functions) {
+            self.allocate_statics(&function.body)?;
+        }
+        let result = self.call("main", &[], self.program.main.declaration.span);
+        // what was printed before a fault is still shown
+        self.io
+            .flush()
+            .map_err(|message| RuntimeError::new(message, self.program.main.declaration.span))?;
+        result.map(|_| ())
+    }
+
+    /// `type name = value` for each variable of main's outermost block, as it
+    /// was when main returned
+    pub fn main_variables(&self) -> Vec<String> {
+        self.main_variables
+            .iter()
+            .map(|(name, binding)| self.show_variable(name, binding))
+            .collect()
+    }
+
+    /// Calls active in a paused run, innermost first, each with where it was
+    /// made from
+    pub fn calls(&self) -> Vec<Call> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| Call {
+                function: frame.function.clone(),
+                span: frame.call,
+            })
+            .collect()
+    }
+
+    /// number of calls active, main included
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// name of the function running, empty before main is called
+    pub fn function(&self) -> &str {
+        self.frames.last().map_or("", |frame| &frame.function)
+    }
+
+    /// `type name = value` for each variable in scope in the function
+    /// running, outermost first, without the ones a nested block hides
+    pub fn locals(&self) -> Vec<String> {
+        self.visible()
+            .into_iter()
+            .map(|(name, binding)| self.show_variable(name, binding))
+            .collect()
+    }
+
+    /// names and types of the variables `locals` shows, for parsing
+    /// expressions to `inspect`
+    pub fn local_types(&self) -> Vec<(String, Type)> {
+        self.visible()
+            .into_iter()
+            .map(|(name, binding)| (name.clone(), binding.data_type.clone()))
+            .collect()
+    }
+
+    fn visible(&self) -> Vec<&(String, Binding)> {
+        let scopes = match self.frames.last() {
+            Some(frame) => &frame.scopes,
+            None => return Vec::new(),
+        };
+        let variables: Vec<_> = scopes.iter().flatten().collect();
+        variables
+            .iter()
+            .enumerate()
+            .filter(|(i, (name, _))| variables[i + 1..].iter().all(|(later, _)| later != name))
+            .map(|(_, variable)| *variable)
+            .collect()
+    }
+
+    /// `type name = value` for each global variable, in the order they were
+    /// declared
+    pub fn globals(&self) -> Vec<String> {
+        let mut globals: Vec<_> = self.globals.iter().collect();
+        globals.sort_by_key(|(_, binding)| binding.address);
+        globals
+            .into_iter()
+            .map(|(name, binding)| self.show_variable(name, binding))
+            .collect()
+    }
+
+    fn show_variable(&self, name: &str, binding: &Binding) -> String {
+        format!(
+            "{} {} = {}",
+            binding.data_type,
+            name,
+            self.describe(binding.address, &binding.data_type)
+        )
+    }
+
+    /// Evaluate an expression in the scope of a paused run: the type and the
+    /// value, None for a void call
+    ///
+    /// # Arguments
+    ///
+    /// * `expression` - any expression, a fault in it is returned as an error
+    ///
+    pub fn inspect(&mut self, expression: &Expression) -> Result<Option<(Type, String)>> {
+        let result = self.unwinding(|interpreter| interpreter.show(expression));
+        self.io
+            .flush()
+            .map_err(|message| RuntimeError::new(message, expression.span))?;
+        result
+    }
+
+    /// Evaluate an expression after `run`, as if it ended main's body: the
+    /// variables of main's outermost block are in scope with the values they
+    /// were left with. The type and the value, None for a void call.
+    ///
+    /// # Arguments
+    ///
+    /// * `expression` - checked against the program, with main's variables
+    ///   declared
+    ///
+    pub fn evaluate_in_main(&mut self, expression: &Expression) -> Result<Option<(Type, String)>> {
+        self.frames.push(Frame {
+            function: String::from("main"),
+            call: self.program.main.declaration.span,
+            scopes: vec![self.main_variables.clone()],
+        });
+        let result = self.inspect(expression).map_err(|mut error| {
+            error.stack.push(Call {
+                function: String::from("main"),
+                span: self.program.main.declaration.span,
+            });
+            error
+        });
+        self.frames.pop();
+        result
+    }
+
+    fn show(&mut self, expression: &Expression) -> Result<Option<(Type, String)>> {
+        let data_type = self.static_type(expression);
+        let (data_type, value) = match (data_type, self.evaluate(expression)?) {
+            (_, Value::Void) | (None, _) => return Ok(None),
+            (Some(data_type), value) => (data_type, value),
+        };
+        // arrays are shown where they are, other values are stored to be shown
+        let text = match value {
+            Value::Pointer(address, _) if data_type.is_array() => {
+                self.describe(address, &data_type)
+            }
+            value => {
+                let address = self.allocate(&data_type, expression.span)?;
+                self.store(address, &data_type, value, expression.span)?;
+                let text = self.describe(address, &data_type);
+                self.memory.truncate(address);
+                text
+            }
+        };
+        Ok(Some((data_type, text)))
+    }
+
+    fn global(&mut self, variable: &VariableDeclaration) -> Result<()> {
+        // an extern declaration and the definition share one variable
+        let address = match self.globals.get(&variable.name) {
+            Some(binding) => binding.address,
+            None => {
+                let address = self.allocate(&variable.data_type, variable.span)?;
+                self.fill(address, &variable.data_type, true);
+                self.globals.insert(
+                    variable.name.clone(),
+                    Binding {
+                        address,
+                        data_type: variable.data_type.clone(),
+                    },
+                );
+                address
+            }
+        };
+        match &variable.initializer {
+            Some(initializer) => self.initialize(address, &variable.data_type, initializer),
+            None => Ok(()),
+        }
+    }
+
+    /// static locals are created once, before main runs, like globals
+    fn allocate_statics(&mut self, block: &Block) -> Result<()> {
+        for statement in &block.statements {
+            match &statement.kind {
+                StatementKind::Declaration(Declaration::Variable(variable))
+                    if variable.qualifiers.storage == StorageClass::Static =>
+                {
+                    let address = self.allocate(&variable.data_type, variable.span)?;
+                    self.fill(address, &variable.data_type, true);
+                    if let Some(initializer) = &variable.initializer {
+                        self.initialize(address, &variable.data_type, initializer)?;
+                    }
+                    self.statics.insert(variable.span, address);
+                }
+                StatementKind::Block(body)
+                | StatementKind::If { body, .. }
+                | StatementKind::While { body, .. }
+                | StatementKind::For { body, .. }
+                | StatementKind::Switch { body, .. } => self.allocate_statics(body)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn allocate(&mut self, data_type: &Type, span: Span) -> Result<usize> {
+        let address = self.memory.len();
+        let size = self.layout.cells(data_type);
+        self.limits
+            .check(Limit::Memory, (address + size) as u64, span)?;
+        self.memory.resize(address + size, Value::Uninitialized);
+        Ok(address)
+    }
+
+    /// use one step of fuel
+    fn step(&mut self, span: Span) -> Result<()> {
+        self.steps += 1;
+        self.limits.check(Limit::Fuel, self.steps, span)
+    }
+
+    /// set every scalar inside the variable to zero, or mark it unassigned
+    fn fill(&mut self, address: usize, data_type: &Type, zero: bool) {
+        match data_type {
+            Type::Array(element, size) => {
+                let stride = self.layout.cells(element);
+                for i in 0..size.unwrap_or(0) {
+                    self.fill(address + i * stride, element, zero);
+                }
+            }
+            Type::Struct(name) => {
+                let mut offset = 0;
+                for member in self.layout.members(name).unwrap_or_default() {
+                    self.fill(address + offset, &member.data_type, zero);
+                    offset += self.layout.cells(&member.data_type);
+                }
+            }
+            Type::Void => {}
+            scalar => {
+                self.memory[address] = match zero {
+                    true => zero_value(scalar),
+                    false => Value::Uninitialized,
+                }
+            }
+        }
+    }
+
+    /// store an initialiser, elements and members a list leaves out are zero
+    fn initialize(
+        &mut self,
+        address: usize,
+        data_type: &Type,
+        initializer: &Initializer,
+    ) -> Result<()> {
+        match (initializer, data_type) {
+            (Initializer::Expression(expr), _) => {
+                let value = self.evaluate(expr)?;
+                self.store(address, data_type, value, expr.span)
+            }
+            (Initializer::List(items, _), Type::Array(element, _)) => {
+                self.fill(address, data_type, true);
+                let stride = self.layout.cells(element);
+                for (i, item) in items.iter().enumerate() {
+                    self.initialize(address + i * stride, element, item)?;
+                }
+                Ok(())
+            }
+            (Initializer::List(items, _), Type::Struct(name)) => {
+                self.fill(address, data_type, true);
+                let mut offset = 0;
+                for (member, item) in self
+                    .layout
+                    .members(name)
+                    .unwrap_or_default()
+                    .iter()
+                    .zip(items)
+                {
+                    self.initialize(address + offset, &member.data_type, item)?;
+                    offset += self.layout.cells(&member.data_type);
+                }
+                Ok(())
+            }
+            // `int x = {1};` initialises a scalar with its first item
+            (Initializer::List(items, _), _) => match items.first() {
+                Some(item) => self.initialize(address, data_type, item),
+                None => {
+                    self.fill(address, data_type, true);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    fn check_address(&self, address: usize, size: usize, span: Span) -> Result<()> {
+        if address == 0 {
+            return Err(RuntimeError::new(
+                String::from("null pointer dereference"),
+                span,
+            ));
+        }
+        if address + size > self.memory.len() {
+            return Err(RuntimeError::new(
+                String::from("invalid memory access"),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    /// value of the variable at an address; arrays decay to a pointer to their
+    /// first element
+    fn load(&self, address: usize, data_type: &Type, span: Span) -> Result<Value> {
+        match data_type {
+            Type::Array(element, _) => Ok(Value::Pointer(address, (**element).clone())),
+            Type::Struct(_) => {
+                let size = self.layout.cells(data_type);
+                self.check_address(address, size, span)?;
+                Ok(Value::Aggregate(
+                    self.memory[address..address + size].to_vec(),
+                ))
+            }
+            _ => {
+                self.check_address(address, 1, span)?;
+                match &self.memory[address] {
+                    Value::Uninitialized => Err(RuntimeError::new(
+                        String::from("read of uninitialised memory"),
+                        span,
+                    )),
+                    value => Ok(value.clone()),
+                }
+            }
+        }
+    }
+
+    /// write a value to the variable at an address, converted to its type
+    fn store(&mut self, address: usize, data_type: &Type, value: Value, span: Span) -> Result<()> {
+        match value {
+            Value::Aggregate(cells) => {
+                self.check_address(address, cells.len(), span)?;
+                for (i, cell) in cells.into_iter().enumerate() {
+                    self.memory[address + i] = cell;
+                }
+            }
+            value => {
+                let value = self.convert(value, data_type, span)?;
+                self.check_address(address, 1, span)?;
+                self.memory[address] = value;
+            }
+        }
+        Ok(())
+    }
+
+    /// the conversion of an assignment or a cast
+    fn convert(&self, value: Value, to: &Type, span: Span) -> Result<Value> {
+        match (value, to) {
+            (Value::Void, _) => Err(RuntimeError::new(String::from("void value used"), span)),
+            (Value::Number(constant), Type::Pointer(target)) => Ok(Value::Pointer(
+                constant.as_integer().unwrap_or(0) as usize,
+                (**target).clone(),
+            )),
+            (Value::Number(constant), _) => constant
+                .convert(to)
+                .map(Value::Number)
+                .map_err(|kind| arithmetic_error(kind, span)),
+            (Value::Pointer(address, _), Type::Pointer(target)) => {
+                Ok(Value::Pointer(address, (**target).clone()))
+            }
+            (Value::Pointer(address, _), Type::Integer { .. }) => Ok(Value::Number(
+                Number::Integer {
+                    value: address as i128,
+                    kind: IntKind::Long,
+                    unsigned: true,
+                }
+                .convert(to)
+                .map_err(|kind| arithmetic_error(kind, span))?,
+            )),
+            (value, _) => Ok(value),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.frames
+            .last()
+            .and_then(|frame| {
+                frame
+                    .scopes
+                    .iter()
+                    .rev()
+                    .flat_map(|scope| scope.iter().rev())
+                    .find(|(n, _)| n == name)
+                    .map(|(_, binding)| binding)
+            })
+            .or_else(|| self.globals.get(name))
+    }
+
+    fn bind(&mut self, name: &str, binding: Binding) {
+        let frame = self.frames.last_mut().unwrap();
+        frame
+            .scopes
+            .last_mut()
+            .unwrap()
+            .push((String::from(name), binding));
+    }
+
+    fn evaluate(&mut self, expr: &Expression) -> Result<Value> {
+        self.step(expr.span)?;
+        match &expr.kind {
+            ExpressionKind::IntConstant(_)
+            | ExpressionKind::FloatConstant(_)
+            | ExpressionKind::EnumConstant { .. }
+            | ExpressionKind::SizeOfType(_)
+            | ExpressionKind::SizeOfExpression(_) => {
+                let variable = |name: &str| Some(self.lookup(name)?.data_type.clone());
+                let size_of = |expr: &Expression| self.layout.size_of(expr, &variable);
+                const_eval::evaluate(expr, &size_of)
+                    .map(Value::Number)
+                    .map_err(|error| RuntimeError::new(error.message(), error.span))
+            }
+            ExpressionKind::StringConstant(_) => Err(RuntimeError::new(
+                String::from("a string literal can only be the format of `printf`"),
+                expr.span,
+            )),
+            ExpressionKind::Variable(name) => {
+                let binding = match self.lookup(name) {
+                    Some(binding) => binding.clone(),
+                    None => {
+                        return Err(RuntimeError::new(
+                            format!("`{}` is not declared", name),
+                            expr.span,
+                        ))
+                    }
+                };
+                if binding.data_type.is_scalar()
+                    && self.memory[binding.address] == Value::Uninitialized
+                {
+                    return Err(RuntimeError::new(
+                        format!("`{}` is used before it is assigned", name),
+                        expr.span,
+                    ));
+                }
+                self.load(binding.address, &binding.data_type, expr.span)
+            }
+            ExpressionKind::Index { .. }
+            | ExpressionKind::Member { .. }
+            | ExpressionKind::Unary {
+                operator: UnaryOperator::Dereference,
+                ..
+            } => {
+                let (address, data_type) = self.lvalue(expr)?;
+                self.load(address, &data_type, expr.span)
+            }
+            ExpressionKind::Unary {
+                operator: UnaryOperator::AddressOf,
+                operand,
+            } => {
+                let (address, data_type) = self.lvalue(operand)?;
+                Ok(Value::Pointer(address, data_type))
+            }
+            ExpressionKind::Unary {
+                operator: UnaryOperator::Negate,
+                operand,
+            } => match self.evaluate(operand)? {
+                Value::Number(number) => number
+                    .negate()
+                    .map(Value::Number)
+                    .map_err(|error| arithmetic_error(error, expr.span)),
+                _ => Err(RuntimeError::new(
+                    String::from("negated value is not a number"),
+                    expr.span,
+                )),
+            },
+            ExpressionKind::Cast { data_type, operand } => {
+                let value = self.evaluate(operand)?;
+                self.convert(value, data_type, expr.span)
+            }
+            ExpressionKind::Call { name, arguments } => self.call(name, arguments, expr.span),
+            ExpressionKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                self.binary(*operator, left, right, expr.span)
+            }
+        }
+    }
+
+    /// address and type of the storage an lvalue names
+    fn lvalue(&mut self, expr: &Expression) -> Result<(usize, Type)> {
+        match &expr.kind {
+            ExpressionKind::Variable(name) => match self.lookup(name) {
+                Some(binding) => Ok((binding.address, binding.data_type.clone())),
+                None => Err(RuntimeError::new(
+                    format!("`{}` is not declared", name),
+                    expr.span,
+                )),
+            },
+            ExpressionKind::Index { array, index } => {
+                let base = self.evaluate(array)?;
+                let position = match self.evaluate(index)? {
+                    Value::Number(constant) => constant.as_integer().unwrap_or(0),
+                    _ => 0,
+                };
+                if let Some(Type::Array(_, Some(size))) = self.static_type(array) {
+                    if position < 0 || position >= size as i128 {
+                        return Err(RuntimeError::new(
+                            format!(
+                                "index {} is out of bounds for an array of {} elements",
+                                position, size
+                            ),
+                            index.span,
+                        ));
+                    }
+                }
+                match base {
+                    Value::Pointer(address, element) => {
+                        let address =
+                            address as i128 + position * self.layout.cells(&element) as i128;
+                        if address <= 0 {
+                            return Err(RuntimeError::new(
+                                String::from("invalid memory access"),
+                                expr.span,
+                            ));
+                        }
+                        Ok((address as usize, element))
+                    }
+                    _ => Err(RuntimeError::new(
+                        String::from("indexed value is not an array or a pointer"),
+                        expr.span,
+                    )),
+                }
+            }
+            ExpressionKind::Member { base, member } => {
+                let (address, data_type) = self.lvalue(base)?;
+                let found = match &data_type {
+                    Type::Struct(name) => self.layout.member(name, member),
+                    _ => None,
+                };
+                match found {
+                    Some((offset, member_type)) => Ok((address + offset, member_type)),
+                    None => Err(RuntimeError::new(
+                        format!("`{}` has no member `{}`", data_type, member),
+                        expr.span,
+                    )),
+                }
+            }
+            ExpressionKind::Unary {
+                operator: UnaryOperator::Dereference,
+                operand,
+            } => match self.evaluate(operand)? {
+                Value::Pointer(0, _) => Err(RuntimeError::new(
+                    String::from("null pointer dereference"),
+                    expr.span,
+                )),
+                Value::Pointer(address, target) => Ok((address, target)),
+                _ => Err(RuntimeError::new(
+                    String::from("dereferenced value is not a pointer"),
+                    expr.span,
+                )),
+            },
+            _ => Err(RuntimeError::new(
+                String::from("expression does not name a variable"),
+                expr.span,
+            )),
+        }
+    }
+
+    fn binary(
+        &self,
+        operator: BinaryOperator,
+        left: Value,
+        right: Value,
+        span: Span,
+    ) -> Result<Value> {
+        let stride = |target: &Type| self.layout.cells(target).max(1) as i128;
+        match (left, right) {
+            (Value::Number(a), Value::Number(b)) => a
+                .binary(operator, b)
+                .map(Value::Number)
+                .map_err(|kind| arithmetic_error(kind, span)),
+            (Value::Pointer(address, target), Value::Number(n)) if !operator.is_relational() => {
+                let n = n.as_integer().unwrap_or(0) * stride(&target);
+                let address = match operator {
+                    BinaryOperator::Add => address as i128 + n,
+                    _ => address as i128 - n,
+                };
+                Ok(Value::Pointer(address.max(0) as usize, target))
+            }
+            (Value::Number(n), Value::Pointer(address, target))
+                if operator == BinaryOperator::Add =>
+            {
+                let address = address as i128 + n.as_integer().unwrap_or(0) * stride(&target);
+                Ok(Value::Pointer(address.max(0) as usize, target))
+            }
+            (Value::Pointer(a, target), Value::Pointer(b, _))
+                if operator == BinaryOperator::Subtract =>
+            {
+                Ok(Value::Number(Number::Integer {
+                    value: (a as i128 - b as i128) / stride(&target),
+                    kind: IntKind::Long,
+                    unsigned: false,
+                }))
+            }
+            (left, right) if operator.is_relational() => {
+                // pointers compare by address, a null pointer constant is 0
+                let address = |value: &Value| match value {
+                    Value::Pointer(address, _) => *address as i128,
+                    Value::Number(constant) => constant.as_integer().unwrap_or(0),
+                    _ => 0,
+                };
+                let long = |value: i128| Number::Integer {
+                    value,
+                    kind: IntKind::Long,
+                    unsigned: false,
+                };
+                long(address(&left))
+                    .binary(operator, long(address(&right)))
+                    .map(Value::Number)
+                    .map_err(|kind| arithmetic_error(kind, span))
+            }
+            _ => Err(RuntimeError::new(
+                format!("invalid operands to `{}`", operator.as_str()),
+                span,
+            )),
+        }
+    }
+
+    /// type of an expression without evaluating it, as `sizeof` needs
+    fn static_type(&self, expr: &Expression) -> Option<Type> {
+        let variable = |name: &str| Some(self.lookup(name)?.data_type.clone());
+        self.layout.type_of(expr, &variable)
+    }
+
+    fn truth(&self, value: Value, span: Span) -> Result<bool> {
+        match value {
+            Value::Number(constant) => Ok(!constant.is_zero()),
+            Value::Pointer(address, _) => Ok(address != 0),
+            _ => Err(RuntimeError::new(
+                String::from("condition is not a number or a pointer"),
+                span,
+            )),
+        }
+    }
+
+    fn call(&mut self, name: &str, arguments: &[Expression], span: Span) -> Result<Value> {
+        let function = match self.functions.get(name) {
+            Some(function) => *function,
+            None if builtins::is_builtin(name) => return self.builtin(name, arguments, span),
+            None => {
+                return Err(RuntimeError::new(
+                    format!("`{}` is called but never defined", name),
+                    span,
+                ))
+            }
+        };
+        let mut values = Vec::new();
+        for argument in arguments {
+            values.push(self.evaluate(argument)?);
+        }
+
+        self.limits
+            .check(Limit::CallDepth, self.frames.len() as u64 + 1, span)?;
+        self.unwinding(|interpreter| interpreter.enter(function, name, values, arguments, span))
+    }
+
+    /// run the body of a called function with its parameters bound to the
+    /// values of the arguments
+    fn enter(
+        &mut self,
+        function: &'a Function,
+        name: &str,
+        values: Vec<Value>,
+        arguments: &[Expression],
+        span: Span,
+    ) -> Result<Value> {
+        let base = self.memory.len();
+        let mut scope = Vec::new();
+        for ((parameter, value), argument) in function
+            .declaration
+            .parameters
+            .iter()
+            .zip(values)
+            .zip(arguments)
+        {
+            // `int a[]` receives a pointer to the caller's array
+            let data_type = match &parameter.data_type {
+                Type::Array(element, _) => Type::Pointer(element.clone()),
+                data_type => data_type.clone(),
+            };
+            let address = self.allocate(&data_type, span)?;
+            self.store(address, &data_type, value, argument.span)?;
+            scope.push((parameter.name.clone(), Binding { address, data_type }));
+        }
+        self.frames.push(Frame {
+            function: String::from(name),
+            call: span,
+            scopes: vec![scope],
+        });
+        let flow = self
+            .statements(&function.body.statements)
+            .map_err(|mut error| {
+                error.stack.push(Call {
+                    function: String::from(name),
+                    span,
+                });
+                error
+            })?;
+        let mut frame = self.frames.pop().unwrap();
+
+        let return_type = &function.declaration.return_type;
+        let result = match flow {
+            Flow::Return(Some(value)) => self.convert(value, return_type, span)?,
+            _ if *return_type == Type::Void => Value::Void,
+            _ => {
+                return Err(RuntimeError::new(
+                    format!("`{}` ended without returning a value", name),
+                    function.body.span,
+                ))
+            }
+        };
+        if self.frames.is_empty() {
+            self.main_variables = frame.scopes.swap_remove(0);
+        } else {
+            self.memory.truncate(base);
+        }
+        Ok(result)
+    }
+
+    fn block(&mut self, block: &Block) -> Result<Flow> {
+        self.block_from(block, 0)
+    }
+
+    /// run the statements of a block from the one at `position`, as a switch
+    /// does from the label it matched
+    fn block_from(&mut self, block: &Block, position: usize) -> Result<Flow> {
+        self.unwinding(|interpreter| {
+            let base = interpreter.memory.len();
+            interpreter
+                .frames
+                .last_mut()
+                .unwrap()
+                .scopes
+                .push(Vec::new());
+            let flow = interpreter.statements(&block.statements[position..])?;
+            interpreter.frames.last_mut().unwrap().scopes.pop();
+            interpreter.memory.truncate(base);
+            Ok(flow)
+        })
+    }
+
+    /// run `f`, putting the frames, the scopes of the current frame and the
+    /// memory back as they were when it fails, so that a fault in what the
+    /// debugger evaluates leaves the paused run as it found it
+    fn unwinding<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let frames = self.frames.len();
+        let scopes = self.frames.last().map_or(0, |frame| frame.scopes.len());
+        let memory = self.memory.len();
+        let result = f(self);
+        if result.is_err() {
+            self.frames.truncate(frames);
+            if let Some(frame) = self.frames.last_mut() {
+                frame.scopes.truncate(scopes);
+            }
+            self.memory.truncate(memory);
+        }
+        result
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<Flow> {
+        for statement in statements {
+            match self.statement(statement)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<Flow> {
+        self.step(statement.span)?;
+        // taken out while it runs, so that what it evaluates is not paused
+        if let Some(mut debugger) = self.debugger.take() {
+            debugger.statement(self, statement);
+            self.debugger = Some(debugger);
+        }
+        match &statement.kind {
+            StatementKind::Assignment { targets, value } => {
+                let mut value = self.evaluate(value)?;
+                // `a = b = v` stores into b first, then stores b's new value into a
+                for target in targets.iter().rev() {
+                    let (address, data_type) = self.lvalue(target)?;
+                    self.store(address, &data_type, value, target.span)?;
+                    value = self.load(address, &data_type, target.span)?;
+                }
+                Ok(Flow::Next)
+            }
+            StatementKind::While { condition, body } => loop {
+                let value = self.evaluate(condition)?;
+                if !self.truth(value, condition.span)? {
+                    return Ok(Flow::Next);
+                }
+                match self.block(body)? {
+                    Flow::Next => {}
+                    Flow::Break => return Ok(Flow::Next),
+                    flow => return Ok(flow),
+                }
+            },
+            StatementKind::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                if let Some(init) = init {
+                    self.statement(init)?;
+                }
+                loop {
+                    if let Some(condition) = condition {
+                        let value = self.evaluate(condition)?;
+                        if !self.truth(value, condition.span)? {
+                            return Ok(Flow::Next);
+                        }
+                    }
+                    match self.block(body)? {
+                        Flow::Next => {}
+                        Flow::Break => return Ok(Flow::Next),
+                        flow => return Ok(flow),
+                    }
+                    if let Some(step) = step {
+                        self.statement(step)?;
+                    }
+                }
+            }
+            StatementKind::If { condition, body } => {
+                let value = self.evaluate(condition)?;
+                match self.truth(value, condition.span)? {
+                    true => self.block(body),
+                    false => Ok(Flow::Next),
+                }
+            }
+            StatementKind::Switch { value, body, cases } => {
+                let value = self.evaluate(value)?;
+                let mut start = None;
+                for case in cases {
+                    if let Some(label) = &case.label {
+                        let label_value = self.evaluate(label)?;
+                        let equal = self.binary(
+                            BinaryOperator::Equal,
+                            value.clone(),
+                            label_value,
+                            case.span,
+                        )?;
+                        if self.truth(equal, case.span)? {
+                            start = Some(case.position);
+                            break;
+                        }
+                    }
+                }
+                let default = cases.iter().find(|case| case.label.is_none());
+                match start.or(default.map(|case| case.position)) {
+                    Some(position) => match self.block_from(body, position)? {
+                        Flow::Break => Ok(Flow::Next),
+                        flow => Ok(flow),
+                    },
+                    None => Ok(Flow::Next),
+                }
+            }
+            StatementKind::Return(value) => match value {
+                Some(expr) => Ok(Flow::Return(Some(self.evaluate(expr)?))),
+                None => Ok(Flow::Return(None)),
+            },
+            StatementKind::Break => Ok(Flow::Break),
+            StatementKind::Call(call) => {
+                self.evaluate(call)?;
+                Ok(Flow::Next)
+            }
+            StatementKind::Block(block) => self.block(block),
+            StatementKind::Declaration(Declaration::Variable(variable)) => {
+                self.local(variable)?;
+                Ok(Flow::Next)
+            }
+            StatementKind::Declaration(_) => Ok(Flow::Next),
+        }
+    }
+
+    /// run a builtin function, its arguments converted to the parameter types
+    fn builtin(&mut self, name: &str, arguments: &[Expression], span: Span) -> Result<Value> {
+        if name == builtins::PRINTF {
+            return self.printf(arguments, span);
+        }
+        let declaration = builtins::declarations()
+            .into_iter()
+            .find(|function| function.name == name)
+            .unwrap();
+        let mut numbers = Vec::new();
+        for (argument, parameter) in arguments.iter().zip(&declaration.parameters) {
+            let value = self.evaluate(argument)?;
+            match self.convert(value, &parameter.data_type, argument.span)? {
+                Value::Number(number) => numbers.push(number),
+                _ => {
+                    return Err(RuntimeError::new(
+                        format!("argument of `{}` is not a number", name),
+                        argument.span,
+                    ))
+                }
+            }
+        }
+        match self.io.call(name, &numbers) {
+            Ok(Some(number)) => Ok(Value::Number(number)),
+            Ok(None) => Ok(Value::Void),
+            Err(message) => Err(RuntimeError::new(message, span)),
+        }
+    }
+
+    fn printf(&mut self, arguments: &[Expression], span: Span) -> Result<Value> {
+        let format = match arguments.first().map(|argument| &argument.kind) {
+            Some(ExpressionKind::StringConstant(format)) => format,
+            _ => {
+                return Err(RuntimeError::new(
+                    String::from("the format of `printf` must be a string literal"),
+                    span,
+                ))
+            }
+        };
+        let pieces = builtins::parse_format(format)
+            .map_err(|message| RuntimeError::new(message, arguments[0].span))?;
+        let mut numbers = Vec::new();
+        for argument in &arguments[1..] {
+            match self.evaluate(argument)? {
+                Value::Number(number) => numbers.push(number),
+                _ => {
+                    return Err(RuntimeError::new(
+                        String::from("argument of `printf` is not a number"),
+                        argument.span,
+                    ))
+                }
+            }
+        }
+        self.io
+            .printf(&pieces, &numbers)
+            .map(Value::Number)
+            .map_err(|message| RuntimeError::new(message, span))
+    }
+
+    /// a local comes into scope at its declaration, with its initialiser
+    /// evaluated each time control passes it
+    fn local(&mut self, variable: &VariableDeclaration) -> Result<()> {
+        let data_type = variable.data_type.clone();
+        let address = match variable.qualifiers.storage {
+            StorageClass::Static => self.statics[&variable.span],
+            StorageClass::Extern => match self.globals.get(&variable.name) {
+                Some(binding) => binding.address,
+                None => {
+                    return Err(RuntimeError::new(
+                        format!("extern variable `{}` is never defined", variable.name),
+                        variable.span,
+                    ))
+                }
+            },
+            StorageClass::Auto => {
+                let address = self.allocate(&data_type, variable.span)?;
+                self.bind(&variable.name, Binding { address, data_type });
+                if let Some(initializer) = &variable.initializer {
+                    self.initialize(address, &variable.data_type, initializer)?;
+                }
+                return Ok(());
+            }
+        };
+        self.bind(&variable.name, Binding { address, data_type });
+        Ok(())
+    }
+
+    /// text of the value stored at an address, `?` for unassigned cells
+    fn describe(&self, address: usize, data_type: &Type) -> String {
+        let cell = |address: usize| match self.memory.get(address) {
+            Some(Value::Number(constant)) => constant.to_string(),
+            Some(Value::Pointer(0, _)) => String::from("NULL"),
+            Some(Value::Pointer(address, _)) => format!("0x{:x}", address),
+            _ => String::from("?"),
+        };
+        self.layout.describe(address, data_type, &cell)
+    }
+}
+
+/// zero of a scalar type, the value of globals without an initialiser
+fn zero_value(data_type: &Type) -> Value {
+    match data_type {
+        Type::Pointer(target) => Value::Pointer(0, (**target).clone()),
+        _ => match Number::zero(data_type) {
+            Some(zero) => Value::Number(zero),
+            None => Value::Uninitialized,
+        },
+    }
+}
+
+fn arithmetic_error(error: ArithmeticError, span: Span) -> RuntimeError {
+    let message = match error {
+        ArithmeticError::DivisionByZero => String::from("division by zero"),
+        ArithmeticError::Overflow(data_type) => {
+            format!("integer overflow in `{}` arithmetic", data_type)
+        }
+        ArithmeticError::OutOfRange(data_type) => {
+            format!("value does not fit in `{}`", data_type)
+        }
+    };
+    RuntimeError::new(message, span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    // run a program on the given input, returning what it printed and how it ended
+    fn run(source: &str, input: &str) -> (String, Result<()>) {
+        run_with(source, input, Limits::default())
+    }
+
+    fn run_with(source: &str, input: &str, limits: Limits) -> (String, Result<()>) {
+        let program = Parser::from_source(source, "format.csv").parse();
+        let mut output = Vec::new();
+        let result = {
+            let mut interpreter =
+                Interpreter::with_io(&program, Box::new(input.as_bytes()), Box::new(&mut output));
+            interpreter.set_limits(limits);
+            interpreter.run()
+        };
+        (String::from_utf8(output).unwrap(), result)
+    }
+
+    const RECURSION: &str = "int Down(int n);
+        void main(){ print_int(Down(read_int())); }
+        int Down(int n){
+            if (n == 0) { return 0; }
+            return 1 + Down(n - 1);
+        }";
+
+    #[test]
+    fn builtins_use_the_given_streams() {
+        let source = "void main(){
+            int a; int b;
+            a = read_int();
+            b = read_int();
+            print_int(a * b);
+            print_char(10);
+            print_float(a / 4.0);
+            print_char(33);
+            printf(\"\\n%d%% %c %f\\n\", b, 65, 1.5);
+        }";
+        let (output, result) = run(source, "6\n  -7\n");
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "-42\n1.500000!\n-7% A 1.500000\n");
+    }
+
+    #[test]
+    fn globals_recursion_and_static_locals() {
+        let source = "int counter;
+            int Fact(int n);
+            int Next();
+            void main(){
+                int first;
+                counter = 5;
+                printf(\"%d\\n\", Fact(counter));
+                first = Next();
+                printf(\"%d %d\\n\", first, Next());
+            }
+            int Fact(int n){
+                if (n == 0) { return 1; }
+                return n * Fact(n - 1);
+            }
+            int Next(){
+                static int calls = 0;
+                calls = calls + 1;
+                counter = counter + calls;
+                return counter;
+            }";
+        let program = Parser::from_source(source, "format.csv").parse();
+        let mut output = Vec::new();
+        let mut interpreter =
+            Interpreter::with_io(&program, Box::new("".as_bytes()), Box::new(&mut output));
+        assert_eq!(interpreter.run(), Ok(()));
+        // the static local keeps its value between calls, set up only once
+        assert_eq!(interpreter.globals(), vec!["int counter = 8"]);
+        assert_eq!(interpreter.main_variables(), vec!["int first = 6"]);
+        drop(interpreter);
+        assert_eq!(String::from_utf8(output).unwrap(), "120\n6 8\n");
+    }
+
+    #[test]
+    fn runs_the_example_program() {
+        let program = Parser::from_source(include_str!("../example1.x"), "format.csv").parse();
+        let mut interpreter = Interpreter::new(&program);
+        assert_eq!(interpreter.run(), Ok(()));
+        // 1.4 squared 7 times, in float
+        assert_eq!(
+            interpreter.main_variables(),
+            vec!["float Value = 5062759600000000000"]
+        );
+    }
+
+    #[test]
+    fn output_before_a_fault_is_kept() {
+        let source = "void main(){
+            print_int(read_int());
+            printf(\"%d %d\\n\", 1);
+        }";
+        let (output, result) = run(source, "5");
+        assert_eq!(output, "5");
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.message,
+            "too few arguments for the format of `printf`"
+        );
+        assert_eq!(error.limit, None);
+
+        let (output, result) = run("void main(){ print_int(read_int()); }", "");
+        assert_eq!(output, "");
+        assert_eq!(
+            result.unwrap_err().message,
+            "`read_int` reached the end of the input"
+        );
+    }
+
+    #[test]
+    fn each_limit_stops_the_run() {
+        let limits = Limits {
+            fuel: None,
+            call_depth: None,
+            memory: None,
+        };
+        let cases = [
+            (
+                "void main(){ while (1) { } }",
+                Limits {
+                    fuel: Some(1000),
+                    ..limits
+                },
+                Limit::Fuel,
+            ),
+            (
+                RECURSION,
+                Limits {
+                    call_depth: Some(20),
+                    ..limits
+                },
+                Limit::CallDepth,
+            ),
+            (
+                "void main(){ int a[100]; a[0] = 1; }",
+                Limits {
+                    memory: Some(50),
+                    ..limits
+                },
+                Limit::Memory,
+            ),
+        ];
+        for (source, limits, limit) in cases {
+            let (_, result) = run_with(source, "100", limits);
+            assert_eq!(result.unwrap_err().limit, Some(limit), "{}", source);
+        }
+        let (output, result) = run_with(RECURSION, "19", Limits::default());
+        assert_eq!((output.as_str(), result), ("19", Ok(())));
+    }
+
+    #[test]
+    fn default_limits_stop_an_endless_loop() {
+        let (_, result) = run_with("void main(){ while (1 == 1) { } }", "", Limits::default());
+        let error = result.unwrap_err();
+        assert_eq!(error.limit, Some(Limit::Fuel));
+        assert_eq!(error.message, "fuel limit of 10000000 steps exceeded");
+    }
+
+    #[test]
+    fn unlimited_depth_stops_before_the_stack_overflows() {
+        let limits = Limits {
+            call_depth: None,
+            ..Limits::default()
+        };
+        assert_eq!(limits.for_interpreter().call_depth, Some(MAX_CALL_DEPTH));
+        let huge = Limits {
+            call_depth: Some(usize::MAX),
+            ..limits
+        };
+        assert_eq!(huge.stack_size(), limits.stack_size());
+
+        let input = (MAX_CALL_DEPTH * 2).to_string();
+        let (_, result) = std::thread::Builder::new()
+            .stack_size(limits.stack_size())
+            .spawn(move || run_with(RECURSION, &input, limits))
+            .unwrap()
+            .join()
+            .unwrap();
+        let error = result.unwrap_err();
+        assert_eq!(error.limit, Some(Limit::CallDepth));
+        assert_eq!(error.message, "call depth limit of 10000 calls exceeded");
+    }
+}
@@ -0,0 +1,276 @@
+use crate::ast::*;
+use crate::builtins;
+use crate::value::{int_literal_type, usual_arithmetic_conversion};
+
+use std::collections::HashMap;
+
+const INT: Type = Type::Integer {
+    kind: IntKind::Int,
+    unsigned: false,
+};
+
+/// How the values of a program are laid out in memory cells, and the static
+/// types of its expressions
+///
+/// Every scalar takes one cell: arrays and structs take consecutive cells,
+/// element after element and member after member. The interpreter and the
+/// bytecode compiler share this layout, so both place variables alike.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    structs: HashMap<String, Vec<Member>>,
+    /// return type of each function, the builtins included
+    functions: HashMap<String, Type>,
+}
+
+impl Layout {
+    /// Collect the structs and functions of a program, including the structs
+    /// declared inside function bodies
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - syntax tree from the parser
+    ///
+    pub fn new(program: &Program) -> Layout {
+        let mut layout = Layout::default();
+        for declaration in &program.declarations {
+            layout.collect_struct(declaration);
+        }
+        for function in std::iter::once(&program.main).chain(&program.functions) {
+            layout.collect_structs(&function.body);
+            layout.functions.insert(
+                function.declaration.name.clone(),
+                function.declaration.return_type.clone(),
+            );
+        }
+        for declaration in builtins::declarations() {
+            layout
+                .functions
+                .insert(declaration.name, declaration.return_type);
+        }
+        layout
+    }
+
+    /// A layout knowing only the given structs, for running compiled code
+    /// without its syntax tree
+    pub fn from_structs(structs: Vec<(String, Vec<Member>)>) -> Layout {
+        Layout {
+            structs: structs.into_iter().collect(),
+            functions: HashMap::new(),
+        }
+    }
+
+    /// every struct with its members, sorted by name
+    pub fn structs(&self) -> Vec<(String, Vec<Member>)> {
+        let mut structs: Vec<_> = self
+            .structs
+            .iter()
+            .map(|(name, members)| (name.clone(), members.clone()))
+            .collect();
+        structs.sort_by(|a, b| a.0.cmp(&b.0));
+        structs
+    }
+
+    fn collect_struct(&mut self, declaration: &Declaration) {
+        if let Declaration::Struct(declaration) = declaration {
+            self.structs
+                .entry(declaration.name.clone())
+                .or_insert_with(|| declaration.members.clone());
+        }
+    }
+
+    /// struct declarations inside function bodies, X has no nested struct names
+    /// that could clash with global ones in a valid program
+    fn collect_structs(&mut self, block: &Block) {
+        for statement in &block.statements {
+            match &statement.kind {
+                StatementKind::Declaration(declaration) => self.collect_struct(declaration),
+                StatementKind::Block(body)
+                | StatementKind::If { body, .. }
+                | StatementKind::While { body, .. }
+                | StatementKind::For { body, .. }
+                | StatementKind::Switch { body, .. } => self.collect_structs(body),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn members(&self, name: &str) -> Option<Vec<Member>> {
+        self.structs.get(name).cloned()
+    }
+
+    /// number of cells taken by a value of the type
+    pub fn cells(&self, data_type: &Type) -> usize {
+        match data_type {
+            Type::Void => 0,
+            Type::Array(element, size) => self.cells(element) * size.unwrap_or(0),
+            Type::Struct(name) => self.structs.get(name).map_or(0, |members| {
+                members
+                    .iter()
+                    .map(|member| self.cells(&member.data_type))
+                    .sum()
+            }),
+            _ => 1,
+        }
+    }
+
+    /// cell offset and type of a struct member
+    pub fn member(&self, name: &str, member: &str) -> Option<(usize, Type)> {
+        let mut offset = 0;
+        for m in self.structs.get(name)? {
+            if m.name == member {
+                return Some((offset, m.data_type.clone()));
+            }
+            offset += self.cells(&m.data_type);
+        }
+        None
+    }
+
+    /// type of each cell of a value of the type, in memory order
+    pub fn scalars(&self, data_type: &Type) -> Vec<Type> {
+        match data_type {
+            Type::Array(element, size) => {
+                let cells = self.scalars(element);
+                (0..size.unwrap_or(0))
+                    .flat_map(|_| cells.iter().cloned())
+                    .collect()
+            }
+            Type::Struct(name) => self
+                .structs
+                .get(name)
+                .into_iter()
+                .flatten()
+                .flat_map(|member| self.scalars(&member.data_type))
+                .collect(),
+            Type::Void => Vec::new(),
+            scalar => vec![scalar.clone()],
+        }
+    }
+
+    /// size in bytes for `sizeof`
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - a `sizeof` expression
+    /// * `variable` - declared type of a variable in scope
+    ///
+    pub fn size_of(
+        &self,
+        expr: &Expression,
+        variable: &dyn Fn(&str) -> Option<Type>,
+    ) -> Option<usize> {
+        let members = |name: &str| self.members(name);
+        match &expr.kind {
+            ExpressionKind::SizeOfType(data_type) => data_type.size(&members),
+            ExpressionKind::SizeOfExpression(operand) => {
+                self.type_of(operand, variable)?.size(&members)
+            }
+            _ => None,
+        }
+    }
+
+    /// type of an expression without evaluating it, arrays not decayed
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - any expression
+    /// * `variable` - declared type of a variable in scope
+    ///
+    pub fn type_of(
+        &self,
+        expr: &Expression,
+        variable: &dyn Fn(&str) -> Option<Type>,
+    ) -> Option<Type> {
+        match &expr.kind {
+            ExpressionKind::IntConstant(n) => Some(int_literal_type(*n)),
+            ExpressionKind::FloatConstant(_) => Some(Type::Double),
+            ExpressionKind::EnumConstant { .. } => Some(INT),
+            ExpressionKind::StringConstant(_) => Some(Type::Pointer(Box::new(Type::Integer {
+                kind: IntKind::Char,
+                unsigned: false,
+            }))),
+            ExpressionKind::Variable(name) => variable(name),
+            ExpressionKind::Index { array, .. } => {
+                self.type_of(array, variable)?.pointee().cloned()
+            }
+            ExpressionKind::Member { base, member } => match self.type_of(base, variable)? {
+                Type::Struct(name) => self.member(&name, member).map(|(_, t)| t),
+                _ => None,
+            },
+            ExpressionKind::Call { name, .. } => self.functions.get(name).cloned(),
+            ExpressionKind::Unary { operator, operand } => {
+                let operand = self.type_of(operand, variable)?;
+                match operator {
+                    UnaryOperator::Negate => Some(usual_arithmetic_conversion(&operand, &operand)),
+                    UnaryOperator::Dereference => operand.pointee().cloned(),
+                    UnaryOperator::AddressOf => Some(Type::Pointer(Box::new(operand))),
+                }
+            }
+            ExpressionKind::Cast { data_type, .. } => Some(data_type.clone()),
+            ExpressionKind::SizeOfType(_) | ExpressionKind::SizeOfExpression(_) => {
+                Some(Type::Integer {
+                    kind: IntKind::Long,
+                    unsigned: true,
+                })
+            }
+            ExpressionKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                if operator.is_relational() {
+                    return Some(INT);
+                }
+                let left = self.type_of(left, variable)?;
+                let right = self.type_of(right, variable)?;
+                match (left.pointee(), right.pointee()) {
+                    (Some(_), Some(_)) => Some(Type::Integer {
+                        kind: IntKind::Long,
+                        unsigned: false,
+                    }),
+                    (Some(target), None) | (None, Some(target)) => {
+                        Some(Type::Pointer(Box::new(target.clone())))
+                    }
+                    (None, None) => Some(usual_arithmetic_conversion(&left, &right)),
+                }
+            }
+        }
+    }
+
+    /// text of the value of a variable, `{...}` around the elements of arrays
+    /// and the members of structs
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - first cell of the variable
+    /// * `data_type` - declared type of the variable
+    /// * `cell` - text of the scalar held by a cell
+    ///
+    pub fn describe(
+        &self,
+        address: usize,
+        data_type: &Type,
+        cell: &dyn Fn(usize) -> String,
+    ) -> String {
+        let list = |items: Vec<String>| format!("{{{}}}", items.join(", "));
+        match data_type {
+            Type::Array(element, size) => {
+                let stride = self.cells(element);
+                list(
+                    (0..size.unwrap_or(0))
+                        .map(|i| self.describe(address + i * stride, element, cell))
+                        .collect(),
+                )
+            }
+            Type::Struct(name) => {
+                let mut offset = 0;
+                let mut items = Vec::new();
+                for member in self.members(name).unwrap_or_default() {
+                    items.push(self.describe(address + offset, &member.data_type, cell));
+                    offset += self.cells(&member.data_type);
+                }
+                list(items)
+            }
+            _ => cell(address),
+        }
+    }
+}
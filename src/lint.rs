@@ -0,0 +1,649 @@
+use crate::ast::*;
+use crate::cfg::{Cfg, Instruction};
+use crate::dataflow;
+use crate::diagnostic::Diagnostic;
+use crate::token::Span;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+
+/// What to do when a lint rule matches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn from_text(s: &str) -> Option<Level> {
+        match s {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+
+    /// level set by a command line flag: `-A`, `-W` or `-D`
+    pub fn from_flag(s: &str) -> Option<Level> {
+        match s {
+            "-A" => Some(Level::Allow),
+            "-W" => Some(Level::Warn),
+            "-D" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// Named lint rules with the level they have unless configured otherwise
+pub const RULES: [(&str, Level); 7] = [
+    ("unused-variable", Level::Warn),
+    ("unused-parameter", Level::Warn),
+    ("unused-function", Level::Warn),
+    ("shadowing", Level::Warn),
+    ("magic-number", Level::Allow),
+    ("naming-convention", Level::Warn),
+    ("empty-loop-body", Level::Warn),
+];
+
+/// Integer and float literals that are not magic numbers
+const PLAIN_NUMBERS: [f64; 3] = [0.0, 1.0, 2.0];
+
+/// Level of every lint rule
+#[derive(Clone, Debug)]
+pub struct LintConfig {
+    levels: HashMap<String, Level>,
+}
+
+impl LintConfig {
+    /// Every rule at its default level
+    pub fn new() -> LintConfig {
+        LintConfig {
+            levels: RULES
+                .iter()
+                .map(|(rule, level)| (String::from(*rule), *level))
+                .collect(),
+        }
+    }
+
+    /// Read the levels from a project file; rules it does not list, or all of
+    /// them when there is no such file, keep their default level
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - path to the config csv file, `lint.csv` in this project
+    ///
+    pub fn load(f: &str) -> LintConfig {
+        let mut config = LintConfig::new();
+        if let Ok(file) = File::open(f) {
+            let reader = BufReader::new(file);
+            // the first line names the columns: RULE,LEVEL
+            for line in reader.lines().skip(1) {
+                let line = line.unwrap();
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let fields: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+                match fields.get(1).and_then(|level| Level::from_text(level)) {
+                    Some(level) => config.set(fields[0], level),
+                    None => panic!("invalid lint level in {}: {}", f, line),
+                }
+            }
+        }
+        config
+    }
+
+    pub fn set(&mut self, rule: &str, level: Level) {
+        if !self.levels.contains_key(rule) {
+            panic!("unknown lint rule {}", rule);
+        }
+        self.levels.insert(String::from(rule), level);
+    }
+
+    pub fn level(&self, rule: &str) -> Level {
+        self.levels[rule]
+    }
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig::new()
+    }
+}
+
+/// Run every lint rule that is not allowed over the program
+///
+/// # Arguments
+///
+/// * `program` - syntax tree from the parser
+/// * `config` - level of each rule
+///
+pub fn check(program: &Program, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        config,
+        scopes: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    linter.unused_variables(program);
+    linter.unused_functions(program);
+    linter.shadowing(program);
+    linter.magic_numbers(program);
+    linter.naming_convention(program);
+    linter.empty_loop_bodies(program);
+    linter.diagnostics
+}
+
+/// Letter case an identifier is written in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Case {
+    /// `val`, `test_float`
+    Lower,
+    /// `MAX_SIZE`
+    Upper,
+    /// `testFloat`
+    Camel,
+    /// `TestFloat`
+    Pascal,
+}
+
+impl Case {
+    // None for names such as `x` or `N` that fit several cases
+    fn of(name: &str) -> Option<Case> {
+        let letters: Vec<char> = name.chars().filter(|c| c.is_alphabetic()).collect();
+        if letters.len() < 2 {
+            return None;
+        }
+        let has_lower = letters.iter().any(|c| c.is_lowercase());
+        let has_upper = letters.iter().any(|c| c.is_uppercase());
+        let starts_upper = letters[0].is_uppercase();
+        match (has_lower, has_upper) {
+            (true, false) => Some(Case::Lower),
+            (false, true) => Some(Case::Upper),
+            _ if starts_upper => Some(Case::Pascal),
+            _ => Some(Case::Camel),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Case::Lower => "lower_case",
+            Case::Upper => "UPPER_CASE",
+            Case::Camel => "camelCase",
+            Case::Pascal => "PascalCase",
+        }
+    }
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    // names declared in each open scope while looking for shadowing
+    scopes: Vec<HashMap<String, Span>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: &str, message: String, span: Span) {
+        let message = format!("{} [{}]", message, rule);
+        match self.config.level(rule) {
+            Level::Allow => {}
+            Level::Warn => self.diagnostics.push(Diagnostic::warning(message, span)),
+            Level::Deny => self.diagnostics.push(Diagnostic::error(message, span)),
+        }
+    }
+
+    fn functions(program: &Program) -> impl Iterator<Item = &Function> {
+        std::iter::once(&program.main).chain(&program.functions)
+    }
+
+    // locals and parameters never mentioned but as the whole target of an
+    // assignment; the stores to a variable the dataflow analysis tracks are
+    // already reported there as never read
+    fn unused_variables(&mut self, program: &Program) {
+        for function in Linter::functions(program) {
+            let cfg = Cfg::build(function);
+            let tracked = dataflow::tracked_variables(&cfg);
+            let mut stored_only = HashSet::new();
+            for block in &cfg.blocks {
+                for instruction in &block.instructions {
+                    if let Instruction::Assign { targets, .. } = instruction {
+                        for target in targets {
+                            if let ExpressionKind::Variable(_) = target.kind {
+                                stored_only.insert(target.span);
+                            }
+                        }
+                    }
+                }
+            }
+            let (stored, used): (Vec<_>, Vec<_>) = cfg
+                .resolutions
+                .iter()
+                .partition(|(span, _)| stored_only.contains(span));
+            let stored: HashSet<usize> = stored.into_iter().map(|(_, v)| *v).collect();
+            let used: HashSet<usize> = used.into_iter().map(|(_, v)| *v).collect();
+            for (id, variable) in cfg.variables.iter().enumerate() {
+                if used.contains(&id) || (stored.contains(&id) && tracked[id]) {
+                    continue;
+                }
+                match variable.is_parameter {
+                    true => self.report(
+                        "unused-parameter",
+                        format!("unused parameter `{}`", variable.name),
+                        variable.span,
+                    ),
+                    false if stored.contains(&id) => self.report(
+                        "unused-variable",
+                        format!("variable `{}` is assigned but never used", variable.name),
+                        variable.span,
+                    ),
+                    false => self.report(
+                        "unused-variable",
+                        format!("unused variable `{}`", variable.name),
+                        variable.span,
+                    ),
+                }
+            }
+        }
+    }
+
+    // functions other than main that no other function calls
+    fn unused_functions(&mut self, program: &Program) {
+        let mut called = HashSet::new();
+        for function in Linter::functions(program) {
+            let name = &function.declaration.name;
+            visit_block(&function.body, &mut |expr| {
+                if let ExpressionKind::Call { name: callee, .. } = &expr.kind {
+                    // calling itself does not make a function used
+                    if callee != name {
+                        called.insert(callee.clone());
+                    }
+                }
+            });
+        }
+
+        let mut reported = HashSet::new();
+        let definitions = program.functions.iter().map(|f| &f.declaration);
+        let prototypes = program.declarations.iter().filter_map(|d| match d {
+            Declaration::Function(function) => Some(function),
+            _ => None,
+        });
+        for declaration in definitions.chain(prototypes) {
+            if !called.contains(&declaration.name) && reported.insert(declaration.name.clone()) {
+                self.report(
+                    "unused-function",
+                    format!("function `{}` is never called", declaration.name),
+                    declaration.span,
+                );
+            }
+        }
+    }
+
+    fn shadowing(&mut self, program: &Program) {
+        self.scopes = vec![HashMap::new()];
+        for declaration in &program.declarations {
+            self.declare_names(declaration);
+        }
+        for function in Linter::functions(program) {
+            self.scopes[0]
+                .entry(function.declaration.name.clone())
+                .or_insert(function.declaration.span);
+            // parameters share the scope of the outermost block of the body
+            self.scopes.push(HashMap::new());
+            for parameter in &function.declaration.parameters {
+                self.declare_shadowing(&parameter.name, parameter.span);
+            }
+            self.shadowing_statements(&function.body.statements);
+            self.scopes.pop();
+        }
+    }
+
+    fn shadowing_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match &statement.kind {
+                StatementKind::Declaration(declaration) => self.declare_names(declaration),
+                StatementKind::While { body, .. }
+                | StatementKind::For { body, .. }
+                | StatementKind::If { body, .. }
+                | StatementKind::Switch { body, .. }
+                | StatementKind::Block(body) => {
+                    self.scopes.push(HashMap::new());
+                    self.shadowing_statements(&body.statements);
+                    self.scopes.pop();
+                }
+                StatementKind::Assignment { .. }
+                | StatementKind::Call(_)
+                | StatementKind::Return(_)
+                | StatementKind::Break => {}
+            }
+        }
+    }
+
+    fn declare_names(&mut self, declaration: &Declaration) {
+        match declaration {
+            Declaration::Variable(variable) => {
+                self.declare_shadowing(&variable.name, variable.span)
+            }
+            Declaration::Function(function) => {
+                self.declare_shadowing(&function.name, function.span)
+            }
+            Declaration::Typedef(typedef) => self.declare_shadowing(&typedef.name, typedef.span),
+            Declaration::Enum(declaration) => {
+                for enumerator in &declaration.enumerators {
+                    self.declare_shadowing(&enumerator.name, enumerator.span);
+                }
+            }
+            Declaration::Struct(_) => {}
+        }
+    }
+
+    // add a name to the innermost scope, reporting an outer one it hides
+    fn declare_shadowing(&mut self, name: &str, span: Span) {
+        let (current, outer) = self.scopes.split_last_mut().unwrap();
+        if !current.contains_key(name) {
+            if let Some(hidden) = outer.iter().rev().find_map(|scope| scope.get(name)) {
+                let message = format!("`{}` shadows the declaration at line {}", name, hidden.line);
+                current.insert(String::from(name), span);
+                self.report("shadowing", message, span);
+                return;
+            }
+        }
+        current.insert(String::from(name), span);
+    }
+
+    // literals inside function bodies, other than the initialisers of const variables
+    fn magic_numbers(&mut self, program: &Program) {
+        let mut found = Vec::new();
+        for function in Linter::functions(program) {
+            let mut statements: Vec<&Statement> = function.body.statements.iter().collect();
+            while let Some(statement) = statements.pop() {
+                let mut check = |expr: &Expression| {
+                    expr.visit(&mut |e| match &e.kind {
+                        ExpressionKind::IntConstant(n) if !PLAIN_NUMBERS.contains(&(*n as f64)) => {
+                            found.push((n.to_string(), e.span))
+                        }
+                        ExpressionKind::FloatConstant(x) if !PLAIN_NUMBERS.contains(x) => {
+                            found.push((x.to_string(), e.span))
+                        }
+                        _ => {}
+                    })
+                };
+                match &statement.kind {
+                    StatementKind::Assignment { targets, value } => {
+                        targets.iter().for_each(&mut check);
+                        check(value);
+                    }
+                    StatementKind::While { condition, body }
+                    | StatementKind::If { condition, body } => {
+                        check(condition);
+                        statements.extend(&body.statements);
+                    }
+                    StatementKind::For {
+                        init,
+                        condition,
+                        step,
+                        body,
+                    } => {
+                        condition.iter().for_each(&mut check);
+                        statements.extend(init.iter().chain(step).map(|s| s.as_ref()));
+                        statements.extend(&body.statements);
+                    }
+                    StatementKind::Switch { value, body, cases } => {
+                        check(value);
+                        cases
+                            .iter()
+                            .filter_map(|case| case.label.as_ref())
+                            .for_each(&mut check);
+                        statements.extend(&body.statements);
+                    }
+                    StatementKind::Return(value) => value.iter().for_each(check),
+                    StatementKind::Call(call) => check(call),
+                    StatementKind::Declaration(Declaration::Variable(variable))
+                        if !variable.qualifiers.is_const =>
+                    {
+                        if let Some(initializer) = &variable.initializer {
+                            visit_initializer(initializer, &mut check);
+                        }
+                    }
+                    StatementKind::Declaration(_) | StatementKind::Break => {}
+                    StatementKind::Block(block) => statements.extend(&block.statements),
+                }
+            }
+        }
+        found.sort_by_key(|(_, span)| (span.line, span.column));
+        for (number, span) in found {
+            self.report(
+                "magic-number",
+                format!("magic number {}, consider a named constant", number),
+                span,
+            );
+        }
+    }
+
+    // variable names, then function names, should all be written in the case
+    // most of them use; the first name decides a tie
+    fn naming_convention(&mut self, program: &Program) {
+        let mut variables = Vec::new();
+        for declaration in &program.declarations {
+            if let Declaration::Variable(variable) = declaration {
+                variables.push((variable.name.clone(), variable.span));
+            }
+        }
+        for function in Linter::functions(program) {
+            let cfg = Cfg::build(function);
+            variables.extend(cfg.variables.iter().map(|v| (v.name.clone(), v.span)));
+        }
+        variables.sort_by_key(|(_, span)| (span.line, span.column));
+        self.check_case("variable", &variables);
+
+        let functions: Vec<(String, Span)> = program
+            .functions
+            .iter()
+            .map(|f| (f.declaration.name.clone(), f.declaration.span))
+            .collect();
+        self.check_case("function", &functions);
+    }
+
+    fn check_case(&mut self, what: &str, names: &[(String, Span)]) {
+        let cases: Vec<Option<Case>> = names.iter().map(|(name, _)| Case::of(name)).collect();
+        let mut counts: Vec<(Case, usize)> = Vec::new();
+        for case in cases.iter().flatten() {
+            match counts.iter_mut().find(|(c, _)| c == case) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((*case, 1)),
+            }
+        }
+        // max_by_key keeps the last of equal counts, so look from the end
+        let dominant = match counts.iter().rev().max_by_key(|(_, count)| *count) {
+            Some((case, _)) => *case,
+            None => return,
+        };
+        for ((name, span), case) in names.iter().zip(cases) {
+            if let Some(case) = case {
+                if case != dominant {
+                    let message = format!(
+                        "{} `{}` is {}, but most {} names are {}",
+                        what,
+                        name,
+                        case.as_str(),
+                        what,
+                        dominant.as_str()
+                    );
+                    self.report("naming-convention", message, *span);
+                }
+            }
+        }
+    }
+
+    fn empty_loop_bodies(&mut self, program: &Program) {
+        let mut found = Vec::new();
+        for function in Linter::functions(program) {
+            let mut statements: Vec<&Statement> = function.body.statements.iter().collect();
+            while let Some(statement) = statements.pop() {
+                match &statement.kind {
+                    StatementKind::While { body, .. } | StatementKind::For { body, .. } => {
+                        if body.statements.is_empty() {
+                            found.push(statement.span);
+                        }
+                        statements.extend(&body.statements);
+                    }
+                    StatementKind::If { body, .. }
+                    | StatementKind::Switch { body, .. }
+                    | StatementKind::Block(body) => statements.extend(&body.statements),
+                    _ => {}
+                }
+            }
+        }
+        found.sort_by_key(|span| (span.line, span.column));
+        for span in found {
+            self.report("empty-loop-body", String::from("empty loop body"), span);
+        }
+    }
+}
+
+// call `f` on every expression of the block, nested blocks included
+fn visit_block(block: &Block, f: &mut dyn FnMut(&Expression)) {
+    for statement in &block.statements {
+        visit_statement(statement, f);
+    }
+}
+
+fn visit_statement(statement: &Statement, f: &mut dyn FnMut(&Expression)) {
+    match &statement.kind {
+        StatementKind::Assignment { targets, value } => {
+            for target in targets {
+                target.visit(f);
+            }
+            value.visit(f);
+        }
+        StatementKind::While { condition, body } | StatementKind::If { condition, body } => {
+            condition.visit(f);
+            visit_block(body, f);
+        }
+        StatementKind::For {
+            init,
+            condition,
+            step,
+            body,
+        } => {
+            if let Some(init) = init {
+                visit_statement(init, f);
+            }
+            if let Some(condition) = condition {
+                condition.visit(f);
+            }
+            if let Some(step) = step {
+                visit_statement(step, f);
+            }
+            visit_block(body, f);
+        }
+        StatementKind::Switch { value, body, cases } => {
+            value.visit(f);
+            for label in cases.iter().filter_map(|case| case.label.as_ref()) {
+                label.visit(f);
+            }
+            visit_block(body, f);
+        }
+        StatementKind::Return(Some(value)) | StatementKind::Call(value) => value.visit(f),
+        StatementKind::Return(None) | StatementKind::Break => {}
+        StatementKind::Declaration(Declaration::Variable(variable)) => {
+            if let Some(initializer) = &variable.initializer {
+                visit_initializer(initializer, f);
+            }
+        }
+        StatementKind::Declaration(_) => {}
+        StatementKind::Block(block) => visit_block(block, f),
+    }
+}
+
+fn visit_initializer(initializer: &Initializer, f: &mut dyn FnMut(&Expression)) {
+    match initializer {
+        Initializer::Expression(expr) => expr.visit(f),
+        Initializer::List(items, _) => {
+            for item in items {
+                visit_initializer(item, f);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Severity;
+    use crate::parser::Parser;
+
+    // each diagnostic of the linter with its severity, line and column
+    fn diagnostics(source: &str, config: &LintConfig) -> Vec<(Severity, String, i32, i32)> {
+        let program = Parser::from_source(source, "format.csv").parse();
+        check(&program, config)
+            .into_iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.severity,
+                    diagnostic.message,
+                    diagnostic.span.line,
+                    diagnostic.span.column,
+                )
+            })
+            .collect()
+    }
+
+    const PROGRAM: &str = "int Unused(int n);
+        void main(){
+            int x;
+            int total_sum;
+            int item_count;
+            int maxValue;
+            x = 42;
+            total_sum = x; item_count = x; maxValue = x;
+            print_int(total_sum + item_count + maxValue);
+            { int x; x = 1; print_int(x); }
+            while (x > 100) { }
+            print_int(x);
+        }
+        int Unused(int n){ return 0; }";
+
+    #[test]
+    fn allowed_rules_stay_quiet() {
+        let clean = "int Twice(int n);
+            void main(){ int total; total = Twice(1); print_int(total); }
+            int Twice(int n){ return n * 2; }";
+        assert_eq!(diagnostics(clean, &LintConfig::new()), vec![]);
+
+        let mut config = LintConfig::new();
+        for (rule, _) in RULES {
+            config.set(rule, Level::Allow);
+        }
+        assert_eq!(diagnostics(PROGRAM, &config), vec![]);
+    }
+
+    #[test]
+    fn each_rule_reports_at_its_level() {
+        use Severity::*;
+        let mut config = LintConfig::new();
+        config.set("magic-number", Level::Deny);
+        let expected = vec![
+            (Warning, "unused parameter `n` [unused-parameter]", 14, 24),
+            (Warning, "function `Unused` is never called [unused-function]", 14, 13),
+            (Warning, "`x` shadows the declaration at line 3 [shadowing]", 10, 19),
+            (Error, "magic number 42, consider a named constant [magic-number]", 7, 17),
+            (Error, "magic number 100, consider a named constant [magic-number]", 11, 24),
+            (
+                Warning,
+                "variable `maxValue` is camelCase, but most variable names are lower_case [naming-convention]",
+                6,
+                17,
+            ),
+            (Warning, "empty loop body [empty-loop-body]", 11, 13),
+        ];
+        let expected: Vec<(Severity, String, i32, i32)> = expected
+            .into_iter()
+            .map(|(severity, message, line, column)| {
+                (severity, String::from(message), line, column)
+            })
+            .collect();
+        assert_eq!(diagnostics(PROGRAM, &config), expected);
+    }
+}
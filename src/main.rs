@@ -1,20 +1,293 @@
-#![cfg_attr(debug_assertions, allow(dead_code, unused_imports))]
-
+mod ast;
+mod builtins;
+mod bytecode;
+mod cfg;
 mod character_stream;
+mod compiler;
+mod const_eval;
+mod control_flow;
+mod dataflow;
+mod debugger;
+mod diagnostic;
+mod interpreter;
+mod layout;
+mod lint;
+mod object;
+mod optimizer;
 mod parser;
 mod prettifier;
+mod repl;
 mod scanner;
+mod semantic;
+mod symbol_table;
 mod token;
+mod type_checker;
+mod value;
+mod vm;
 use parser::*;
 
-use prettifier::*;
-
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
 
 fn main() {
 	let args: Vec<String> = env::args().collect();
+	// exec OBJECT: run a file written by compile, without parsing the source
+	if args[1] == "exec" {
+		let mut limits = interpreter::Limits::default();
+		let mut options = args.iter().skip(3);
+		while let Some(arg) = options.next() {
+			if !limit_option(arg, &mut options, &mut limits) {
+				panic!("unknown option {}", arg);
+			}
+		}
+		exec(&args[2], limits);
+		return;
+	}
+	// repl: read declarations, statements and expressions from the keyboard
+	if args[1] == "repl" {
+		let mut limits = interpreter::Limits::default();
+		let mut options = args.iter().skip(2);
+		while let Some(arg) = options.next() {
+			if !limit_option(arg, &mut options, &mut limits) {
+				panic!("unknown option {}", arg);
+			}
+		}
+		// every input runs on the interpreter, as deep as run goes
+		thread::Builder::new()
+			.stack_size(limits.stack_size())
+			.spawn(move || repl::run(limits))
+			.unwrap_or_else(|error| {
+				eprintln!("cannot start the REPL: {}", error);
+				process::exit(1);
+			})
+			.join()
+			.unwrap();
+		return;
+	}
 	let mut parser = Parser::new(&args[2], "format.csv");
 
-	parser.parse();
-	println!("{}", parser.to_xhtml());
+	// -A, -W or -D RULE after the file override the levels of lint.csv
+	let mut lint_config = lint::LintConfig::load("lint.csv");
+	// --no-optimize and --dump-optimization change how bytecode is compiled,
+	// --fuel, --max-call-depth and --max-memory N bound the resources of a run
+	let mut limits = interpreter::Limits::default();
+	let mut optimize = true;
+	let mut dump_optimization = false;
+	let mut operands = Vec::new();
+	let mut options = args.iter().skip(3);
+	while let Some(arg) = options.next() {
+		match lint::Level::from_flag(arg) {
+			Some(level) => match options.next() {
+				Some(rule) => lint_config.set(rule, level),
+				None => panic!("missing lint rule after {}", arg),
+			},
+			None if arg == "--no-optimize" => optimize = false,
+			None if arg == "--dump-optimization" => dump_optimization = true,
+			None if limit_option(arg, &mut options, &mut limits) => {}
+			None => operands.push(arg.clone()),
+		}
+	}
+	let compile = |program: &ast::Program| {
+		let mut module = compiler::compile(program);
+		if optimize {
+			if dump_optimization {
+				eprintln!("before optimization:\n{}", module.disassemble());
+			}
+			optimizer::optimize(&mut module);
+			if dump_optimization {
+				eprintln!("after optimization:\n{}", module.disassemble());
+			}
+		}
+		module
+	};
+
+	let program = match parser.try_parse() {
+		Ok(program) => program,
+		Err(diagnostic) => {
+			eprintln!("{}", diagnostic.render(&args[2]));
+			process::exit(1);
+		}
+	};
+	let mut diagnostics = semantic::analyze(&program);
+	diagnostics.extend(type_checker::check(&program));
+	diagnostics.extend(control_flow::analyze(&program));
+	diagnostics.extend(dataflow::analyze(&program));
+	diagnostics.extend(const_eval::analyze(&program));
+	diagnostics.extend(lint::check(&program, &lint_config));
+	let failed = diagnostics.iter().any(|diagnostic| diagnostic.is_error());
+	for diagnostic in diagnostics {
+		eprintln!("{}", diagnostic.render(&args[2]));
+	}
+	match args[1].as_str() {
+		// cfg FILE [FUNCTION]: control flow graphs in Graphviz DOT
+		"cfg" => {
+			let cfgs = cfg::build_program(&program);
+			match operands.first() {
+				Some(name) => match cfgs.iter().find(|cfg| &cfg.name == name) {
+					Some(cfg) => print!("{}", cfg.to_dot()),
+					None => eprintln!("no function named {}", name),
+				},
+				None => print!("{}", cfg::program_to_dot(&cfgs)),
+			}
+		}
+		// run FILE: execute main, then show the variables it left behind
+		"run" => {
+			if failed {
+				process::exit(1);
+			}
+			// X calls nest Rust calls, so the interpreter gets a stack deep enough for its limit
+			let result = thread::scope(|scope| {
+				thread::Builder::new()
+					.stack_size(limits.stack_size())
+					.spawn_scoped(scope, || {
+						let mut interpreter = interpreter::Interpreter::new(&program);
+						interpreter.set_limits(limits);
+						interpreter.run().map(|()| interpreter.main_variables())
+					})
+					.unwrap_or_else(|error| {
+						eprintln!("cannot start the interpreter: {}", error);
+						process::exit(1);
+					})
+					.join()
+					.unwrap()
+			});
+			match result {
+				Ok(variables) => {
+					for variable in variables {
+						println!("{}", variable);
+					}
+				}
+				Err(error) => {
+					let source = fs::read_to_string(&args[2]).ok();
+					eprintln!("{}", error.render(&args[2], source.as_deref()));
+					process::exit(1);
+				}
+			}
+		}
+		// debug FILE: run like run, stopping at breakpoints and steps to take commands
+		"debug" => {
+			if failed {
+				process::exit(1);
+			}
+			let source = fs::read_to_string(&args[2]).unwrap_or_default();
+			let result = thread::scope(|scope| {
+				thread::Builder::new()
+					.stack_size(limits.stack_size())
+					.spawn_scoped(scope, || debugger::debug(&program, &parser, &args[2], &source, limits))
+					.unwrap_or_else(|error| {
+						eprintln!("cannot start the debugger: {}", error);
+						process::exit(1);
+					})
+					.join()
+					.unwrap()
+			});
+			match result {
+				Ok(variables) => {
+					for variable in variables {
+						println!("{}", variable);
+					}
+				}
+				Err(error) => {
+					eprintln!("{}", error.render(&args[2], Some(&source)));
+					process::exit(1);
+				}
+			}
+		}
+		// vm FILE: compile to bytecode, then run it like run
+		"vm" => {
+			if failed {
+				process::exit(1);
+			}
+			run_module(&compile(&program), &args[2], limits);
+		}
+		// compile FILE [OUT]: write the bytecode to an object file, FILE with the extension .xo by default
+		"compile" => {
+			if failed {
+				process::exit(1);
+			}
+			let module = compile(&program);
+			let path = match operands.first() {
+				Some(path) => PathBuf::from(path),
+				None => Path::new(&args[2]).with_extension("xo"),
+			};
+			if let Err(error) = fs::write(&path, object::save(&module, &args[2])) {
+				eprintln!("cannot write {}: {}", path.display(), error);
+				process::exit(1);
+			}
+		}
+		// disasm FILE: bytecode listing of every function
+		"disasm" => {
+			if failed {
+				process::exit(1);
+			}
+			print!("{}", compile(&program).disassemble());
+		}
+		_ => println!("{}", parser.to_xhtml()),
+	}
+
+	if failed {
+		process::exit(1);
+	}
+}
+
+fn exec(path: &str, limits: interpreter::Limits) {
+	let loaded = fs::read(path)
+		.map_err(|error| error.to_string())
+		.and_then(|bytes| object::load(&bytes));
+	let (source, module) = match loaded {
+		Ok(loaded) => loaded,
+		Err(message) => {
+			eprintln!("cannot load {}: {}", path, message);
+			process::exit(1);
+		}
+	};
+	run_module(&module, &source, limits);
+}
+
+/// run compiled code on the virtual machine, then show the variables main left behind
+fn run_module(module: &bytecode::Module, source: &str, limits: interpreter::Limits) {
+	let mut vm = vm::Vm::new(module);
+	vm.set_limits(limits);
+	match vm.run() {
+		Ok(()) => {
+			for variable in vm.main_variables() {
+				println!("{}", variable);
+			}
+		}
+		Err(error) => {
+			let text = fs::read_to_string(source).ok();
+			eprintln!("{}", error.render(source, text.as_deref()));
+			process::exit(1);
+		}
+	}
+}
+
+/// read `--fuel`, `--max-call-depth` or `--max-memory` and the bound after it,
+/// a number or `unlimited`; false when `arg` is not one of them
+fn limit_option<'a>(
+	arg: &str,
+	options: &mut impl Iterator<Item = &'a String>,
+	limits: &mut interpreter::Limits,
+) -> bool {
+	let flags = ["--fuel", "--max-call-depth", "--max-memory"];
+	if !flags.contains(&arg) {
+		return false;
+	}
+	let bound = match options.next().map(String::as_str) {
+		Some("unlimited") => None,
+		Some(value) => match value.parse::<u64>() {
+			Ok(bound) => Some(bound),
+			Err(_) => panic!("invalid bound {} after {}", value, arg),
+		},
+		None => panic!("missing bound after {}", arg),
+	};
+	match arg {
+		"--fuel" => limits.fuel = bound,
+		"--max-call-depth" => limits.call_depth = bound.map(|bound| bound as usize),
+		_ => limits.memory = bound.map(|bound| bound as usize),
+	}
+	true
 }
@@ -0,0 +1,610 @@
+use crate::ast::*;
+use crate::builtins;
+use crate::bytecode::{Constant, Function, Instruction, Location, Module, Scalar, Variable};
+use crate::token::Span;
+use crate::value::Number;
+
+/// first bytes of every object file
+pub const MAGIC: &[u8; 4] = b"XOBJ";
+
+/// format written by `save`; `load` rejects any other
+pub const VERSION: u16 = 1;
+
+/// Serialise a compiled module to an object file
+///
+/// After the magic bytes and the version come, in order: the path of the
+/// source file, the constant pool, the data segment, the struct definitions,
+/// the globals, the entry and main function indices and the function table.
+/// Each function holds its code followed by its debug line table, the span of
+/// every instruction, and its variables. Numbers are little endian, counts
+/// and indices are u32 and strings are a u32 length followed by UTF-8 bytes.
+///
+/// # Arguments
+///
+/// * `module` - output of the compiler
+/// * `source` - path of the X file it was compiled from, for error messages
+///
+pub fn save(module: &Module, source: &str) -> Vec<u8> {
+    let mut writer = Writer { bytes: Vec::new() };
+    writer.bytes.extend(MAGIC);
+    writer.bytes.extend(VERSION.to_le_bytes());
+    writer.string(source);
+
+    writer.count(module.constants.len());
+    for constant in &module.constants {
+        writer.constant(constant);
+    }
+    writer.count(module.data.len());
+    for constant in &module.data {
+        writer.constant(constant);
+    }
+    writer.count(module.structs.len());
+    for (name, members) in &module.structs {
+        writer.string(name);
+        writer.count(members.len());
+        for member in members {
+            writer.string(&member.name);
+            writer.data_type(&member.data_type);
+            writer.span(member.span);
+        }
+    }
+    writer.count(module.globals.len());
+    for variable in &module.globals {
+        writer.variable(variable);
+    }
+    writer.count(module.entry);
+    writer.count(module.main);
+
+    writer.count(module.functions.len());
+    for function in &module.functions {
+        writer.string(&function.name);
+        writer.count(function.parameters);
+        writer.count(function.frame);
+        writer.count(function.returns);
+        writer.count(function.code.len());
+        for instruction in &function.code {
+            writer.instruction(instruction);
+        }
+        for span in &function.spans {
+            writer.span(*span);
+        }
+        writer.count(function.variables.len());
+        for variable in &function.variables {
+            writer.variable(variable);
+        }
+    }
+    writer.bytes
+}
+
+/// Read back a module written by `save`, checking the header and that every
+/// index points into its table
+///
+/// # Arguments
+///
+/// * `bytes` - content of an object file
+///
+/// # Returns
+///
+/// the path of the source file and the module
+pub fn load(bytes: &[u8]) -> Result<(String, Module), String> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(4)? != MAGIC {
+        return Err(String::from("not an X object file"));
+    }
+    let version = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+    if version != VERSION {
+        return Err(format!(
+            "object file version {} is not supported, expected {}",
+            version, VERSION
+        ));
+    }
+    let source = reader.string()?;
+
+    let constants = reader.list(Reader::constant)?;
+    let data = reader.list(Reader::constant)?;
+    let structs = reader.list(|reader| {
+        let name = reader.string()?;
+        let members = reader.list(|reader| {
+            Ok(Member {
+                name: reader.string()?,
+                data_type: reader.data_type()?,
+                span: reader.span()?,
+            })
+        })?;
+        Ok((name, members))
+    })?;
+    let globals = reader.list(Reader::variable)?;
+    let entry = reader.count()?;
+    let main = reader.count()?;
+    let functions = reader.list(|reader| {
+        let name = reader.string()?;
+        let parameters = reader.count()?;
+        let frame = reader.count()?;
+        let returns = reader.count()?;
+        let length = reader.count()?;
+        let mut code = Vec::new();
+        for _ in 0..length {
+            code.push(reader.instruction()?);
+        }
+        let mut spans = Vec::new();
+        for _ in 0..length {
+            spans.push(reader.span()?);
+        }
+        Ok(Function {
+            name,
+            code,
+            spans,
+            parameters,
+            frame,
+            returns,
+            variables: reader.list(Reader::variable)?,
+        })
+    })?;
+    if reader.position != bytes.len() {
+        return Err(String::from("unexpected bytes after the function table"));
+    }
+
+    let module = Module {
+        functions,
+        constants,
+        data,
+        globals,
+        structs,
+        entry,
+        main,
+    };
+    validate(&module)?;
+    Ok((source, module))
+}
+
+/// indices out of their tables would make the machine panic instead of
+/// reporting a broken file
+fn validate(module: &Module) -> Result<(), String> {
+    let functions = module.functions.len();
+    if module.entry >= functions || module.main >= functions {
+        return Err(String::from(
+            "entry or main function is out of the function table",
+        ));
+    }
+    for function in &module.functions {
+        if function.parameters > function.frame {
+            return Err(format!(
+                "parameters of `{}` exceed its frame",
+                function.name
+            ));
+        }
+        for instruction in &function.code {
+            let valid = match *instruction {
+                Instruction::Push(index) => index < module.constants.len(),
+                Instruction::Fail(index) | Instruction::Printf { format: index, .. } => {
+                    matches!(module.constants.get(index), Some(Constant::Text(_)))
+                }
+                Instruction::Local(offset) => offset < function.frame,
+                Instruction::Jump(target) | Instruction::JumpIfFalse(target) => {
+                    target < function.code.len()
+                }
+                Instruction::Call(index) => index < functions,
+                Instruction::Builtin(index) => index < builtins::declarations().len(),
+                _ => true,
+            };
+            if !valid {
+                return Err(format!(
+                    "instruction `{}` of `{}` is out of range",
+                    instruction, function.name
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn count(&mut self, count: usize) {
+        self.bytes.extend((count as u32).to_le_bytes());
+    }
+
+    fn string(&mut self, text: &str) {
+        self.count(text.len());
+        self.bytes.extend(text.as_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        self.bytes.extend(span.line.to_le_bytes());
+        self.bytes.extend(span.column.to_le_bytes());
+    }
+
+    fn int_kind(&mut self, kind: IntKind, unsigned: bool) {
+        self.byte(kind as u8);
+        self.byte(unsigned as u8);
+    }
+
+    fn constant(&mut self, constant: &Constant) {
+        match constant {
+            Constant::Number(Number::Integer {
+                value,
+                kind,
+                unsigned,
+            }) => {
+                self.byte(0);
+                self.bytes.extend(value.to_le_bytes());
+                self.int_kind(*kind, *unsigned);
+            }
+            Constant::Number(Number::Float(x)) => {
+                self.byte(1);
+                self.bytes.extend(x.to_bits().to_le_bytes());
+            }
+            Constant::Number(Number::Double(x)) => {
+                self.byte(2);
+                self.bytes.extend(x.to_bits().to_le_bytes());
+            }
+            Constant::Null => self.byte(3),
+            Constant::Text(text) => {
+                self.byte(4);
+                self.string(text);
+            }
+        }
+    }
+
+    fn data_type(&mut self, data_type: &Type) {
+        match data_type {
+            Type::Void => self.byte(0),
+            Type::Integer { kind, unsigned } => {
+                self.byte(1);
+                self.int_kind(*kind, *unsigned);
+            }
+            Type::Float => self.byte(2),
+            Type::Double => self.byte(3),
+            Type::Pointer(target) => {
+                self.byte(4);
+                self.data_type(target);
+            }
+            Type::Array(element, size) => {
+                self.byte(5);
+                self.data_type(element);
+                match size {
+                    Some(size) => {
+                        self.byte(1);
+                        self.count(*size);
+                    }
+                    None => self.byte(0),
+                }
+            }
+            Type::Struct(name) => {
+                self.byte(6);
+                self.string(name);
+            }
+        }
+    }
+
+    fn variable(&mut self, variable: &Variable) {
+        self.string(&variable.name);
+        self.data_type(&variable.data_type);
+        match variable.location {
+            Location::Frame(offset) => {
+                self.byte(0);
+                self.count(offset);
+            }
+            Location::Global(address) => {
+                self.byte(1);
+                self.count(address);
+            }
+        }
+        self.count(variable.scope.0);
+        self.count(variable.scope.1);
+        self.count(variable.depth);
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        let (opcode, operand) = match *instruction {
+            Instruction::Push(index) => (0, Some(index)),
+            Instruction::Global(address) => (1, Some(address)),
+            Instruction::Local(offset) => (2, Some(offset)),
+            Instruction::Load => (3, None),
+            Instruction::Copy(cells) => (4, Some(cells)),
+            Instruction::Store(cells) => (5, Some(cells)),
+            Instruction::Clear(cells) => (6, Some(cells)),
+            Instruction::Dup(cells) => (7, Some(cells)),
+            Instruction::Pop(cells) => (8, Some(cells)),
+            Instruction::Convert(scalar) => {
+                self.byte(9);
+                match scalar {
+                    Scalar::Integer { kind, unsigned } => {
+                        self.byte(0);
+                        self.int_kind(kind, unsigned);
+                    }
+                    Scalar::Float => self.byte(1),
+                    Scalar::Double => self.byte(2),
+                    Scalar::Pointer => self.byte(3),
+                }
+                return;
+            }
+            Instruction::Negate => (10, None),
+            Instruction::Binary(operator) => {
+                self.byte(11);
+                self.byte(operator as u8);
+                return;
+            }
+            Instruction::PointerAdd(stride) => (12, Some(stride)),
+            Instruction::PointerSubtract(stride) => (13, Some(stride)),
+            Instruction::PointerDifference(stride) => (14, Some(stride)),
+            Instruction::Index(stride) => (15, Some(stride)),
+            Instruction::Bounds(size) => (16, Some(size)),
+            Instruction::Offset(offset) => (17, Some(offset)),
+            Instruction::Jump(target) => (18, Some(target)),
+            Instruction::JumpIfFalse(target) => (19, Some(target)),
+            Instruction::Call(function) => (20, Some(function)),
+            Instruction::Builtin(builtin) => (21, Some(builtin)),
+            Instruction::Printf { format, arguments } => {
+                self.byte(22);
+                self.count(format);
+                self.count(arguments);
+                return;
+            }
+            Instruction::Return => (23, None),
+            Instruction::NoReturn => (24, None),
+            Instruction::Fail(message) => (25, Some(message)),
+        };
+        self.byte(opcode);
+        if let Some(operand) = operand {
+            self.count(operand);
+        }
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    position: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, length: usize) -> Result<&'b [u8], String> {
+        let end = self.position + length;
+        if end > self.bytes.len() {
+            return Err(String::from("object file ends too early"));
+        }
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn list<T>(&mut self, item: impl Fn(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let count = self.count()?;
+        let mut items = Vec::new();
+        for _ in 0..count {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.count()?;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| String::from("string of the object file is not UTF-8"))
+    }
+
+    fn span(&mut self) -> Result<Span, String> {
+        let line = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        let column = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        Ok(Span { line, column })
+    }
+
+    fn int_kind(&mut self) -> Result<(IntKind, bool), String> {
+        let kind = match self.byte()? {
+            0 => IntKind::Char,
+            1 => IntKind::Short,
+            2 => IntKind::Int,
+            3 => IntKind::Long,
+            other => return Err(format!("unknown integer kind {}", other)),
+        };
+        Ok((kind, self.byte()? != 0))
+    }
+
+    fn constant(&mut self) -> Result<Constant, String> {
+        Ok(match self.byte()? {
+            0 => {
+                let value = i128::from_le_bytes(self.take(16)?.try_into().unwrap());
+                let (kind, unsigned) = self.int_kind()?;
+                Constant::Number(Number::Integer {
+                    value,
+                    kind,
+                    unsigned,
+                })
+            }
+            1 => Constant::Number(Number::Float(f32::from_bits(u32::from_le_bytes(
+                self.take(4)?.try_into().unwrap(),
+            )))),
+            2 => Constant::Number(Number::Double(f64::from_bits(u64::from_le_bytes(
+                self.take(8)?.try_into().unwrap(),
+            )))),
+            3 => Constant::Null,
+            4 => Constant::Text(self.string()?),
+            other => return Err(format!("unknown constant tag {}", other)),
+        })
+    }
+
+    fn data_type(&mut self) -> Result<Type, String> {
+        Ok(match self.byte()? {
+            0 => Type::Void,
+            1 => {
+                let (kind, unsigned) = self.int_kind()?;
+                Type::Integer { kind, unsigned }
+            }
+            2 => Type::Float,
+            3 => Type::Double,
+            4 => Type::Pointer(Box::new(self.data_type()?)),
+            5 => {
+                let element = self.data_type()?;
+                let size = match self.byte()? {
+                    0 => None,
+                    _ => Some(self.count()?),
+                };
+                Type::Array(Box::new(element), size)
+            }
+            6 => Type::Struct(self.string()?),
+            other => return Err(format!("unknown type tag {}", other)),
+        })
+    }
+
+    fn variable(&mut self) -> Result<Variable, String> {
+        let name = self.string()?;
+        let data_type = self.data_type()?;
+        let location = match self.byte()? {
+            0 => Location::Frame(self.count()?),
+            1 => Location::Global(self.count()?),
+            other => return Err(format!("unknown location tag {}", other)),
+        };
+        Ok(Variable {
+            name,
+            data_type,
+            location,
+            scope: (self.count()?, self.count()?),
+            depth: self.count()?,
+        })
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, String> {
+        let opcode = self.byte()?;
+        Ok(match opcode {
+            0 => Instruction::Push(self.count()?),
+            1 => Instruction::Global(self.count()?),
+            2 => Instruction::Local(self.count()?),
+            3 => Instruction::Load,
+            4 => Instruction::Copy(self.count()?),
+            5 => Instruction::Store(self.count()?),
+            6 => Instruction::Clear(self.count()?),
+            7 => Instruction::Dup(self.count()?),
+            8 => Instruction::Pop(self.count()?),
+            9 => Instruction::Convert(match self.byte()? {
+                0 => {
+                    let (kind, unsigned) = self.int_kind()?;
+                    Scalar::Integer { kind, unsigned }
+                }
+                1 => Scalar::Float,
+                2 => Scalar::Double,
+                3 => Scalar::Pointer,
+                other => return Err(format!("unknown conversion {}", other)),
+            }),
+            10 => Instruction::Negate,
+            11 => Instruction::Binary(match self.byte()? {
+                0 => BinaryOperator::Add,
+                1 => BinaryOperator::Subtract,
+                2 => BinaryOperator::Multiply,
+                3 => BinaryOperator::Divide,
+                4 => BinaryOperator::Equal,
+                5 => BinaryOperator::NotEqual,
+                6 => BinaryOperator::Less,
+                7 => BinaryOperator::Greater,
+                8 => BinaryOperator::LessEqual,
+                9 => BinaryOperator::GreaterEqual,
+                other => return Err(format!("unknown operator {}", other)),
+            }),
+            12 => Instruction::PointerAdd(self.count()?),
+            13 => Instruction::PointerSubtract(self.count()?),
+            14 => Instruction::PointerDifference(self.count()?),
+            15 => Instruction::Index(self.count()?),
+            16 => Instruction::Bounds(self.count()?),
+            17 => Instruction::Offset(self.count()?),
+            18 => Instruction::Jump(self.count()?),
+            19 => Instruction::JumpIfFalse(self.count()?),
+            20 => Instruction::Call(self.count()?),
+            21 => Instruction::Builtin(self.count()?),
+            22 => Instruction::Printf {
+                format: self.count()?,
+                arguments: self.count()?,
+            },
+            23 => Instruction::Return,
+            24 => Instruction::NoReturn,
+            25 => Instruction::Fail(self.count()?),
+            other => return Err(format!("unknown opcode {}", other)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::parser::Parser;
+
+    // a module using every kind of constant, global, struct and statement
+    fn module() -> Module {
+        let source = "struct Point { int x; double y; };
+            int table[3] = {1, 2, 3};
+            char letter = 120;
+            unsigned long big = 18446744073709551615;
+            int Scale(struct Point *p, float f);
+            void main(){
+                struct Point p;
+                p.x = table[1];
+                p.y = 2.5;
+                printf(\"%d %c\\n\", Scale(&p, 1.5), letter);
+            }
+            int Scale(struct Point *p, float f){
+                static int calls = 0;
+                calls = calls + 1;
+                switch ((*p).x) {
+                    case 2: return (int) ((*p).y * f) + calls;
+                    default: break;
+                }
+                return -1;
+            }";
+        let program = Parser::from_source(source, "format.csv").parse();
+        compiler::compile(&program)
+    }
+
+    #[test]
+    fn save_then_load_gives_the_module_back() {
+        let module = module();
+        let bytes = save(&module, "points.x");
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(load(&bytes), Ok((String::from("points.x"), module)));
+    }
+
+    #[test]
+    fn rejects_other_versions_and_damaged_files() {
+        let bytes = save(&module(), "points.x");
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            load(&newer),
+            Err(format!(
+                "object file version {} is not supported, expected {}",
+                VERSION + 1,
+                VERSION
+            ))
+        );
+
+        let mut other = bytes.clone();
+        other[0] = b'Y';
+        assert_eq!(load(&other), Err(String::from("not an X object file")));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert_eq!(
+            load(truncated),
+            Err(String::from("object file ends too early"))
+        );
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(
+            load(&longer),
+            Err(String::from("unexpected bytes after the function table"))
+        );
+    }
+}
@@ -0,0 +1,529 @@
+use crate::bytecode::{intern, Constant, Function, Instruction, Module};
+use crate::token::Span;
+
+use std::collections::HashSet;
+
+/// Improve the code of every function of a module without changing what it
+/// does, faults included
+///
+/// Four peephole passes run until none of them changes anything:
+///
+/// * constant folding: an operator or a conversion applied to constants is
+///   replaced by its result when it does not fault, and a conditional jump
+///   on a constant becomes a jump or disappears
+/// * redundant loads and stores: a variable stored and read back at once
+///   keeps the value on the stack, and values pushed only to be discarded
+///   are not pushed
+/// * jump threading: a jump to a jump goes straight to the final target, and
+///   a jump to the next instruction is removed
+/// * dead code removal: instructions no path from the start of the function
+///   reaches are removed
+///
+/// Jump targets, the debug line table and the scopes of variables follow the
+/// instructions they refer to.
+///
+/// # Arguments
+///
+/// * `module` - output of the compiler
+///
+pub fn optimize(module: &mut Module) {
+    for i in 0..module.functions.len() {
+        let mut function = std::mem::replace(&mut module.functions[i], empty_function());
+        loop {
+            let mut changed = fold_constants(&mut function, &mut module.constants);
+            changed |= remove_redundant_moves(&mut function);
+            changed |= thread_jumps(&mut function);
+            changed |= remove_dead_code(&mut function);
+            if !changed {
+                break;
+            }
+        }
+        module.functions[i] = function;
+    }
+}
+
+fn empty_function() -> Function {
+    Function {
+        name: String::new(),
+        code: Vec::new(),
+        spans: Vec::new(),
+        parameters: 0,
+        frame: 0,
+        returns: 0,
+        variables: Vec::new(),
+    }
+}
+
+/// instructions some jump goes to; a pattern may start at one of them but
+/// not span one, or the jump would land inside the replacement
+fn jump_targets(code: &[Instruction]) -> HashSet<usize> {
+    code.iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => Some(*target),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A rewrite of a function: which instructions stay and what replaces the
+/// instructions of the patterns found
+struct Rewrite {
+    /// new instructions for the one at each index, none to remove it
+    replacements: Vec<Vec<(Instruction, Span)>>,
+    changed: bool,
+}
+
+impl Rewrite {
+    fn new(function: &Function) -> Rewrite {
+        Rewrite {
+            replacements: function
+                .code
+                .iter()
+                .zip(&function.spans)
+                .map(|(instruction, span)| vec![(*instruction, *span)])
+                .collect(),
+            changed: false,
+        }
+    }
+
+    /// replace the instructions from `start` on with the given ones, all
+    /// placed at `start`
+    fn replace(&mut self, start: usize, length: usize, with: Vec<(Instruction, Span)>) {
+        self.replacements[start] = with;
+        for i in start + 1..start + length {
+            self.replacements[i] = Vec::new();
+        }
+        self.changed = true;
+    }
+
+    /// build the new code, moving jump targets and scopes that pointed at a
+    /// removed instruction to the next one that remains
+    fn apply(self, function: &mut Function) -> bool {
+        if !self.changed {
+            return false;
+        }
+        let mut position = Vec::new();
+        let mut code = Vec::new();
+        let mut spans = Vec::new();
+        for replacement in self.replacements {
+            position.push(code.len());
+            for (instruction, span) in replacement {
+                code.push(instruction);
+                spans.push(span);
+            }
+        }
+        position.push(code.len());
+        for instruction in &mut code {
+            if let Instruction::Jump(target) | Instruction::JumpIfFalse(target) = instruction {
+                *target = position[*target];
+            }
+        }
+        for variable in &mut function.variables {
+            variable.scope = (position[variable.scope.0], position[variable.scope.1]);
+        }
+        function.code = code;
+        function.spans = spans;
+        true
+    }
+}
+
+fn fold_constants(function: &mut Function, constants: &mut Vec<Constant>) -> bool {
+    let targets = jump_targets(&function.code);
+    let code = &function.code;
+    let mut rewrite = Rewrite::new(function);
+    let number = |instruction: &Instruction, constants: &[Constant]| match instruction {
+        Instruction::Push(index) => match constants[*index] {
+            Constant::Number(number) => Some(number),
+            _ => None,
+        },
+        _ => None,
+    };
+    let mut i = 0;
+    while i < code.len() {
+        let span = function.spans[i];
+        let first = number(&code[i], constants);
+        let next = code.get(i + 1).filter(|_| !targets.contains(&(i + 1)));
+        let third = code.get(i + 2).filter(|_| !targets.contains(&(i + 2)));
+
+        // push a; push b; binary
+        if let (Some(a), Some(Some(b)), Some(Instruction::Binary(operator))) =
+            (first, next.map(|next| number(next, constants)), third)
+        {
+            if let Ok(result) = a.binary(*operator, b) {
+                let result = intern(constants, Constant::Number(result));
+                rewrite.replace(i, 3, vec![(Instruction::Push(result), span)]);
+                i += 3;
+                continue;
+            }
+        }
+        let folded = match (first, next) {
+            (Some(a), Some(Instruction::Negate)) => a.negate().ok().map(Constant::Number),
+            (Some(a), Some(Instruction::Convert(scalar))) => match scalar.arithmetic() {
+                Some(to) => a.convert(&to).ok().map(Constant::Number),
+                None if a.as_integer() == Some(0) => Some(Constant::Null),
+                None => None,
+            },
+            _ => None,
+        };
+        if let Some(folded) = folded {
+            let folded = intern(constants, folded);
+            rewrite.replace(i, 2, vec![(Instruction::Push(folded), span)]);
+            i += 2;
+            continue;
+        }
+        // a condition known when compiling
+        let truth = match (&code[i], first) {
+            (_, Some(a)) => Some(!a.is_zero()),
+            (Instruction::Push(index), None) => match constants[*index] {
+                Constant::Null => Some(false),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let (Some(truth), Some(Instruction::JumpIfFalse(target))) = (truth, next) {
+            let replacement = match truth {
+                true => Vec::new(),
+                false => vec![(Instruction::Jump(*target), function.spans[i + 1])],
+            };
+            rewrite.replace(i, 2, replacement);
+            i += 2;
+            continue;
+        }
+        i += 1;
+    }
+    rewrite.apply(function)
+}
+
+fn remove_redundant_moves(function: &mut Function) -> bool {
+    let targets = jump_targets(&function.code);
+    let code = &function.code;
+    let mut rewrite = Rewrite::new(function);
+    let address = |instruction: &Instruction| {
+        matches!(instruction, Instruction::Local(_) | Instruction::Global(_))
+    };
+    let mut i = 0;
+    while i < code.len() {
+        let inside = |length: usize| {
+            i + length <= code.len() && (i + 1..i + length).all(|j| !targets.contains(&j))
+        };
+        // address; store 1; address; load: keep a copy of the value instead
+        if inside(4)
+            && address(&code[i])
+            && code[i + 1] == Instruction::Store(1)
+            && code[i + 2] == code[i]
+            && code[i + 3] == Instruction::Load
+        {
+            rewrite.replace(
+                i,
+                4,
+                vec![
+                    (Instruction::Dup(1), function.spans[i + 3]),
+                    (code[i], function.spans[i]),
+                    (Instruction::Store(1), function.spans[i + 1]),
+                ],
+            );
+            i += 4;
+            continue;
+        }
+        // values pushed only to be discarded
+        let discarded = inside(2)
+            && match (code[i], code[i + 1]) {
+                (Instruction::Dup(n), Instruction::Pop(m)) => n == m,
+                (Instruction::Push(_), Instruction::Pop(1)) => true,
+                _ => false,
+            };
+        if discarded {
+            rewrite.replace(i, 2, Vec::new());
+            i += 2;
+            continue;
+        }
+        if matches!(
+            code[i],
+            Instruction::Pop(0) | Instruction::Dup(0) | Instruction::Offset(0)
+        ) {
+            rewrite.replace(i, 1, Vec::new());
+        }
+        i += 1;
+    }
+    rewrite.apply(function)
+}
+
+fn thread_jumps(function: &mut Function) -> bool {
+    let code = &function.code;
+    // where a jump really ends up, following chains of jumps without looping
+    let destination = |mut target: usize| {
+        let mut seen = HashSet::new();
+        while let Some(Instruction::Jump(next)) = code.get(target) {
+            if !seen.insert(target) {
+                break;
+            }
+            target = *next;
+        }
+        target
+    };
+    let mut rewrite = Rewrite::new(function);
+    for (i, instruction) in code.iter().enumerate() {
+        let span = function.spans[i];
+        match *instruction {
+            Instruction::Jump(target) if destination(target) == i + 1 => {
+                rewrite.replace(i, 1, Vec::new());
+            }
+            Instruction::Jump(target) if destination(target) != target => {
+                rewrite.replace(i, 1, vec![(Instruction::Jump(destination(target)), span)]);
+            }
+            Instruction::JumpIfFalse(target) if destination(target) != target => rewrite.replace(
+                i,
+                1,
+                vec![(Instruction::JumpIfFalse(destination(target)), span)],
+            ),
+            _ => {}
+        }
+    }
+    rewrite.apply(function)
+}
+
+fn remove_dead_code(function: &mut Function) -> bool {
+    let code = &function.code;
+    let mut reached = vec![false; code.len()];
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        if i >= code.len() || reached[i] {
+            continue;
+        }
+        reached[i] = true;
+        match code[i] {
+            Instruction::Jump(target) => pending.push(target),
+            Instruction::JumpIfFalse(target) => {
+                pending.push(target);
+                pending.push(i + 1);
+            }
+            Instruction::Return | Instruction::NoReturn | Instruction::Fail(_) => {}
+            _ => pending.push(i + 1),
+        }
+    }
+    let mut rewrite = Rewrite::new(function);
+    for (i, reached) in reached.into_iter().enumerate() {
+        if !reached {
+            rewrite.replace(i, 1, Vec::new());
+        }
+    }
+    rewrite.apply(function)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::BinaryOperator;
+    use crate::compiler;
+    use crate::interpreter::RuntimeError;
+    use crate::parser::Parser;
+    use crate::value::Number;
+    use crate::vm::Vm;
+    use Instruction::*;
+
+    fn function(code: Vec<Instruction>) -> Function {
+        Function {
+            spans: vec![Span::default(); code.len()],
+            code,
+            ..empty_function()
+        }
+    }
+
+    fn numbers(values: &[i128]) -> Vec<Constant> {
+        values
+            .iter()
+            .map(|value| Constant::Number(Number::int(*value)))
+            .collect()
+    }
+
+    #[test]
+    fn folds_operators_on_constants() {
+        let mut constants = numbers(&[1, 2]);
+        let mut f = function(vec![Push(0), Push(1), Binary(BinaryOperator::Add), Return]);
+        assert!(fold_constants(&mut f, &mut constants));
+        assert_eq!(f.code, vec![Push(2), Return]);
+        assert_eq!(constants[2], Constant::Number(Number::int(3)));
+    }
+
+    #[test]
+    fn leaves_faults_to_the_run() {
+        let mut constants = numbers(&[1, 0, i32::MAX as i128]);
+        let code = vec![
+            Push(0),
+            Push(1),
+            Binary(BinaryOperator::Divide),
+            Push(2),
+            Push(0),
+            Binary(BinaryOperator::Add),
+            Return,
+        ];
+        let mut f = function(code.clone());
+        assert!(!fold_constants(&mut f, &mut constants));
+        assert_eq!(f.code, code);
+    }
+
+    #[test]
+    fn decides_constant_conditions() {
+        let mut constants = numbers(&[0, 1]);
+        let mut f = function(vec![
+            Push(0),
+            JumpIfFalse(4),
+            Push(1),
+            JumpIfFalse(4),
+            Return,
+        ]);
+        assert!(fold_constants(&mut f, &mut constants));
+        assert_eq!(f.code, vec![Jump(1), Return]);
+    }
+
+    #[test]
+    fn keeps_stored_values_on_the_stack() {
+        let mut f = function(vec![
+            Local(0),
+            Store(1),
+            Local(0),
+            Load,
+            Push(0),
+            Pop(1),
+            Return,
+        ]);
+        assert!(remove_redundant_moves(&mut f));
+        assert_eq!(f.code, vec![Dup(1), Local(0), Store(1), Return]);
+    }
+
+    #[test]
+    fn threads_jumps_to_jumps() {
+        let mut f = function(vec![Jump(2), Return, Jump(4), Return, Push(0), Return]);
+        assert!(thread_jumps(&mut f));
+        assert_eq!(
+            f.code,
+            vec![Jump(4), Return, Jump(4), Return, Push(0), Return]
+        );
+    }
+
+    #[test]
+    fn removes_jumps_to_the_next_instruction() {
+        // the jump at 2 goes away, and the one over it is moved back
+        let mut f = function(vec![Push(0), JumpIfFalse(3), Jump(3), Push(0), Return]);
+        assert!(thread_jumps(&mut f));
+        assert_eq!(f.code, vec![Push(0), JumpIfFalse(2), Push(0), Return]);
+    }
+
+    #[test]
+    fn removes_unreachable_code() {
+        let mut f = function(vec![Jump(3), Push(0), Pop(1), Return]);
+        assert!(remove_dead_code(&mut f));
+        assert_eq!(f.code, vec![Jump(1), Return]);
+    }
+
+    // run a program on the vm, returning what it printed and how it ended
+    fn run(module: &Module, input: &str) -> (String, Result<(), RuntimeError>) {
+        let mut output = Vec::new();
+        let result = Vm::with_io(module, Box::new(input.as_bytes()), Box::new(&mut output)).run();
+        (String::from_utf8(output).unwrap(), result)
+    }
+
+    #[test]
+    fn optimized_programs_do_the_same() {
+        let programs = [
+            // loops, with a break and a for loop with an empty part
+            (
+                "void main(){
+                int i; int total;
+                i = 0; total = 0;
+                while (i < 10) {
+                    if (i == 7) { break; }
+                    total = total + i;
+                    i = i + 1;
+                }
+                for (i = 0; ; i = i + 1) {
+                    if (i > 3) { break; }
+                    total = total * 2;
+                }
+                printf(\"%d %d\\n\", i, total);
+            }",
+                "4 336\n",
+                None,
+            ),
+            // constant conditions
+            (
+                "void main(){
+                int x;
+                x = 1;
+                if (1) { x = x + 2 * 3; }
+                if (0) { x = 100; }
+                while (0) { x = 200; }
+                if (2 < 1) { x = 300; }
+                print_int(x);
+            }",
+                "7",
+                None,
+            ),
+            // dead code after return
+            (
+                "int F(int a);
+            void main(){ print_int(F(read_int())); }
+            int F(int a){
+                if (a > 2) { return a * 2; print_int(99); }
+                return a;
+                a = a + 1;
+            }",
+                "10",
+                None,
+            ),
+            // jump chains, nested loops and ifs ending together
+            (
+                "void main(){
+                int i; int j; int n;
+                i = 0; n = 0;
+                while (i < 4) {
+                    j = 0;
+                    while (j < i) {
+                        if (j == 1) { if (i == 3) { n = n + 10; } }
+                        n = n + 1;
+                        j = j + 1;
+                    }
+                    i = i + 1;
+                }
+                print_int(n);
+            }",
+                "16",
+                None,
+            ),
+            // faults inside expressions that could be folded
+            (
+                "void main(){ int x; print_int(1); x = 2 + 3 / (2 - 2); }",
+                "1",
+                Some("division by zero"),
+            ),
+            (
+                "void main(){ int x; print_int(2); x = 2147483647 + 1; }",
+                "2",
+                Some("integer overflow in `int` arithmetic"),
+            ),
+            (
+                "void main(){ char c; print_int(3); c = (char) 300.0; }",
+                "3",
+                Some("value does not fit in `char`"),
+            ),
+            (
+                "void main(){ int x; x = 1; if (0 * 5) { x = 1 / 0; } print_int(x); }",
+                "1",
+                None,
+            ),
+        ];
+        for (source, printed, fault) in programs {
+            let program = Parser::from_source(source, "format.csv").parse();
+            let module = compiler::compile(&program);
+            let mut optimized = module.clone();
+            optimize(&mut optimized);
+            let (output, result) = run(&module, "5");
+            assert_eq!(output, printed, "{}", source);
+            assert_eq!(
+                result.map_err(|error| error.message).err().as_deref(),
+                fault
+            );
+            assert_eq!(run(&optimized, "5"), run(&module, "5"), "{}", source);
+        }
+    }
+}
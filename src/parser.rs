@@ -1,7 +1,35 @@
+use crate::ast::*;
+use crate::const_eval::{self, ConstErrorKind};
+use crate::diagnostic::Diagnostic;
 use crate::prettifier::Prettifier;
 use crate::scanner::Scanner;
+use crate::token::Span;
 use crate::token::Token;
 use crate::token::TokenType;
+use crate::value::Number;
+
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    // set while `recover` runs a rule on this thread, whose syntax errors are
+    // caught rather than printed
+    static RECOVERING: Cell<bool> = const { Cell::new(false) };
+}
+
+static QUIET_HOOK: Once = Once::new();
+
+// what a name in scope stands for; struct and enum tags are kept under "struct Name" and "enum Name"
+#[derive(Clone)]
+enum ScopeEntry {
+    Variable(Type, Qualifiers),
+    TypeName(Type),
+    Struct(Vec<Member>),
+    Enum,
+    Enumerator(i64),
+}
 
 #[derive(Clone)]
 pub struct Parser {
@@ -9,26 +37,41 @@ pub struct Parser {
     idx: usize,
     result: String,
     prettifier: Prettifier,
+    // variables, typedef names and struct tags visible at the current point, innermost scope last
+    scopes: Vec<HashMap<String, ScopeEntry>>,
+    // loops and switches around the current point, which a `break` may leave
+    breakable: usize,
 }
 
 impl Parser {
     /// Create a new Parser for X-lang
-    /// 
+    ///
     /// # Arguments
-    /// 
+    ///
     /// * `f` - path to the source file
     /// * `format_f` - path to the config csv file, `format.csv` in this project
-    /// 
+    ///
     pub fn new(f: &str, format_f: &str) -> Parser {
+        Parser::with_scanner(Scanner::new(f), format_f)
+    }
+
+    /// Create a new Parser for X-lang source that is not in a file
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - the X source
+    /// * `format_f` - path to the config csv file, `format.csv` in this project
+    ///
+    pub fn from_source(source: &str, format_f: &str) -> Parser {
+        Parser::with_scanner(Scanner::from_source(source), format_f)
+    }
+
+    fn with_scanner(mut scanner: Scanner, format_f: &str) -> Parser {
         let mut token_acc = Vec::new();
-        let mut scanner = Scanner::new(f);
 
         // read in all tokens from scanner
-        loop {
-            match scanner.get_next_token() {
-                Some(token) => token_acc.push(token),
-                None => break,
-            }
+        while let Some(token) = scanner.get_next_token() {
+            token_acc.push(token);
         }
 
         Parser {
@@ -36,27 +79,101 @@ impl Parser {
             idx: 0,
             result: String::from(""),
             prettifier: Prettifier::new(format_f),
+            scopes: vec![HashMap::new()],
+            breakable: 0,
         }
     }
 
-    /// print all all the tokens after lexer
-    pub fn print_lex_results(&self) {
-        for token in &self.tokens {
-            println!("text: {}", token.get_text());
-            println!("token type: {}", token.get_type().as_str());
-            println!("line number: {}", token.get_line_number());
-            println!("char position: {}", token.get_char_pos());
-            println!("=======================================");
-        }
+    /// the tokens, with the categories the parser refined
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// parse like `try_parse`, panicking at the first syntax error
+    #[cfg(test)]
+    pub fn parse(&mut self) -> Program {
+        self.program()
     }
 
-    pub fn get_result(&self) -> &str {
-        &self.result
+    /// start the recursive descent parser based on EBNF rules, returning the
+    /// first syntax error instead of stopping the process
+    pub fn try_parse(&mut self) -> Result<Program, Diagnostic> {
+        self.recover(Parser::program)
+    }
+
+    /// Parse an expression on its own, as if it appeared where the given
+    /// variables are in scope, after everything this parser has parsed
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - the expression, positions in errors are relative to it
+    /// * `variables` - names and types of the variables in scope besides the
+    ///   globals, outermost first
+    ///
+    pub fn try_parse_expression(
+        &self,
+        source: &str,
+        variables: &[(String, Type)],
+    ) -> Result<Expression, Diagnostic> {
+        let mut parser = self.clone();
+        let mut scanner = Scanner::from_source(source);
+        parser.tokens.clear();
+        while let Some(token) = scanner.get_next_token() {
+            parser.tokens.push(token);
+        }
+        parser.idx = 0;
+        parser.result.clear();
+        parser.scopes.push(HashMap::new());
+        for (name, data_type) in variables {
+            parser.declare(name, data_type, Qualifiers::default());
+        }
+        parser.recover(|parser| {
+            if parser.is_end() {
+                panic!("expected an expression");
+            }
+            let expression = parser.expression();
+            if !parser.is_end() {
+                parser.panic_with_error("unexpected text after the expression");
+            }
+            expression
+        })
     }
 
-    /// start the recursive descent parser based on EBNF rules
-    pub fn parse(&mut self) {
-        self.program();
+    // run a rule, turning the panic of a syntax error into a diagnostic
+    fn recover<T>(&mut self, rule: impl FnOnce(&mut Parser) -> T) -> Result<T, Diagnostic> {
+        // the errors are panics, caught here; a hook installed once for the
+        // process keeps them quiet on a thread that is recovering, and
+        // leaves every other panic to the hook that was there before
+        QUIET_HOOK.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if !RECOVERING.with(Cell::get) {
+                    previous(info);
+                }
+            }));
+        });
+        let outer = RECOVERING.with(|recovering| recovering.replace(true));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| rule(self)));
+        RECOVERING.with(|recovering| recovering.set(outer));
+        result.map_err(|payload| {
+            let message = match payload.downcast_ref::<String>() {
+                Some(message) => message.as_str(),
+                None => payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .unwrap_or("syntax error"),
+            };
+            // the position is given by the token the parser stopped at
+            let message = message.lines().next().unwrap_or_default();
+            let span = match self
+                .tokens
+                .get(self.idx.min(self.tokens.len().saturating_sub(1)))
+            {
+                Some(token) => token.get_span(),
+                None => Span { line: 1, column: 1 },
+            };
+            Diagnostic::error(String::from(message), span)
+        })
     }
 
     pub fn to_xhtml(&self) -> String {
@@ -77,26 +194,50 @@ impl Parser {
         xhtml
     }
 
-    fn panic_with_error(&self, msg: &str) {
+    fn panic_with_error(&self, msg: &str) -> ! {
+        let token = &self.tokens[self.idx.min(self.tokens.len() - 1)];
         panic!(
             "{}\n  line number: {}\n  char pos: {}",
             msg,
-            self.tokens[self.idx].get_line_number(),
-            self.tokens[self.idx].get_char_pos()
+            token.get_line_number(),
+            token.get_char_pos()
         );
     }
 
-    fn is_line_changed(&self) -> bool {
-        self.tokens[self.idx].get_line_number() != self.tokens[self.idx - 1].get_line_number()
+    fn is_end(&self) -> bool {
+        self.idx >= self.tokens.len()
     }
 
-    fn show(&self) {
-        println!(
-            "!!! {} {} \n{}",
-            self.tokens[self.idx].get_text(),
-            self.tokens[self.idx].get_type().as_str(),
-            self.result
-        );
+    // the token under the cursor, it is an error to run out of tokens here
+    fn current(&self) -> &Token {
+        if self.is_end() {
+            panic!("Unexpected end of file");
+        }
+        &self.tokens[self.idx]
+    }
+
+    fn is_text(&self, text: &str) -> bool {
+        !self.is_end() && self.tokens[self.idx].get_text() == text
+    }
+
+    fn is_text_ahead(&self, k: usize, text: &str) -> bool {
+        self.idx + k < self.tokens.len() && self.tokens[self.idx + k].get_text() == text
+    }
+
+    // write the current token to the result and move to the next one
+    fn consume(&mut self) -> Token {
+        let token = self.current().clone();
+        self.result
+            .push_str(&self.prettifier.prettify(token.clone()));
+        self.idx += 1;
+        token
+    }
+
+    fn expect(&mut self, text: &str, rule: &str) -> Token {
+        if !self.is_text(text) {
+            self.panic_with_error(&format!("expected '{}' in {}", text, rule));
+        }
+        self.consume()
     }
 
     fn indent(&mut self, len: i32) {
@@ -105,455 +246,1553 @@ impl Parser {
         }
     }
 
-    fn program(&mut self) {
-        // {Declaration}
-        while self.idx < self.tokens.len() && self.tokens[self.idx].get_text() != "void" {
-            self.declaration(0);
-            self.idx += 1;
+    fn new_line(&mut self) {
+        self.result.push_str("<br />");
+    }
+
+    fn declare(&mut self, name: &str, data_type: &Type, qualifiers: Qualifiers) {
+        self.scopes.last_mut().unwrap().insert(
+            String::from(name),
+            ScopeEntry::Variable(data_type.clone(), qualifiers),
+        );
+    }
+
+    fn lookup_entry(&self, name: &str) -> Option<&ScopeEntry> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Type> {
+        match self.lookup_entry(name) {
+            Some(ScopeEntry::Variable(data_type, _)) => Some(data_type),
+            _ => None,
         }
+    }
 
-        if self.idx >= self.tokens.len() {
-            panic!("Missing Main Declaration!");
+    fn lookup_type_name(&self, name: &str) -> Option<&Type> {
+        match self.lookup_entry(name) {
+            Some(ScopeEntry::TypeName(data_type)) => Some(data_type),
+            _ => None,
         }
-        self.main_declaration();
+    }
 
-        // {Function Definition}
-        while self.idx < self.tokens.len() {
-            self.function_definition();
-            self.idx += 1;
+    fn lookup_struct(&self, name: &str) -> Option<&Vec<Member>> {
+        match self.lookup_entry(&format!("struct {}", name)) {
+            Some(ScopeEntry::Struct(members)) => Some(members),
+            _ => None,
         }
     }
 
-    fn declaration(&mut self, indent_len: i32) {
-        self.declaration_type(indent_len);
-        self.idx += 1;
-        match self.tokens[self.idx - 1].get_type() {
-            TokenType::VARIABLE => self.variable_declaration(),
-            TokenType::FUNCTION => self.function_declaration(),
-            _ => self.panic_with_error("Invalid declaration"),
+    fn lookup_enumerator(&self, name: &str) -> Option<i64> {
+        match self.lookup_entry(name) {
+            Some(ScopeEntry::Enumerator(value)) => Some(*value),
+            _ => None,
         }
-        self.result
-            .push_str("<font color=\"white\"><b>;</b></font><br />");
-        // self.result.push_str(";\n");
     }
 
-    fn main_declaration(&mut self) {
-        // skip two key words: void main
-        if self.tokens[self.idx].get_text() == "void" {
-            // self.result.push_str(self.tokens[self.idx].get_text());
-            self.result
-                .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
-            self.result.push(' ');
-            self.idx += 1;
-        } else {
-            self.panic_with_error("invalid main declaration: void");
-        }
-        if self.tokens[self.idx].get_text() == "main" {
-            // self.result.push_str("main() ");
-            self.result
-                .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
-            self.result.push_str(
-                "<font color=\"white\"><b>(</b></font><font color=\"white\"><b>)</b></font> ",
-            );
-            self.idx += 1;
-        } else {
-            self.panic_with_error("invalid main declaration: main");
+    fn is_enumerator(&self) -> bool {
+        !self.is_end()
+            && matches!(
+                self.current().get_type(),
+                TokenType::VARIABLE | TokenType::ENUMCONSTANT
+            )
+            && self.lookup_enumerator(self.current().get_text()).is_some()
+    }
+
+    // an enumerator is written with its own colour, wherever it appears
+    fn enumerator(&mut self) -> Expression {
+        let name = String::from(self.current().get_text());
+        let value = self.lookup_enumerator(&name).unwrap();
+        self.tokens[self.idx].set_type(TokenType::ENUMCONSTANT);
+        let token = self.consume();
+        Expression::new(
+            ExpressionKind::EnumConstant { name, value },
+            token.get_span(),
+        )
+    }
+
+    /// true if the current token starts a type: a builtin type name, `struct`, `enum`,
+    /// or an identifier declared by a typedef that is visible here
+    fn is_type(&self) -> bool {
+        self.is_type_ahead(0)
+    }
+
+    fn is_type_ahead(&self, k: usize) -> bool {
+        if self.idx + k >= self.tokens.len() {
+            return false;
         }
+        let token = &self.tokens[self.idx + k];
+        token.is_type()
+            || token.get_text() == "struct"
+            || token.get_text() == "enum"
+            || (token.get_type() == &TokenType::VARIABLE
+                && self.lookup_type_name(token.get_text()).is_some())
+    }
 
-        self.block(0);
+    // a type in a cast or sizeof may also be void
+    fn is_type_name_ahead(&self, k: usize) -> bool {
+        self.is_type_ahead(k) || self.is_text_ahead(k, "void")
     }
 
-    fn function_definition(&mut self) {
-        self.declaration_type(0);
-        self.idx += 1;
-        self.parameter_block();
-        self.idx += 1;
-        self.block(0);
+    fn is_declaration_start(&self) -> bool {
+        self.is_type()
+            || self.is_text("typedef")
+            || (!self.is_end() && self.current().get_type() == &TokenType::QUALIFIER)
     }
 
-    fn declaration_type(&mut self, indent_len: i32) {
-        self.data_type(indent_len);
-        self.idx += 1;
-        // Identifier
-        self.indent(0);
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+    // the variable an lvalue finally stores into, `a` for `a[i].m`
+    fn root_variable(expr: &Expression) -> Option<&str> {
+        match &expr.kind {
+            ExpressionKind::Variable(name) => Some(name),
+            ExpressionKind::Index { array, .. } => Parser::root_variable(array),
+            ExpressionKind::Member { base, .. } => Parser::root_variable(base),
+            _ => None,
+        }
     }
 
-    fn variable_declaration(&mut self) {
-        if self.idx >= self.tokens.len() || self.tokens[self.idx].get_text() != "=" {
-            self.idx -= 1;
-            return;
+    // variables and members must have a complete type, pointers to undefined structs are fine
+    fn check_complete(&self, data_type: &Type) {
+        match data_type {
+            Type::Struct(name) if self.lookup_struct(name).is_none() => {
+                self.panic_with_error(&format!("struct {} is not defined", name))
+            }
+            Type::Array(element, _) => self.check_complete(element),
+            _ => {}
         }
-        self.result.push_str(" ");
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
-        self.result.push_str(" ");
-        self.idx += 1;
-        self.constant();
     }
 
-    fn function_declaration(&mut self) {
-        self.parameter_block();
+    fn size_of(&self, data_type: &Type) -> Option<usize> {
+        data_type.size(&|name| self.lookup_struct(name).cloned())
     }
 
-    fn block(&mut self, indent_len: i32) {
-        // self.indent(indent_len);
-        // self.result.push_str("{\n");
-        self.result
-            .push_str("<font color=\"white\"><b>{</b></font><br />");
+    // value of a constant expression, `what` names where it is required in errors
+    fn fold(&self, expr: &Expression, what: &str) -> Number {
+        let size_of = |expr: &Expression| match &expr.kind {
+            ExpressionKind::SizeOfType(data_type) => self.size_of(data_type),
+            ExpressionKind::SizeOfExpression(operand) => self.size_of(&self.static_type(operand)?),
+            _ => None,
+        };
+        match const_eval::evaluate(expr, &size_of) {
+            Ok(constant) => constant,
+            Err(error) if error.kind == ConstErrorKind::NotConstant => {
+                self.panic_with_error(&format!("{} must be a constant expression", what))
+            }
+            Err(error) => self.panic_with_error(&format!("{}: {}", what, error.message())),
+        }
+    }
+
+    // type of a variable, indexed array or pointer access, if it can be told from the declarations
+    fn static_type(&self, expr: &Expression) -> Option<Type> {
+        match &expr.kind {
+            ExpressionKind::Variable(name) => self.lookup(name).cloned(),
+            ExpressionKind::Index { array, .. } => self.static_type(array)?.pointee().cloned(),
+            ExpressionKind::Member { base, member } => match self.static_type(base)? {
+                Type::Struct(name) => self
+                    .lookup_struct(&name)?
+                    .iter()
+                    .find(|m| &m.name == member)
+                    .map(|m| m.data_type.clone()),
+                _ => None,
+            },
+            ExpressionKind::Unary {
+                operator: UnaryOperator::Dereference,
+                operand,
+            } => self.static_type(operand)?.pointee().cloned(),
+            ExpressionKind::Unary {
+                operator: UnaryOperator::AddressOf,
+                operand,
+            } => Some(Type::Pointer(Box::new(self.static_type(operand)?))),
+            // p + n and p - n stay pointers, p - q is a distance
+            ExpressionKind::Binary {
+                operator: BinaryOperator::Add | BinaryOperator::Subtract,
+                left,
+                right,
+            } => {
+                let pointer = |data_type: Type| match data_type {
+                    Type::Array(element, _) => Some(Type::Pointer(element)),
+                    Type::Pointer(_) => Some(data_type),
+                    _ => None,
+                };
+                let left = self.static_type(left).and_then(pointer);
+                let right = self.static_type(right).and_then(pointer);
+                match (left, right) {
+                    (Some(_), Some(_)) => None,
+                    (Some(pointer), None) | (None, Some(pointer)) => Some(pointer),
+                    (None, None) => None,
+                }
+            }
+            ExpressionKind::Cast { data_type, .. } => Some(data_type.clone()),
+            ExpressionKind::EnumConstant { .. } => Some(Type::Integer {
+                kind: IntKind::Int,
+                unsigned: false,
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_main_declaration(&self) -> bool {
+        self.is_text("void") && self.is_text_ahead(1, "main")
+    }
 
+    fn program(&mut self) -> Program {
         // {Declaration}
-        while self.idx < self.tokens.len() && self.tokens[self.idx].is_type() {
-            self.declaration(indent_len + 1);
-            self.idx += 1;
+        let mut declarations = Vec::new();
+        while !self.is_end() && !self.is_main_declaration() {
+            declarations.push(self.declaration(0));
         }
 
-        // {Statement}
-        let statement_keywords = vec!["while", "if", "return"];
-        while self.idx < self.tokens.len() {
-            let token: Token = self.tokens[self.idx].clone();
-            if token.get_type() != &TokenType::VARIABLE
-                && !statement_keywords.contains(&token.get_text())
-            {
-                break;
+        if self.is_end() {
+            panic!("Missing Main Declaration!");
+        }
+        let main = self.main_declaration();
+
+        // {Function Definition}
+        let mut functions = Vec::new();
+        while !self.is_end() {
+            functions.push(self.function_definition());
+        }
+
+        Program {
+            declarations,
+            main,
+            functions,
+        }
+    }
+
+    fn declaration(&mut self, indent_len: i32) -> Declaration {
+        if self.is_text("typedef") {
+            return Declaration::Typedef(self.typedef_declaration(indent_len));
+        }
+        if self.is_text("struct") && self.is_text_ahead(2, "{") {
+            return Declaration::Struct(self.struct_declaration(indent_len));
+        }
+        if self.is_text("enum") && (self.is_text_ahead(1, "{") || self.is_text_ahead(2, "{")) {
+            return Declaration::Enum(self.enum_declaration(indent_len));
+        }
+        let (qualifiers, data_type, name) = self.declaration_type(indent_len);
+        let declaration = match name.get_type() {
+            TokenType::VARIABLE => {
+                Declaration::Variable(self.variable_declaration(qualifiers, data_type, name))
             }
-            self.statement(indent_len + 1);
-            if self.tokens[self.idx - 1].get_type() != &TokenType::NONE {
-                self.idx += 1;
+            TokenType::FUNCTION => {
+                Declaration::Function(self.function_declaration(qualifiers, data_type, name))
             }
-            if self.tokens[self.idx].get_type() == &TokenType::NONE {
-                self.idx += 1;
-                break;
+            _ => {
+                self.idx -= 1;
+                self.panic_with_error("Invalid declaration")
             }
+        };
+        self.expect(";", "declaration");
+        self.new_line();
+        declaration
+    }
+
+    // struct Identifier { DataType Identifier ; {DataType Identifier ;} } ;
+    fn struct_declaration(&mut self, indent_len: i32) -> StructDeclaration {
+        self.indent(indent_len);
+        self.consume();
+        self.result.push(' ');
+        let name = self.current().clone();
+        if name.get_type() != &TokenType::VARIABLE {
+            self.panic_with_error("expected struct name");
+        }
+        let key = format!("struct {}", name.get_text());
+        if self.scopes.last().unwrap().contains_key(&key) {
+            self.panic_with_error(&format!("struct {} is already defined", name.get_text()));
         }
+        self.consume();
+        self.expect("{", "struct declaration");
+        self.new_line();
 
-        // {Function Definition}
+        let mut members: Vec<Member> = Vec::new();
+        while !self.is_text("}") {
+            let (qualifiers, data_type, member) = self.declaration_type(indent_len + 1);
+            if qualifiers.storage != StorageClass::Auto {
+                self.idx -= 1;
+                self.panic_with_error("struct members cannot have a storage class");
+            }
+            let data_type = self.array_suffix(data_type);
+            if data_type == Type::Struct(String::from(name.get_text())) {
+                self.panic_with_error("a struct cannot contain itself");
+            }
+            self.check_complete(&data_type);
+            if members.iter().any(|m| m.name == member.get_text()) {
+                self.idx -= 1;
+                self.panic_with_error(&format!("duplicate member {}", member.get_text()));
+            }
+            members.push(Member {
+                name: String::from(member.get_text()),
+                data_type,
+                span: member.get_span(),
+            });
+            self.expect(";", "struct member");
+            self.new_line();
+        }
+        if members.is_empty() {
+            self.panic_with_error("a struct needs at least one member");
+        }
 
         self.indent(indent_len);
-        // self.result.push_str("}\n");
-        self.result
-            .push_str("<font color=\"white\"><b>}</b></font><br />");
+        self.expect("}", "struct declaration");
+        self.expect(";", "struct declaration");
+        self.new_line();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(key, ScopeEntry::Struct(members.clone()));
+        StructDeclaration {
+            name: String::from(name.get_text()),
+            members,
+            span: name.get_span(),
+        }
     }
 
-    fn parameter_block(&mut self) {
-        // self.result.push('(');
-        self.result
-            .push_str("<font color=\"white\"><b>(</b></font>");
+    // enum [Identifier] { Enumerator {, Enumerator} [,] } ;
+    // Enumerator := Identifier [= Constant], counting up from the previous value
+    fn enum_declaration(&mut self, indent_len: i32) -> EnumDeclaration {
+        self.indent(indent_len);
+        let span = self.consume().get_span();
+        self.result.push(' ');
+        let mut name = None;
+        if !self.is_text("{") {
+            let token = self.current().clone();
+            if token.get_type() != &TokenType::VARIABLE {
+                self.panic_with_error("expected enum name");
+            }
+            let key = format!("enum {}", token.get_text());
+            if self.scopes.last().unwrap().contains_key(&key) {
+                self.panic_with_error(&format!("enum {} is already defined", token.get_text()));
+            }
+            self.consume();
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert(key, ScopeEntry::Enum);
+            name = Some(String::from(token.get_text()));
+        }
+        self.expect("{", "enum declaration");
+        self.new_line();
 
-        // [Parameter {, Parameter}]
-        if self.tokens[self.idx].is_type() {
-            self.parameter();
-            self.idx += 1;
-        }
-
-        while self.idx < self.tokens.len()
-            && self.tokens[self.idx].is_type()
-            && !self.is_line_changed()
-        {
-            // self.result.push_str(", ");
-            self.result
-                .push_str("<font color=\"white\"><b>,</b></font>");
+        let mut enumerators = Vec::new();
+        let mut value: i64 = 0;
+        while !self.is_text("}") {
+            self.indent(indent_len + 1);
+            let token = self.current().clone();
+            if token.get_type() != &TokenType::VARIABLE {
+                self.panic_with_error("expected enumerator name");
+            }
+            self.tokens[self.idx].set_type(TokenType::ENUMCONSTANT);
+            self.consume();
+            if self.is_text("=") {
+                self.result.push(' ');
+                self.consume();
+                self.result.push(' ');
+                let expr = self.expression();
+                value = match self.fold(&expr, "enumerator value") {
+                    Number::Integer { value, .. } => {
+                        value.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+                    }
+                    _ => {
+                        self.idx -= 1;
+                        self.panic_with_error("enumerator value must be an integer")
+                    }
+                };
+            }
+            if value < i32::MIN as i64 || value > i32::MAX as i64 {
+                self.idx -= 1;
+                self.panic_with_error("enumerator value does not fit in an int");
+            }
+            self.scopes.last_mut().unwrap().insert(
+                String::from(token.get_text()),
+                ScopeEntry::Enumerator(value),
+            );
+            enumerators.push(Enumerator {
+                name: String::from(token.get_text()),
+                value,
+                span: token.get_span(),
+            });
+            value += 1;
+            if !self.is_text("}") {
+                self.expect(",", "enum declaration");
+            }
+            self.new_line();
+        }
+        if enumerators.is_empty() {
+            self.panic_with_error("an enum needs at least one enumerator");
+        }
+
+        self.indent(indent_len);
+        self.expect("}", "enum declaration");
+        self.expect(";", "enum declaration");
+        self.new_line();
+        EnumDeclaration {
+            name,
+            enumerators,
+            span,
+        }
+    }
+
+    // typedef DataType Identifier {[IntConstant]} ;
+    fn typedef_declaration(&mut self, indent_len: i32) -> TypedefDeclaration {
+        self.indent(indent_len);
+        self.consume();
+        self.result.push(' ');
+        let (qualifiers, data_type, name) = self.declaration_type(0);
+        if name.get_type() != &TokenType::VARIABLE {
+            self.idx -= 1;
+            self.panic_with_error("invalid typedef name");
+        }
+        if qualifiers != Qualifiers::default() {
+            self.idx -= 1;
+            self.panic_with_error("typedef names cannot be qualified");
+        }
+        let data_type = self.array_suffix(data_type);
+        self.expect(";", "typedef");
+        self.new_line();
+        self.scopes.last_mut().unwrap().insert(
+            String::from(name.get_text()),
+            ScopeEntry::TypeName(data_type.clone()),
+        );
+        TypedefDeclaration {
+            name: String::from(name.get_text()),
+            data_type,
+            span: name.get_span(),
+        }
+    }
+
+    fn main_declaration(&mut self) -> Function {
+        let span = self.current().get_span();
+        self.expect("void", "main declaration");
+        self.result.push(' ');
+        self.expect("main", "main declaration");
+        self.expect("(", "main declaration");
+        self.expect(")", "main declaration");
+
+        let body = self.block(0);
+        Function {
+            declaration: FunctionDeclaration {
+                name: String::from("main"),
+                return_type: Type::Void,
+                parameters: Vec::new(),
+                qualifiers: Qualifiers::default(),
+                span,
+            },
+            body,
+        }
+    }
+
+    fn function_definition(&mut self) -> Function {
+        let (qualifiers, return_type, name) = self.declaration_type(0);
+        if name.get_type() != &TokenType::FUNCTION {
+            self.idx -= 1;
+            self.panic_with_error("invalid function definition");
+        }
+        if qualifiers.storage == StorageClass::Extern {
+            self.idx -= 1;
+            self.panic_with_error("a function definition cannot be extern");
+        }
+        self.scopes.push(HashMap::new());
+        let declaration = self.function_declaration(qualifiers, return_type, name);
+        for parameter in &declaration.parameters {
+            self.declare(&parameter.name, &parameter.data_type, parameter.qualifiers);
+        }
+        let body = self.block_in_scope(0);
+        self.scopes.pop();
+        Function { declaration, body }
+    }
+
+    // DataType Identifier, the identifier token is returned for the caller to classify
+    // {Qualifier} DataType {*} Identifier, the identifier token is returned for the caller to classify
+    fn declaration_type(&mut self, indent_len: i32) -> (Qualifiers, Type, Token) {
+        self.indent(indent_len);
+        let qualifiers = self.qualifiers();
+        let data_type = if self.is_text("void") {
+            self.consume();
             self.result.push(' ');
-            self.parameter();
-            self.idx += 1;
+            Type::Void
+        } else {
+            self.data_type(0)
+        };
+        // { * }
+        let mut data_type = data_type;
+        while self.is_text("*") {
+            self.consume();
+            data_type = Type::Pointer(Box::new(data_type));
+        }
+        // Identifier
+        let name = self.current().clone();
+        if name.get_type() != &TokenType::VARIABLE && name.get_type() != &TokenType::FUNCTION {
+            self.panic_with_error("expected identifier");
+        }
+        if data_type == Type::Void && name.get_type() != &TokenType::FUNCTION {
+            self.panic_with_error("only functions may be declared void");
         }
+        if qualifiers.is_const && name.get_type() == &TokenType::FUNCTION {
+            self.panic_with_error("functions cannot be const");
+        }
+        self.consume();
+        (qualifiers, data_type, name)
+    }
 
-        if self.idx >= self.tokens.len() {
-            return;
+    // { const | static | extern }
+    fn qualifiers(&mut self) -> Qualifiers {
+        let mut qualifiers = Qualifiers::default();
+        while !self.is_end() && self.current().get_type() == &TokenType::QUALIFIER {
+            let token = self.current().clone();
+            match token.get_text() {
+                "const" if !qualifiers.is_const => qualifiers.is_const = true,
+                "static" if qualifiers.storage == StorageClass::Auto => {
+                    qualifiers.storage = StorageClass::Static
+                }
+                "extern" if qualifiers.storage == StorageClass::Auto => {
+                    qualifiers.storage = StorageClass::Extern
+                }
+                "const" => self.panic_with_error("duplicate const"),
+                _ => self.panic_with_error("more than one storage class"),
+            }
+            self.consume();
+            self.result.push(' ');
+        }
+        qualifiers
+    }
+
+    fn variable_declaration(
+        &mut self,
+        qualifiers: Qualifiers,
+        data_type: Type,
+        name: Token,
+    ) -> VariableDeclaration {
+        let data_type = self.array_suffix(data_type);
+        self.check_complete(&data_type);
+        let mut initializer = None;
+        if self.is_text("=") {
+            if qualifiers.storage == StorageClass::Extern {
+                self.panic_with_error("an extern declaration cannot have an initialiser");
+            }
+            self.result.push(' ');
+            self.consume();
+            self.result.push(' ');
+            // globals and statics are set before the program runs
+            let constant = self.scopes.len() == 1 || qualifiers.storage == StorageClass::Static;
+            initializer = Some(self.initializer(&data_type, constant));
+        } else if qualifiers.is_const && qualifiers.storage != StorageClass::Extern {
+            self.panic_with_error(&format!(
+                "const variable {} needs an initialiser",
+                name.get_text()
+            ));
         }
 
-        // if break out of loop by line change, an extra 1 is added
-        if self.is_line_changed() {
+        // `int buf[] = {1, 2}` takes its size from the initialiser
+        let data_type = match (data_type, &initializer) {
+            (Type::Array(element, None), Some(Initializer::List(items, _))) => {
+                Type::Array(element, Some(items.len()))
+            }
+            (data_type, _) => data_type,
+        };
+        self.declare(name.get_text(), &data_type, qualifiers);
+
+        VariableDeclaration {
+            name: String::from(name.get_text()),
+            data_type,
+            initializer,
+            qualifiers,
+            span: name.get_span(),
+        }
+    }
+
+    // { [ [IntConstant] ] }, applied to an element type
+    fn array_suffix(&mut self, element: Type) -> Type {
+        let mut sizes = Vec::new();
+        while self.is_text("[") {
+            self.consume();
+            if !self.is_text("]") {
+                let expr = self.expression();
+                let size = match self.fold(&expr, "array size") {
+                    Number::Integer { value, .. } => value,
+                    _ => self.panic_with_error("array size must be an integer"),
+                };
+                if size <= 0 {
+                    self.idx -= 1;
+                    self.panic_with_error("array size must be positive");
+                }
+                sizes.push(Some(size as usize));
+            } else {
+                sizes.push(None);
+            }
+            self.expect("]", "array declaration");
+        }
+        if sizes.iter().skip(1).any(|size| size.is_none()) {
             self.idx -= 1;
+            self.panic_with_error("only the first array dimension may be left out");
         }
+        // int m[2][3] is an array of 2 arrays of 3 ints
+        sizes.into_iter().rev().fold(element, |data_type, size| {
+            Type::Array(Box::new(data_type), size)
+        })
+    }
 
-        // self.result.push(')');
-        self.result
-            .push_str("<font color=\"white\"><b>)</b></font>");
+    // Expression | { Initializer {, Initializer} }, where `constant` requires constant expressions
+    fn initializer(&mut self, data_type: &Type, constant: bool) -> Initializer {
+        match data_type {
+            Type::Array(element, size) => {
+                let span = self.current().get_span();
+                if !self.is_text("{") {
+                    self.panic_with_error("array initialiser must be a list");
+                }
+                self.consume();
+                let mut items = Vec::new();
+                if !self.is_text("}") {
+                    items.push(self.initializer(element, constant));
+                    while self.is_text(",") {
+                        self.consume();
+                        self.result.push(' ');
+                        items.push(self.initializer(element, constant));
+                    }
+                }
+                if let Some(size) = size {
+                    if items.len() > *size {
+                        self.panic_with_error("too many elements in array initialiser");
+                    }
+                }
+                self.expect("}", "initialiser list");
+                Initializer::List(items, span)
+            }
+            Type::Struct(name) => {
+                // one initialiser per member, in declaration order
+                let span = self.current().get_span();
+                let members = self.lookup_struct(name).cloned().unwrap_or_default();
+                if !self.is_text("{") {
+                    self.panic_with_error("struct initialiser must be a list");
+                }
+                self.consume();
+                let mut items = Vec::new();
+                for (i, member) in members.iter().enumerate() {
+                    if self.is_text("}") {
+                        break;
+                    }
+                    if i > 0 {
+                        self.expect(",", "initialiser list");
+                        self.result.push(' ');
+                    }
+                    items.push(self.initializer(&member.data_type, constant));
+                }
+                if !self.is_text("}") {
+                    self.panic_with_error("too many elements in struct initialiser");
+                }
+                self.expect("}", "initialiser list");
+                Initializer::List(items, span)
+            }
+            _ => {
+                if self.is_text("{") {
+                    self.panic_with_error("list initialiser for a scalar variable");
+                }
+                let expr = self.expression();
+                if constant {
+                    self.fold(&expr, "initialiser of a global or static variable");
+                }
+                Initializer::Expression(expr)
+            }
+        }
+    }
+
+    fn function_declaration(
+        &mut self,
+        qualifiers: Qualifiers,
+        return_type: Type,
+        name: Token,
+    ) -> FunctionDeclaration {
+        let parameters = self.parameter_block();
+        FunctionDeclaration {
+            name: String::from(name.get_text()),
+            return_type,
+            parameters,
+            qualifiers,
+            span: name.get_span(),
+        }
+    }
+
+    fn block(&mut self, indent_len: i32) -> Block {
+        self.scopes.push(HashMap::new());
+        let block = self.block_in_scope(indent_len);
+        self.scopes.pop();
+        block
+    }
+
+    // { {Statement} }, declarations may appear among the statements;
+    // a function body shares the scope its parameters were declared in
+    fn block_in_scope(&mut self, indent_len: i32) -> Block {
+        let span = self.current().get_span();
+        self.expect("{", "block");
+        self.new_line();
+
+        let mut statements = Vec::new();
+        while !self.is_text("}") {
+            statements.push(self.statement(indent_len + 1));
+        }
+
+        self.indent(indent_len);
+        self.expect("}", "block");
+        self.new_line();
+        Block { statements, span }
+    }
+
+    fn parameter_block(&mut self) -> Vec<Parameter> {
+        self.expect("(", "parameter block");
+
+        // [Parameter {, Parameter}]
+        let mut parameters = Vec::new();
+        if !self.is_text(")") {
+            parameters.push(self.parameter());
+            while self.is_text(",") {
+                self.consume();
+                self.result.push(' ');
+                parameters.push(self.parameter());
+            }
+        }
+
+        self.expect(")", "parameter block");
+        parameters
     }
 
-    fn data_type(&mut self, indent_len: i32) {
-        let token: Token = self.tokens[self.idx].clone();
-        let int_types = vec!["char", "short", "int", "long"];
-        let float_types = vec!["float", "double"];
-        if &*token.get_text() == "unsigned" {
+    fn data_type(&mut self, indent_len: i32) -> Type {
+        let token: Token = self.current().clone();
+        let int_types = ["char", "short", "int", "long"];
+        let float_types = ["float", "double"];
+        if token.get_text() == "struct" {
+            // struct Identifier
+            self.indent(indent_len);
+            self.consume();
+            self.result.push(' ');
+            let name = self.current().clone();
+            if name.get_type() != &TokenType::VARIABLE {
+                self.panic_with_error("expected struct name");
+            }
+            self.consume();
+            self.result.push(' ');
+            Type::Struct(String::from(name.get_text()))
+        } else if token.get_text() == "enum" {
+            // enum Identifier, an enum variable holds an int
+            self.indent(indent_len);
+            self.consume();
+            self.result.push(' ');
+            let name = self.current().clone();
+            if self
+                .lookup_entry(&format!("enum {}", name.get_text()))
+                .is_none()
+            {
+                self.panic_with_error(&format!("enum {} is not defined", name.get_text()));
+            }
+            self.consume();
+            self.result.push(' ');
+            Type::Integer {
+                kind: IntKind::Int,
+                unsigned: false,
+            }
+        } else if let Some(data_type) = self.lookup_type_name(token.get_text()).cloned() {
+            // typedef name, replaced by the type it stands for
             self.indent(indent_len);
-            // self.result.push_str("unsigned ");
-            self.result
-                .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+            self.consume();
             self.result.push(' ');
-            self.idx += 1;
-            self.data_type(indent_len);
+            data_type
+        } else if token.get_text() == "unsigned" {
+            self.indent(indent_len);
+            self.consume();
+            self.result.push(' ');
+            // `unsigned` on its own is an unsigned int
+            if !self.current().is_type() {
+                return Type::Integer {
+                    kind: IntKind::Int,
+                    unsigned: true,
+                };
+            }
+            match self.data_type(0) {
+                Type::Integer { kind, .. } => Type::Integer {
+                    kind,
+                    unsigned: true,
+                },
+                _ => {
+                    self.idx -= 1;
+                    self.panic_with_error("unsigned applies to integer types only")
+                }
+            }
         } else if int_types.contains(&token.get_text()) {
-            self.integer_type(indent_len);
+            self.integer_type(indent_len)
         } else if float_types.contains(&token.get_text()) {
-            self.float_type(indent_len);
+            self.float_type(indent_len)
         } else {
-            self.panic_with_error("invalid data type");
+            self.panic_with_error("invalid data type")
+        }
+    }
+
+    fn int_constant(&mut self) -> u64 {
+        let token = self.current().clone();
+        match token.get_text().parse::<u64>() {
+            Ok(n) => {
+                self.consume();
+                n
+            }
+            Err(_) => self.panic_with_error("integer constant out of range"),
         }
     }
 
-    fn constant(&mut self) {
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        match self.tokens[self.idx].get_type() {
+    fn constant(&mut self) -> Expression {
+        let token = self.current().clone();
+        match token.get_type() {
             TokenType::OPERATOR => {
-                if self.tokens[self.idx].get_text() == "-" {
-                    self.result
-                        .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
-                    self.idx += 1;
-                    self.constant();
+                if token.get_text() == "-" {
+                    self.consume();
+                    let operand = self.constant();
+                    Expression::new(
+                        ExpressionKind::Unary {
+                            operator: UnaryOperator::Negate,
+                            operand: Box::new(operand),
+                        },
+                        token.get_span(),
+                    )
                 } else {
-                    self.panic_with_error("unsupported unary operator");
+                    self.panic_with_error("unsupported unary operator")
                 }
             }
-            TokenType::INTCONSTANT => self
-                .result
-                .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone())),
-            TokenType::FLOATCONSTANT => self
-                .result
-                .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone())),
+            TokenType::INTCONSTANT => {
+                let n = self.int_constant();
+                Expression::new(ExpressionKind::IntConstant(n), token.get_span())
+            }
+            TokenType::FLOATCONSTANT => match token.get_text().parse::<f64>() {
+                Ok(x) => {
+                    self.consume();
+                    Expression::new(ExpressionKind::FloatConstant(x), token.get_span())
+                }
+                Err(_) => self.panic_with_error("invalid float constant"),
+            },
+            TokenType::VARIABLE | TokenType::ENUMCONSTANT if self.is_enumerator() => {
+                self.enumerator()
+            }
             _ => self.panic_with_error("unsupported constant"),
         }
     }
 
-    fn statement(&mut self, indent_len: i32) {
-        let token = self.tokens[self.idx].clone();
-        if token.get_type() == &TokenType::VARIABLE {
-            self.assignment(indent_len);
-            // self.result.push_str(";\n");
-            self.result
-                .push_str("<font color=\"white\"><b>;</b></font><br />");
+    fn statement(&mut self, indent_len: i32) -> Statement {
+        let token = self.current().clone();
+        let kind = if self.is_declaration_start() {
+            StatementKind::Declaration(self.declaration(indent_len))
+        } else if self.is_text("{") {
+            self.indent(indent_len);
+            StatementKind::Block(self.block(indent_len))
+        } else if self.is_assignment_start() {
+            let kind = self.assignment(indent_len);
+            self.expect(";", "assignment");
+            self.new_line();
+            kind
+        } else if token.get_type() == &TokenType::FUNCTION {
+            // Call ;
+            self.indent(indent_len);
+            let call = self.factor();
+            self.expect(";", "call statement");
+            self.new_line();
+            StatementKind::Call(call)
         } else {
             match token.get_text() {
                 "while" => self.while_loop(indent_len),
+                "for" => self.for_loop(indent_len),
                 "if" => self.if_statement(indent_len),
+                "switch" => self.switch_statement(indent_len),
                 "return" => {
-                    self.return_statement(indent_len);
-                    // self.result.push_str(";\n");
-                    self.result
-                        .push_str("<font color=\"white\"><b>;</b></font><br />");
+                    let kind = self.return_statement(indent_len);
+                    self.expect(";", "return statement");
+                    self.new_line();
+                    kind
                 }
-                _ => {}
+                "break" => {
+                    if self.breakable == 0 {
+                        self.panic_with_error("break statement not within a loop or switch");
+                    }
+                    self.indent(indent_len);
+                    self.consume();
+                    self.expect(";", "break statement");
+                    self.new_line();
+                    StatementKind::Break
+                }
+                _ => self.panic_with_error("invalid statement"),
             }
+        };
+        Statement {
+            kind,
+            span: token.get_span(),
         }
     }
 
-    fn parameter(&mut self) {
-        self.declaration_type(0);
+    fn parameter(&mut self) -> Parameter {
+        let (qualifiers, data_type, name) = self.declaration_type(0);
+        if name.get_type() != &TokenType::VARIABLE {
+            self.idx -= 1;
+            self.panic_with_error("invalid parameter name");
+        }
+        if qualifiers.storage != StorageClass::Auto {
+            self.idx -= 1;
+            self.panic_with_error("parameters cannot have a storage class");
+        }
+        // int a[] is allowed as a parameter, the caller decides the size
+        let data_type = self.array_suffix(data_type);
+        self.check_complete(&data_type);
+        Parameter {
+            name: String::from(name.get_text()),
+            data_type,
+            qualifiers,
+            span: name.get_span(),
+        }
     }
 
-    fn integer_type(&mut self, indent_len: i32) {
+    fn integer_type(&mut self, indent_len: i32) -> Type {
         self.indent(indent_len);
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        let token = self.consume();
         self.result.push(' ');
+        let kind = match token.get_text() {
+            "char" => IntKind::Char,
+            "short" => IntKind::Short,
+            "long" => IntKind::Long,
+            _ => IntKind::Int,
+        };
+        Type::Integer {
+            kind,
+            unsigned: false,
+        }
     }
 
-    fn float_type(&mut self, indent_len: i32) {
+    fn float_type(&mut self, indent_len: i32) -> Type {
         self.indent(indent_len);
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        let token = self.consume();
         self.result.push(' ');
+        match token.get_text() {
+            "double" => Type::Double,
+            _ => Type::Float,
+        }
+    }
+
+    // Identifier { [ Expression ] | . Identifier }
+    fn variable(&mut self) -> Expression {
+        let token = self.consume();
+        let expr = Expression::new(
+            ExpressionKind::Variable(String::from(token.get_text())),
+            token.get_span(),
+        );
+        self.postfix(expr)
+    }
+
+    fn postfix(&mut self, mut expr: Expression) -> Expression {
+        let span = expr.span;
+        loop {
+            if self.is_text("[") {
+                self.consume();
+                let index = self.expression();
+                self.expect("]", "array index");
+                self.check_bounds(&expr, &index);
+                expr = Expression::new(
+                    ExpressionKind::Index {
+                        array: Box::new(expr),
+                        index: Box::new(index),
+                    },
+                    span,
+                );
+            } else if self.is_text(".") {
+                self.consume();
+                let member = self.current().clone();
+                if member.get_type() != &TokenType::VARIABLE {
+                    self.panic_with_error("expected member name");
+                }
+                self.check_member(&expr, member.get_text());
+                self.consume();
+                expr = Expression::new(
+                    ExpressionKind::Member {
+                        base: Box::new(expr),
+                        member: String::from(member.get_text()),
+                    },
+                    span,
+                );
+            } else {
+                return expr;
+            }
+        }
     }
 
-    fn assignment(&mut self, indent_len: i32) {
+    fn check_member(&self, base: &Expression, member: &str) {
+        let data_type = match self.static_type(base) {
+            Some(data_type) => data_type,
+            None => return,
+        };
+        let name = match &data_type {
+            Type::Struct(name) => name,
+            _ => self.panic_with_error(&format!("{} is not a struct", data_type)),
+        };
+        let has_member = match self.lookup_struct(name) {
+            Some(members) => members.iter().any(|m| m.name == member),
+            None => self.panic_with_error(&format!("struct {} is not defined", name)),
+        };
+        if !has_member {
+            self.panic_with_error(&format!("struct {} has no member named {}", name, member));
+        }
+    }
+
+    // constant indexes into arrays of constant size are checked right away
+    fn check_bounds(&mut self, array: &Expression, index: &Expression) {
+        let data_type = match self.static_type(array) {
+            Some(data_type) => data_type,
+            None => return,
+        };
+        if !data_type.is_array() && !data_type.is_pointer() {
+            self.idx -= 1;
+            self.panic_with_error("indexed variable is not an array or pointer");
+        }
+        if let (Type::Array(_, Some(size)), Some(n)) = (data_type, index.as_int_constant()) {
+            if n < 0 || n >= size as i128 {
+                self.idx -= 1;
+                self.panic_with_error(&format!(
+                    "array index {} out of bounds for size {}",
+                    n, size
+                ));
+            }
+        }
+    }
+
+    fn is_assignment_start(&self) -> bool {
+        !self.is_end() && (self.current().get_type() == &TokenType::VARIABLE || self.is_text("*"))
+    }
+
+    // try to read `Variable =` or `*Factor =`, putting everything back if there is no `=`
+    fn assignment_target(&mut self) -> Option<Expression> {
+        if !self.is_assignment_start() {
+            return None;
+        }
+        let idx = self.idx;
+        let len = self.result.len();
+        let target = if self.is_text("*") {
+            self.unary_expression()
+        } else if self.is_enumerator() {
+            self.enumerator()
+        } else {
+            self.variable()
+        };
+        if self.is_text("=") {
+            if !target.is_lvalue() {
+                self.panic_with_error("invalid assignment target");
+            }
+            self.check_assignable(&target);
+            self.result.push(' ');
+            self.consume();
+            self.result.push(' ');
+            Some(target)
+        } else {
+            self.idx = idx;
+            self.result.truncate(len);
+            None
+        }
+    }
+
+    fn check_assignable(&self, target: &Expression) {
+        let name = match Parser::root_variable(target) {
+            Some(name) => name,
+            None => return,
+        };
+        if let Some(ScopeEntry::Variable(_, qualifiers)) = self.lookup_entry(name) {
+            if qualifiers.is_const {
+                self.panic_with_error(&format!("cannot assign to const variable {}", name));
+            }
+        }
+    }
+
+    fn assignment(&mut self, indent_len: i32) -> StatementKind {
         self.indent(indent_len);
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
-        if self.tokens[self.idx + 1].get_text() != "=" {
-            self.panic_with_error("invalid assignment");
+        let mut targets = Vec::new();
+        match self.assignment_target() {
+            Some(target) => targets.push(target),
+            None => self.panic_with_error("invalid assignment"),
         }
-        self.idx += 1;
-        // self.result.push_str(" = ");
-        self.result.push(' ');
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
-        self.result.push(' ');
-        self.idx += 1;
 
         // {Identifier =}
-        while self.idx < self.tokens.len()
-            && self.tokens[self.idx].get_type() == &TokenType::VARIABLE
-            && self.tokens[self.idx + 1].get_text() == "="
-        {
-            self.result.push_str(self.tokens[self.idx].get_text());
-            self.idx += 1;
-            // self.result.push_str(" = ");
+        while let Some(target) = self.assignment_target() {
+            targets.push(target);
+        }
+
+        let value = self.expression();
+        StatementKind::Assignment { targets, value }
+    }
+
+    fn while_loop(&mut self, indent_len: i32) -> StatementKind {
+        self.indent(indent_len);
+        self.consume();
+        self.result.push(' ');
+        self.expect("(", "while loop");
+        let condition = self.expression();
+        self.expect(")", "while loop");
+        self.breakable += 1;
+        let body = self.block(indent_len);
+        self.breakable -= 1;
+        StatementKind::While { condition, body }
+    }
+
+    fn for_loop(&mut self, indent_len: i32) -> StatementKind {
+        self.indent(indent_len);
+        self.consume();
+        self.result.push(' ');
+        self.expect("(", "for loop");
+        // [Assignment | Call] ; [Expression] ; [Assignment | Call]
+        let init = match self.is_text(";") {
+            true => None,
+            false => Some(Box::new(self.simple_statement())),
+        };
+        self.expect(";", "for loop");
+        if !self.is_text(";") {
             self.result.push(' ');
-            self.result
-                .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        }
+        let condition = match self.is_text(";") {
+            true => None,
+            false => Some(self.expression()),
+        };
+        self.expect(";", "for loop");
+        if !self.is_text(")") {
             self.result.push(' ');
-            self.idx += 1;
         }
+        let step = match self.is_text(")") {
+            true => None,
+            false => Some(Box::new(self.simple_statement())),
+        };
+        self.expect(")", "for loop");
+        self.breakable += 1;
+        let body = self.block(indent_len);
+        self.breakable -= 1;
+        StatementKind::For {
+            init,
+            condition,
+            step,
+            body,
+        }
+    }
 
-        self.expression();
+    // an assignment or a call without its `;`, as in the head of a for loop
+    fn simple_statement(&mut self) -> Statement {
+        let token = self.current().clone();
+        let kind = if self.is_assignment_start() {
+            self.assignment(0)
+        } else if token.get_type() == &TokenType::FUNCTION {
+            StatementKind::Call(self.factor())
+        } else {
+            self.panic_with_error("expected an assignment or a call")
+        };
+        Statement {
+            kind,
+            span: token.get_span(),
+        }
     }
 
-    fn while_loop(&mut self, indent_len: i32) {
+    fn if_statement(&mut self, indent_len: i32) -> StatementKind {
         self.indent(indent_len);
-        // self.result.push_str("while (");
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        self.consume();
         self.result.push(' ');
-        self.result
-            .push_str("<font color=\"white\"><b>(</b></font>");
-        self.idx += 1;
-        self.expression();
-        // self.result.push_str(") ");
-        self.result
-            .push_str("<font color=\"white\"><b>)</b></font>");
-        self.idx += 1;
-        if self.idx == self.tokens.len() {
-            self.idx -= 1;
-        }
-        self.block(indent_len);
+        self.expect("(", "if statement");
+        let condition = self.expression();
+        self.expect(")", "if statement");
+        let body = self.block(indent_len);
+        StatementKind::If { condition, body }
     }
 
-    fn if_statement(&mut self, indent_len: i32) {
+    // switch ( Expression ) { {Label {Label} Statement {Statement}} },
+    // Label := case ConstantExpression : | default :
+    fn switch_statement(&mut self, indent_len: i32) -> StatementKind {
         self.indent(indent_len);
-        // self.result.push_str("if (");
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        self.consume();
         self.result.push(' ');
-        self.result
-            .push_str("<font color=\"white\"><b>(</b></font>");
-        self.idx += 1;
-        self.expression();
-        // self.result.push_str(") ");
-        self.result
-            .push_str("<font color=\"white\"><b>)</b></font>");
-        self.idx += 1;
-        if self.idx == self.tokens.len() {
-            self.idx -= 1;
+        self.expect("(", "switch statement");
+        let value = self.expression();
+        self.expect(")", "switch statement");
+        self.scopes.push(HashMap::new());
+        let span = self.current().get_span();
+        self.expect("{", "switch statement");
+        self.new_line();
+
+        let mut statements = Vec::new();
+        let mut cases: Vec<Case> = Vec::new();
+        let mut values = HashSet::new();
+        self.breakable += 1;
+        while !self.is_text("}") {
+            if self.is_text("case") || self.is_text("default") {
+                let token = self.current().clone();
+                self.indent(indent_len + 1);
+                self.consume();
+                let label = match token.get_text() {
+                    "case" => {
+                        self.result.push(' ');
+                        let label = self.expression();
+                        match self.fold(&label, "case label") {
+                            Number::Integer { value, .. } => {
+                                if !values.insert(value) {
+                                    self.panic_with_error(&format!(
+                                        "duplicate case value {}",
+                                        value
+                                    ));
+                                }
+                            }
+                            _ => self.panic_with_error("case label must be an integer"),
+                        }
+                        Some(label)
+                    }
+                    _ => {
+                        if cases.iter().any(|case| case.label.is_none()) {
+                            self.panic_with_error("multiple default labels in one switch");
+                        }
+                        None
+                    }
+                };
+                self.expect(":", "case label");
+                self.new_line();
+                cases.push(Case {
+                    label,
+                    position: statements.len(),
+                    span: token.get_span(),
+                });
+            } else if cases.is_empty() {
+                self.panic_with_error("expected case or default label");
+            } else if self.is_declaration_start() {
+                self.panic_with_error("declarations in a switch must be inside a block");
+            } else {
+                statements.push(self.statement(indent_len + 2));
+            }
+        }
+        self.breakable -= 1;
+
+        self.indent(indent_len);
+        self.expect("}", "switch statement");
+        self.new_line();
+        self.scopes.pop();
+        StatementKind::Switch {
+            value,
+            body: Block { statements, span },
+            cases,
         }
-        self.block(indent_len);
     }
 
-    fn return_statement(&mut self, indent_len: i32) {
+    fn return_statement(&mut self, indent_len: i32) -> StatementKind {
         self.indent(indent_len);
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        self.consume();
+        if self.is_text(";") {
+            return StatementKind::Return(None);
+        }
         self.result.push(' ');
-        self.idx += 1;
-        self.expression();
+        StatementKind::Return(Some(self.expression()))
+    }
+
+    fn binary(&self, token: &Token, left: Expression, right: Expression) -> Expression {
+        let operator = BinaryOperator::from_text(token.get_text()).unwrap();
+        self.check_pointer_arithmetic(operator, &left, &right);
+        // errors about the operation point at its operator
+        let span = token.get_span();
+        Expression::new(
+            ExpressionKind::Binary {
+                operator,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+            span,
+        )
+    }
+
+    // pointers may only be offset by integers, subtracted and compared
+    fn check_pointer_arithmetic(
+        &self,
+        operator: BinaryOperator,
+        left: &Expression,
+        right: &Expression,
+    ) {
+        let is_pointer = |expr: &Expression| match self.static_type(expr) {
+            Some(data_type) => data_type.is_pointer() || data_type.is_array(),
+            None => false,
+        };
+        let (left_pointer, right_pointer) = (is_pointer(left), is_pointer(right));
+        if !left_pointer && !right_pointer {
+            return;
+        }
+        let valid = match operator {
+            BinaryOperator::Add => !(left_pointer && right_pointer),
+            BinaryOperator::Subtract => left_pointer,
+            BinaryOperator::Multiply | BinaryOperator::Divide => false,
+            _ => true,
+        };
+        if !valid {
+            self.panic_with_error(&format!(
+                "invalid operands to '{}' with pointer type",
+                operator.as_str()
+            ));
+        }
     }
 
-    fn expression(&mut self) {
-        self.simple_expression();
+    fn expression(&mut self) -> Expression {
+        let left = self.simple_expression();
         // [ RelationalOperator SimpleExpression ]
-        if self.idx + 1 < self.tokens.len() && self.tokens[self.idx + 1].is_relational_op() {
-            self.idx += 1;
-            self.relational_operator();
-            self.idx += 1;
-            self.simple_expression();
+        if !self.is_end() && self.current().is_relational_op() {
+            let operator = self.relational_operator();
+            let right = self.simple_expression();
+            return self.binary(&operator, left, right);
         }
+        left
     }
 
-    fn simple_expression(&mut self) {
-        self.term();
+    fn simple_expression(&mut self) -> Expression {
+        let mut left = self.term();
 
         // { AddOperator Term }
-        while self.idx + 1 < self.tokens.len() && self.tokens[self.idx + 1].is_add_op() {
-            self.idx += 1;
-            self.add_operator();
-            self.idx += 1;
-            self.term();
+        while !self.is_end() && self.current().is_add_op() {
+            let operator = self.add_operator();
+            let right = self.term();
+            left = self.binary(&operator, left, right);
         }
+        left
     }
 
-    fn term(&mut self) {
-        self.factor();
+    fn term(&mut self) -> Expression {
+        let mut left = self.factor();
         // { MultOperator Factor }
-        while self.idx + 1 < self.tokens.len() && self.tokens[self.idx + 1].is_mult_op() {
-            self.idx += 1;
-            self.mult_operator();
-            self.idx += 1;
-            self.factor();
+        while !self.is_end() && self.current().is_mult_op() {
+            let operator = self.mult_operator();
+            let right = self.factor();
+            left = self.binary(&operator, left, right);
         }
+        left
     }
 
-    fn factor(&mut self) {
-        let token: Token = self.tokens[self.idx].clone();
+    fn factor(&mut self) -> Expression {
+        let token: Token = self.current().clone();
         match token.get_type() {
             TokenType::INTCONSTANT => self.constant(),
-            TokenType::OPERATOR => self.constant(),
             TokenType::FLOATCONSTANT => self.constant(),
-            TokenType::VARIABLE => {
-                // self.result.push_str(token.get_text());
-                self.result
-                    .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+            TokenType::STRINGCONSTANT => self.string_constant(),
+            TokenType::OPERATOR if token.is_unary_op() => self.unary_expression(),
+            TokenType::OPERATOR if token.get_text() == "(" && self.is_type_name_ahead(1) => {
+                self.cast_expression()
+            }
+            TokenType::KEYWORD if token.get_text() == "sizeof" => self.sizeof_expression(),
+            TokenType::OPERATOR if token.get_text() == "(" => {
+                // ( Expression )
+                self.consume();
+                let expr = self.expression();
+                self.expect(")", "parenthesised expression");
+                self.postfix(expr)
+            }
+            TokenType::VARIABLE | TokenType::ENUMCONSTANT if self.is_enumerator() => {
+                self.enumerator()
             }
+            TokenType::VARIABLE => self.variable(),
             TokenType::FUNCTION => {
-                // self.result.push_str(token.get_text());
-                self.result
-                    .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
-                self.idx += 1;
-                // self.result.push('(');
-                self.result
-                    .push_str("<font color=\"white\"><b>(</b></font>");
-                self.expression();
-
-                // {, Expression}
-                let token: Token = self.tokens[self.idx + 1].clone();
-                let parameter_types = vec![
-                    TokenType::FLOATCONSTANT,
-                    TokenType::INTCONSTANT,
-                    TokenType::VARIABLE,
-                ];
-                if parameter_types.contains(token.get_type())
-                    && self.tokens[self.idx].get_line_number() == token.get_line_number()
-                {
-                    self.idx += 1;
-                    // self.result.push_str(", "); // !
-                    self.result
-                        .push_str("<font color=\"white\"><b>,</b></font>");
-                    self.result.push(' ');
-                    self.expression();
+                self.consume();
+                self.expect("(", "function call");
+
+                // [Expression {, Expression}]
+                let mut arguments = Vec::new();
+                if !self.is_text(")") {
+                    arguments.push(self.expression());
+                    while self.is_text(",") {
+                        self.consume();
+                        self.result.push(' ');
+                        arguments.push(self.expression());
+                    }
                 }
-                self.result.push(')');
+                self.expect(")", "function call");
+                Expression::new(
+                    ExpressionKind::Call {
+                        name: String::from(token.get_text()),
+                        arguments,
+                    },
+                    token.get_span(),
+                )
             }
-            _ => {
-                // Todo: ( ( Expression ) )
+            _ => self.panic_with_error("invalid factor"),
+        }
+    }
+
+    // " {Character} ", replacing the escape sequences \n, \t, \0, \\ and \"
+    fn string_constant(&mut self) -> Expression {
+        let token = self.consume();
+        let quoted = token.get_text();
+        let mut text = String::new();
+        let mut chars = quoted[1..quoted.len() - 1].chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\\' {
+                text.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some('0') => text.push('\0'),
+                Some('\\') => text.push('\\'),
+                Some('"') => text.push('"'),
+                other => {
+                    self.idx -= 1;
+                    self.panic_with_error(&format!(
+                        "unknown escape sequence \\{}",
+                        other.map(String::from).unwrap_or_default()
+                    ));
+                }
+            }
+        }
+        Expression::new(ExpressionKind::StringConstant(text), token.get_span())
+    }
+
+    // DataType {*}, as written in casts and sizeof
+    fn type_name(&mut self) -> Type {
+        let mut data_type = if self.is_text("void") {
+            self.consume();
+            Type::Void
+        } else {
+            let data_type = self.data_type(0);
+            // data_type leaves a space for the identifier that a type name does not have
+            if self.result.ends_with(' ') {
+                self.result.pop();
+            }
+            data_type
+        };
+        while self.is_text("*") {
+            self.consume();
+            data_type = Type::Pointer(Box::new(data_type));
+        }
+        data_type
+    }
+
+    // ( DataType ) Factor, told apart from ( Expression ) by the type name after the parenthesis
+    fn cast_expression(&mut self) -> Expression {
+        let span = self.consume().get_span();
+        let data_type = self.type_name();
+        self.expect(")", "cast");
+        self.result.push(' ');
+        if !data_type.is_scalar() && data_type != Type::Void {
+            self.idx -= 1;
+            self.panic_with_error(&format!("cannot cast to {}", data_type));
+        }
+        let operand = self.factor();
+        if let Some(from) = self.static_type(&operand) {
+            if matches!(from, Type::Struct(_)) {
+                self.panic_with_error(&format!("cannot cast {} to {}", from, data_type));
             }
         }
+        Expression::new(
+            ExpressionKind::Cast {
+                data_type,
+                operand: Box::new(operand),
+            },
+            span,
+        )
     }
 
-    fn relational_operator(&mut self) {
-        if !self.tokens[self.idx].is_relational_op() {
+    // sizeof ( DataType ) | sizeof Factor
+    fn sizeof_expression(&mut self) -> Expression {
+        let span = self.consume().get_span();
+        if self.is_text("(") && self.is_type_name_ahead(1) {
+            self.consume();
+            let data_type = self.type_name();
+            if self.size_of(&data_type).is_none() {
+                self.panic_with_error(&format!("sizeof applied to incomplete type {}", data_type));
+            }
+            self.expect(")", "sizeof");
+            return Expression::new(ExpressionKind::SizeOfType(data_type), span);
+        }
+        self.result.push(' ');
+        let operand = self.factor();
+        if let Some(data_type) = self.static_type(&operand) {
+            if self.size_of(&data_type).is_none() {
+                self.panic_with_error(&format!("sizeof applied to incomplete type {}", data_type));
+            }
+        }
+        Expression::new(ExpressionKind::SizeOfExpression(Box::new(operand)), span)
+    }
+
+    // UnaryOperator Factor, where a `*` in front of an operand is a dereference
+    fn unary_expression(&mut self) -> Expression {
+        let token = self.consume();
+        let operator = match UnaryOperator::from_text(token.get_text()) {
+            Some(operator) => operator,
+            None => {
+                self.idx -= 1;
+                self.panic_with_error("invalid unary operator")
+            }
+        };
+        let operand = self.factor();
+        if operator == UnaryOperator::AddressOf && !operand.is_lvalue() {
+            self.panic_with_error("cannot take the address of a value");
+        }
+        if operator == UnaryOperator::Dereference {
+            if let Some(data_type) = self.static_type(&operand) {
+                if !data_type.is_pointer() && !data_type.is_array() {
+                    self.panic_with_error("cannot dereference a non-pointer");
+                }
+            }
+        }
+        Expression::new(
+            ExpressionKind::Unary {
+                operator,
+                operand: Box::new(operand),
+            },
+            token.get_span(),
+        )
+    }
+
+    fn relational_operator(&mut self) -> Token {
+        if !self.current().is_relational_op() {
             self.panic_with_error("Invalid relational operator");
         }
         self.result.push(' ');
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        let token = self.consume();
         self.result.push(' ');
+        token
     }
 
-    fn add_operator(&mut self) {
-        if !self.tokens[self.idx].is_add_op() {
+    fn add_operator(&mut self) -> Token {
+        if !self.current().is_add_op() {
             self.panic_with_error("invalid add operator");
         }
         self.result.push(' ');
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        let token = self.consume();
         self.result.push(' ');
+        token
     }
 
-    fn mult_operator(&mut self) {
-        if !self.tokens[self.idx].is_mult_op() {
-            self.panic_with_error("invalid add operator");
+    fn mult_operator(&mut self) -> Token {
+        if !self.current().is_mult_op() {
+            self.panic_with_error("invalid mult operator");
         }
         self.result.push(' ');
-        // self.result.push_str(self.tokens[self.idx].get_text());
-        self.result
-            .push_str(&self.prettifier.prettify(self.tokens[self.idx].clone()));
+        let token = self.consume();
         self.result.push(' ');
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_errors_are_recovered_on_several_threads() {
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    let source = "void main(){\n  int x;\n  x = 1 +;\n}";
+                    Parser::from_source(source, "format.csv").try_parse()
+                })
+            })
+            .collect();
+        for thread in threads {
+            let error = thread.join().unwrap().unwrap_err();
+            assert_eq!(error.message, "invalid factor");
+            assert_eq!(
+                error.span,
+                Span {
+                    line: 3,
+                    column: 10
+                }
+            );
+        }
+        let parser = Parser::from_source("void main(){ }", "format.csv");
+        let variables = [(String::from("x"), Type::Double)];
+        assert!(parser.try_parse_expression("x * 2", &variables).is_ok());
+        let error = parser
+            .try_parse_expression("x + ;", &variables)
+            .unwrap_err();
+        assert_eq!(error.message, "invalid factor");
     }
 }
@@ -2,12 +2,9 @@ use crate::token::Token;
 use crate::token::TokenType;
 
 use std::collections::HashMap;
-use std::fmt;
 use std::fs::File;
-use std::io::prelude::*;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::path::Path;
 
 /// A prettifier for tokens, telling color/font of foreground and background
 #[derive(Clone)]
@@ -19,17 +16,20 @@ pub struct Prettifier {
     variable: String,
     float: String,
     int: String,
+    enum_constant: String,
+    string: String,
     operator: String,
     keyword: String,
+    qualifier: String,
 }
 
 impl Prettifier {
     /// Create a new Prettifier for Tokens
-    /// 
+    ///
     /// # Arguments
-    /// 
+    ///
     /// * `f` - path to the config csv file, `format.csv` in this project
-    /// 
+    ///
     pub fn new(f: &str) -> Prettifier {
         let mut field_map: HashMap<String, usize> = HashMap::new();
         let mut val_map: HashMap<String, Vec<String>> = HashMap::new();
@@ -49,9 +49,8 @@ impl Prettifier {
                                 key = s;
                                 val_map.insert(String::from(s), Vec::new());
                             }
-                            match val_map.get_mut(&String::from(key)) {
-                                Some(vector) => vector.push(String::from(s)),
-                                _ => {}
+                            if let Some(vector) = val_map.get_mut(key) {
+                                vector.push(String::from(s));
                             }
                         }
                     }
@@ -66,6 +65,12 @@ impl Prettifier {
         let get_value = |field: &str, category: &str| {
             val_map.get(category).unwrap()[*field_map.get(field).unwrap()].clone()
         };
+        // newer categories fall back to an older one when format.csv does not list them
+        let get_value_or =
+            |field: &str, category: &str, fallback: &str| match val_map.contains_key(category) {
+                true => get_value(field, category),
+                false => get_value(field, fallback),
+            };
 
         Prettifier {
             background: get_value("BACKGROUND", "DEFAULT"),
@@ -75,59 +80,136 @@ impl Prettifier {
             variable: get_value("FOREGROUND", "VARIABLE"),
             float: get_value("FOREGROUND", "FLOAT_CONSTANT"),
             int: get_value("FOREGROUND", "INT_CONSTANT"),
+            enum_constant: get_value_or("FOREGROUND", "ENUM_CONSTANT", "INT_CONSTANT"),
+            string: get_value_or("FOREGROUND", "STRING_CONSTANT", "INT_CONSTANT"),
             operator: get_value("FOREGROUND", "OPERATOR"),
             keyword: get_value("FOREGROUND", "KEYWORD"),
+            qualifier: get_value_or("FOREGROUND", "QUALIFIER", "KEYWORD"),
         }
     }
 
     /// open tag for html body
     pub fn get_body_open(&self) -> String {
-        let body_open = String::from(format!(
+        format!(
             "<body bgcolor=\"{}\" text=\"{}\" link=\"{}\" vlink=\"{}\">",
             self.background, self.foreground, self.foreground, self.foreground
-        ));
-        body_open
+        )
     }
 
     /// close tag for html body
     pub fn get_body_close(&self) -> String {
-        let body_close = String::from("</body>");
-        body_close
+        String::from("</body>")
     }
 
     /// open tag for html font tag
     pub fn get_font_open(&self) -> String {
-        let font_open = String::from(format!("<font face={}>", self.font));
-        font_open
+        format!("<font face={}>", self.font)
     }
 
     /// close tag for html font tag
     pub fn get_font_close(&self) -> String {
-        let font_close = String::from("</font>");
-        font_close
+        String::from("</font>")
     }
 
     /// get the html format for a token
     pub fn prettify(&self, token: Token) -> String {
         match token.get_type() {
-            &TokenType::FUNCTION => self.format(&self.function, "", token.get_text(), ""),
-            &TokenType::VARIABLE => self.format(&self.variable, "", token.get_text(), ""),
-            &TokenType::FLOATCONSTANT => self.format(&self.float, "<b>", token.get_text(), "</b>"),
-            &TokenType::INTCONSTANT => self.format(&self.int, "<b>", token.get_text(), "</b>"),
-            &TokenType::OPERATOR => self.format(&self.operator, "<b>", token.get_text(), "</b>"),
-            &TokenType::KEYWORD => self.format(&self.keyword, "<b>", token.get_text(), "</b>"),
+            TokenType::FUNCTION => self.format(&self.function, "", token.get_text(), ""),
+            TokenType::VARIABLE => self.format(&self.variable, "", token.get_text(), ""),
+            TokenType::FLOATCONSTANT => self.format(&self.float, "<b>", token.get_text(), "</b>"),
+            TokenType::INTCONSTANT => self.format(&self.int, "<b>", token.get_text(), "</b>"),
+            TokenType::ENUMCONSTANT => {
+                self.format(&self.enum_constant, "<b>", token.get_text(), "</b>")
+            }
+            TokenType::STRINGCONSTANT => self.format(&self.string, "", token.get_text(), ""),
+            TokenType::OPERATOR => self.format(&self.operator, "<b>", token.get_text(), "</b>"),
+            TokenType::KEYWORD => self.format(&self.keyword, "<b>", token.get_text(), "</b>"),
+            TokenType::QUALIFIER => self.format(&self.qualifier, "<b>", token.get_text(), "</b>"),
             _ => self.format(&self.foreground, "", token.get_text(), ""),
         }
     }
 
+    /// get the token colored for a terminal with ANSI escape sequences, in
+    /// the foreground colors of the html and bold where it is bold
+    pub fn ansi(&self, token: &Token) -> String {
+        let (color, bold) = match token.get_type() {
+            TokenType::FUNCTION => (&self.function, false),
+            TokenType::VARIABLE => (&self.variable, false),
+            TokenType::FLOATCONSTANT => (&self.float, true),
+            TokenType::INTCONSTANT => (&self.int, true),
+            TokenType::ENUMCONSTANT => (&self.enum_constant, true),
+            TokenType::STRINGCONSTANT => (&self.string, false),
+            TokenType::OPERATOR => (&self.operator, true),
+            TokenType::KEYWORD => (&self.keyword, true),
+            TokenType::QUALIFIER => (&self.qualifier, true),
+            _ => (&self.foreground, false),
+        };
+        let mut codes = Vec::new();
+        if bold {
+            codes.push(String::from("1"));
+        }
+        if let Some((red, green, blue)) = rgb(color) {
+            codes.push(format!("38;2;{};{};{}", red, green, blue));
+        }
+        match codes.is_empty() {
+            true => String::from(token.get_text()),
+            false => format!("\x1b[{}m{}\x1b[0m", codes.join(";"), token.get_text()),
+        }
+    }
+
     fn format(&self, color: &str, style_open: &str, text: &str, style_close: &str) -> String {
         let mut res = String::from("<font color=\"");
         res.push_str(color);
         res.push_str("\">");
         res.push_str(style_open);
-        res.push_str(text);
+        res.push_str(&escape(text));
         res.push_str(style_close);
         res.push_str("</font>");
         res
     }
 }
+
+// red, green and blue of an html color, given by name or as #rrggbb
+fn rgb(color: &str) -> Option<(u8, u8, u8)> {
+    if let Some(hex) = color.strip_prefix('#') {
+        let value = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)?;
+        return Some(((value >> 16) as u8, (value >> 8) as u8, value as u8));
+    }
+    let rgb = match color.to_ascii_lowercase().as_str() {
+        "black" => (0, 0, 0),
+        "silver" => (192, 192, 192),
+        "gray" | "grey" => (128, 128, 128),
+        "white" => (255, 255, 255),
+        "maroon" => (128, 0, 0),
+        "red" => (255, 0, 0),
+        "purple" => (128, 0, 128),
+        "fuchsia" | "magenta" => (255, 0, 255),
+        "green" => (0, 128, 0),
+        "lime" => (0, 255, 0),
+        "olive" => (128, 128, 0),
+        "yellow" => (255, 255, 0),
+        "navy" => (0, 0, 128),
+        "blue" => (0, 0, 255),
+        "teal" => (0, 128, 128),
+        "aqua" | "cyan" => (0, 255, 255),
+        "orange" => (255, 165, 0),
+        _ => return None,
+    };
+    Some(rgb)
+}
+
+// replace the characters with a meaning in xhtml by their entities
+fn escape(text: &str) -> String {
+    let mut res = String::new();
+    for ch in text.chars() {
+        match ch {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            _ => res.push(ch),
+        }
+    }
+    res
+}
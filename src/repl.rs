@@ -0,0 +1,840 @@
+use crate::ast::*;
+use crate::const_eval;
+use crate::control_flow;
+use crate::diagnostic::{self, Diagnostic};
+use crate::interpreter::{Interpreter, Limits};
+use crate::layout::Layout;
+use crate::parser::Parser;
+use crate::prettifier::Prettifier;
+use crate::scanner::Scanner;
+use crate::semantic;
+use crate::token::{Span, Token};
+use crate::type_checker;
+
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+
+const FORMAT: &str = "format.csv";
+/// file name positions in the session are given with
+const SESSION: &str = "<repl>";
+const PROMPT: &str = "x> ";
+const CONTINUATION: &str = ".. ";
+
+const HELP: &str = "\
+Enter declarations, function definitions and statements as in a file, and
+expressions to see their values and types. Declarations go before main and
+statements at the end of its body; a variable whose initialiser is not a
+constant is a local of main, out of the reach of functions.
+
+:type EXPR     type of an expression
+:ast EXPR      syntax tree of an expression
+:tokens EXPR   tokens of an expression
+:list          the program the session stands for
+:reset         forget everything entered
+:help          this help
+:quit          leave, as does the end of the input";
+
+/// Read inputs from the keyboard until the end of the input or `:quit`,
+/// running each one in the session of the inputs before it
+///
+/// # Arguments
+///
+/// * `limits` - bounds of every run of the session
+///
+pub fn run(limits: Limits) {
+    let interactive = io::stdin().is_terminal();
+    let color = interactive && io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    if interactive {
+        println!("X REPL, :help lists the commands");
+    }
+    let mut session = Session::new(limits, color);
+    while let Some(input) = session.read(interactive) {
+        let input = input.trim_end();
+        match input.trim_start().strip_prefix(':') {
+            Some(command) => {
+                let (name, argument) = command
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((command, ""));
+                match name {
+                    "quit" | "q" => break,
+                    "reset" => session = Session::new(limits, color),
+                    _ => session.command(name, argument.trim()),
+                }
+            }
+            None if input.trim().is_empty() => {}
+            None => session.enter(input),
+        }
+    }
+}
+
+/// Where an input goes in the program the session stands for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Place {
+    /// globals, typedefs, structs, enums and prototypes, before main
+    Declaration,
+    /// function definitions, after main
+    Function,
+    /// statements and local declarations, at the end of main's body
+    Statement,
+    /// an expression, the condition of an `if` ending main's body so that it
+    /// is parsed in the scope of main
+    Expression,
+}
+
+/// The source of the session with an input placed in it
+struct Candidate {
+    source: String,
+    /// first and last line of the input
+    lines: (i32, i32),
+    /// first line of each text pushed, the input and those entered before it
+    starts: Vec<i32>,
+}
+
+impl Candidate {
+    fn push(&mut self, text: &str) {
+        self.starts.push(self.source.lines().count() as i32 + 1);
+        self.source.push_str(text);
+        if !text.ends_with('\n') {
+            self.source.push('\n');
+        }
+    }
+
+    fn push_input(&mut self, input: &str) {
+        let first = self.source.lines().count() as i32 + 1;
+        self.lines = (first, first + input.lines().count().max(1) as i32 - 1);
+        if !input.is_empty() {
+            self.push(input);
+        }
+    }
+
+    fn contains(&self, span: Span) -> bool {
+        span.line >= self.lines.0 && span.line <= self.lines.1
+    }
+
+    /// the span counted from the start of the input it is in, this one or
+    /// one entered before
+    fn relative(&self, span: Span) -> Span {
+        Span {
+            line: span.line - self.start(span) + 1,
+            column: span.column,
+        }
+    }
+
+    fn start(&self, span: Span) -> i32 {
+        self.starts
+            .iter()
+            .rev()
+            .find(|start| **start <= span.line)
+            .copied()
+            .unwrap_or(1)
+    }
+
+    /// the span as the user sees it, relative to its input, and the line of
+    /// that input under it
+    fn locate(&self, span: Span) -> (Span, Option<String>) {
+        let start = self.start(span);
+        let end = self
+            .starts
+            .iter()
+            .find(|next| **next > start)
+            .copied()
+            .unwrap_or(i32::MAX);
+        let input: Vec<&str> = self
+            .source
+            .lines()
+            .skip(start as usize - 1)
+            .take((end - start) as usize)
+            .collect();
+        let span = self.relative(span);
+        (span, diagnostic::snippet(&input.join("\n"), span))
+    }
+
+    /// how far into the input a span is; past its end when an error was
+    /// only found after reading all of it
+    fn progress(&self, span: Span) -> (i32, i32) {
+        match span.line > self.lines.1 {
+            true => (i32::MAX, 0),
+            false => (span.line - self.lines.0, span.column),
+        }
+    }
+
+    /// a span after the input moved to its end, where the input fell short
+    fn clamp(&self, span: Span) -> Span {
+        match span.line > self.lines.1 {
+            true => {
+                let last = self
+                    .source
+                    .lines()
+                    .nth(self.lines.1 as usize - 1)
+                    .unwrap_or("");
+                Span {
+                    line: self.lines.1,
+                    column: last.chars().count() as i32 + 1,
+                }
+            }
+            false => span,
+        }
+    }
+}
+
+/// Lines typed for read_int, kept so that a run made again from the start
+/// reads the same ones
+#[derive(Default)]
+struct Keyboard {
+    lines: RefCell<Vec<String>>,
+    /// lines the current run has taken
+    taken: Cell<usize>,
+}
+
+/// The input of a run: the lines the runs before it read, then the keyboard
+struct Replay<'a> {
+    keyboard: &'a Keyboard,
+    line: Vec<u8>,
+    offset: usize,
+}
+
+impl Read for Replay<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let length = available.len().min(buffer.len());
+        buffer[..length].copy_from_slice(&available[..length]);
+        self.consume(length);
+        Ok(length)
+    }
+}
+
+impl BufRead for Replay<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.offset == self.line.len() {
+            let mut lines = self.keyboard.lines.borrow_mut();
+            let next = self.keyboard.taken.get();
+            if next == lines.len() {
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(&[]);
+                }
+                lines.push(line);
+            }
+            self.line = lines[next].clone().into_bytes();
+            self.offset = 0;
+            self.keyboard.taken.set(next + 1);
+        }
+        Ok(&self.line[self.offset..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.offset += amount;
+    }
+}
+
+/// What the runs printed
+struct Output {
+    /// bytes the current run has written
+    written: Cell<usize>,
+    /// whether the last byte shown ended a line
+    newline: Cell<bool>,
+}
+
+/// The output of a run, less what the runs before it already printed
+struct Screen<'a> {
+    output: &'a Output,
+    shown: usize,
+}
+
+impl Write for Screen<'_> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let written = self.output.written.get();
+        let start = self.shown.saturating_sub(written).min(buffer.len());
+        let mut stdout = io::stdout();
+        stdout.write_all(&buffer[start..])?;
+        // shown at once, a prompt of the program comes before read_int waits
+        stdout.flush()?;
+        if let Some(last) = buffer[start..].last() {
+            self.output.newline.set(*last == b'\n');
+        }
+        self.output.written.set(written + buffer.len());
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Everything entered so far, kept as the X source of a program
+///
+/// Nothing of a run is kept but what it printed and read: each statement
+/// and expression runs the program again from the start, with the output
+/// already shown left out and the lines read_int took read again. X
+/// programs have nothing else from the outside, so every run repeats the
+/// one before it.
+struct Session {
+    declarations: Vec<String>,
+    statements: Vec<String>,
+    functions: Vec<String>,
+    /// statements of main's body after the parser, one input can hold several
+    statement_count: usize,
+    keyboard: Keyboard,
+    /// lines of the keyboard the statements so far read
+    read: usize,
+    output: Output,
+    /// bytes the statements so far printed
+    shown: usize,
+    limits: Limits,
+    prettifier: Prettifier,
+    color: bool,
+}
+
+impl Session {
+    fn new(limits: Limits, color: bool) -> Session {
+        Session {
+            declarations: Vec::new(),
+            statements: Vec::new(),
+            functions: Vec::new(),
+            statement_count: 0,
+            keyboard: Keyboard::default(),
+            read: 0,
+            output: Output {
+                written: Cell::new(0),
+                newline: Cell::new(true),
+            },
+            shown: 0,
+            limits,
+            prettifier: Prettifier::new(FORMAT),
+            color,
+        }
+    }
+
+    /// the next input, more lines read while brackets are open; None at the
+    /// end of the input
+    fn read(&mut self, interactive: bool) -> Option<String> {
+        self.end_line();
+        let mut input = String::new();
+        let mut lines = 0;
+        loop {
+            if interactive {
+                print!("{}", if lines == 0 { PROMPT } else { CONTINUATION });
+                io::stdout().flush().ok()?;
+            }
+            let mut line = String::new();
+            match io::stdin().lock().read_line(&mut line) {
+                Ok(0) | Err(_) if input.is_empty() => return None,
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            input.push_str(&line);
+            lines += 1;
+            if input.trim_start().starts_with(':') || open_brackets(&input) <= 0 {
+                break;
+            }
+        }
+        // the typed lines are written again, highlighted
+        if self.color && !input.trim_start().starts_with(':') {
+            print!("\x1b[{}A\r\x1b[J", lines);
+            for (i, line) in self
+                .highlight(input.trim_end_matches('\n'))
+                .lines()
+                .enumerate()
+            {
+                println!("{}{}", if i == 0 { PROMPT } else { CONTINUATION }, line);
+            }
+        }
+        Some(input)
+    }
+
+    /// move to a new line when a run left one unfinished
+    fn end_line(&self) {
+        if !self.output.newline.get() {
+            println!();
+            self.output.newline.set(true);
+        }
+    }
+
+    /// the text with each token in the colors of format.csv
+    fn highlight(&self, text: &str) -> String {
+        let mut tokens = tokens(text).into_iter().peekable();
+        let mut highlighted = String::new();
+        for (line, content) in text.split('\n').enumerate() {
+            if line > 0 {
+                highlighted.push('\n');
+            }
+            // characters of the token last written still to pass
+            let mut rest = 0;
+            for (column, ch) in content.chars().enumerate() {
+                if rest > 0 {
+                    rest -= 1;
+                    continue;
+                }
+                let span = Span {
+                    line: line as i32 + 1,
+                    column: column as i32 + 1,
+                };
+                match tokens.next_if(|token| token.get_span() == span) {
+                    Some(token) => {
+                        highlighted.push_str(&self.prettifier.ansi(&token));
+                        rest = token.get_text().chars().count().saturating_sub(1);
+                    }
+                    None => highlighted.push(ch),
+                }
+            }
+        }
+        highlighted
+    }
+
+    fn candidate(&self, place: Place, input: &str) -> Candidate {
+        let mut candidate = Candidate {
+            source: String::new(),
+            lines: (0, 0),
+            starts: Vec::new(),
+        };
+        for declaration in &self.declarations {
+            candidate.push(declaration);
+        }
+        if place == Place::Declaration {
+            candidate.push_input(input);
+        }
+        candidate.push("void main(){");
+        for statement in &self.statements {
+            candidate.push(statement);
+        }
+        match place {
+            Place::Statement => candidate.push_input(input),
+            Place::Expression => {
+                candidate.push("if (");
+                candidate.push_input(input);
+                candidate.push(") {}");
+            }
+            _ => {}
+        }
+        candidate.push("}");
+        for function in &self.functions {
+            candidate.push(function);
+        }
+        if place == Place::Function {
+            candidate.push_input(input);
+        }
+        candidate
+    }
+
+    /// Take an input into the session at the first place it parses in, in
+    /// the order of `Place`, and run it when it is a statement or an expression
+    fn enter(&mut self, input: &str) {
+        let mut furthest: Option<(Candidate, Diagnostic)> = None;
+        for place in [
+            Place::Declaration,
+            Place::Function,
+            Place::Statement,
+            Place::Expression,
+        ] {
+            let candidate = self.candidate(place, input);
+            let mut parser = Parser::from_source(&candidate.source, FORMAT);
+            match parser.try_parse() {
+                Ok(program) => return self.accept(place, input, &candidate, &parser, program),
+                // the error of the place the input got furthest in is shown
+                Err(error) => {
+                    let further = match &furthest {
+                        Some((best, best_error)) => {
+                            candidate.progress(error.span) > best.progress(best_error.span)
+                        }
+                        None => true,
+                    };
+                    if further {
+                        furthest = Some((candidate, error));
+                    }
+                }
+            }
+        }
+        if let Some((candidate, mut error)) = furthest {
+            error.span = candidate.clamp(error.span);
+            self.report(&candidate, &[error]);
+        }
+    }
+
+    fn accept(
+        &mut self,
+        place: Place,
+        input: &str,
+        candidate: &Candidate,
+        parser: &Parser,
+        mut program: Program,
+    ) {
+        let probe = match place {
+            Place::Expression => match self.probe(candidate, &mut program) {
+                Some(probe) => Some(probe),
+                None => return,
+            },
+            _ => None,
+        };
+        let diagnostics = check(&program);
+        self.report(candidate, &diagnostics);
+        if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+            return;
+        }
+        match place {
+            Place::Declaration => self.declarations.push(String::from(input)),
+            Place::Function => {
+                // the definitions follow main, which may call them from now on
+                self.declarations
+                    .extend(prototypes(parser.tokens(), candidate, input));
+                self.functions.push(String::from(input));
+            }
+            Place::Statement => {
+                let statements = &program.main.body.statements[self.statement_count..];
+                if let Some(span) = statements.iter().find_map(find_return) {
+                    let error = Diagnostic::error(
+                        String::from(
+                            "main cannot return at the prompt, later inputs would not run",
+                        ),
+                        span,
+                    );
+                    return self.report(candidate, &[error]);
+                }
+                if self.execute(candidate, &program, None) {
+                    self.statements.push(String::from(input));
+                    self.statement_count = program.main.body.statements.len();
+                }
+            }
+            Place::Expression => {
+                program.main.body.statements.pop();
+                self.execute(candidate, &program, probe.as_ref());
+            }
+        }
+    }
+
+    /// The expression of an input placed as an expression, the `if` around
+    /// it turned into a statement that only evaluates it for the checks
+    fn probe(&self, candidate: &Candidate, program: &mut Program) -> Option<Expression> {
+        let statements = &mut program.main.body.statements;
+        let probe = match statements.pop() {
+            Some(Statement {
+                kind: StatementKind::If { condition, body },
+                span,
+            }) if body.statements.is_empty() && statements.len() == self.statement_count => {
+                statements.push(Statement {
+                    kind: StatementKind::Call(condition.clone()),
+                    span,
+                });
+                Some(condition)
+            }
+            _ => None,
+        };
+        if probe.is_none() {
+            let span = Span {
+                line: candidate.lines.0,
+                column: 1,
+            };
+            let error = Diagnostic::error(String::from("expected an expression"), span);
+            self.report(candidate, &[error]);
+        }
+        probe
+    }
+
+    /// Run the program from the start, then evaluate the probe where main's
+    /// body ended; false when the run stopped on an error
+    fn execute(
+        &mut self,
+        candidate: &Candidate,
+        program: &Program,
+        probe: Option<&Expression>,
+    ) -> bool {
+        self.keyboard.taken.set(0);
+        self.output.written.set(0);
+        let replay = Replay {
+            keyboard: &self.keyboard,
+            line: Vec::new(),
+            offset: 0,
+        };
+        let screen = Screen {
+            output: &self.output,
+            shown: self.shown,
+        };
+        let mut interpreter = Interpreter::with_io(program, Box::new(replay), Box::new(screen));
+        interpreter.set_limits(self.limits);
+        let result = interpreter.run();
+        let (written, taken) = (self.output.written.get(), self.keyboard.taken.get());
+        let value = match (&result, probe) {
+            (Ok(()), Some(probe)) => interpreter.evaluate_in_main(probe),
+            _ => Ok(None),
+        };
+        drop(interpreter);
+        let ran = result.is_ok();
+        if ran && probe.is_none() {
+            self.shown = written;
+            self.read = taken;
+        }
+        // lines read by anything but the statements are not read again
+        self.keyboard.lines.borrow_mut().truncate(self.read);
+        match result.and(value) {
+            Ok(Some((data_type, value))) => {
+                self.end_line();
+                println!("({}) {}", data_type, value);
+            }
+            Ok(None) => {}
+            Err(error) => {
+                self.end_line();
+                let locate = |span| candidate.locate(span);
+                eprintln!("{}", error.render_with(SESSION, &locate));
+            }
+        }
+        ran
+    }
+
+    /// show the diagnostics of an input: the errors and the warnings about
+    /// the input itself, not about what was entered before it
+    fn report(&self, candidate: &Candidate, diagnostics: &[Diagnostic]) {
+        self.end_line();
+        for diagnostic in diagnostics {
+            if !diagnostic.is_error() && !candidate.contains(diagnostic.span) {
+                continue;
+            }
+            let (span, snippet) = candidate.locate(diagnostic.span);
+            let diagnostic = Diagnostic {
+                span,
+                ..diagnostic.clone()
+            };
+            eprintln!("{}", diagnostic.render(SESSION));
+            if let Some(snippet) = snippet {
+                eprintln!("{}", snippet);
+            }
+        }
+    }
+
+    fn command(&mut self, name: &str, argument: &str) {
+        match name {
+            "type" | "ast" if argument.is_empty() => eprintln!(":{} needs an expression", name),
+            "type" => {
+                if let Some((program, expression)) = self.expression(argument, true) {
+                    match type_of(&program, &expression) {
+                        Some(data_type) => println!("{}", data_type),
+                        None => eprintln!("the type of `{}` is unknown", expression),
+                    }
+                }
+            }
+            "ast" => {
+                if let Some((_, expression)) = self.expression(argument, false) {
+                    let candidate = self.candidate(Place::Expression, argument);
+                    print!("{}", tree(&expression, &candidate, 0));
+                }
+            }
+            "tokens" => {
+                for token in tokens(argument) {
+                    let text = match self.color {
+                        true => self.prettifier.ansi(&token),
+                        false => String::from(token.get_text()),
+                    };
+                    println!(
+                        "{:<7} {:<15} {}",
+                        format!("{}:{}", token.get_line_number(), token.get_char_pos()),
+                        token.get_type().as_str(),
+                        text
+                    );
+                }
+            }
+            "list" => {
+                let candidate = self.candidate(Place::Statement, "");
+                for (i, line) in candidate.source.lines().enumerate() {
+                    let line = match self.color {
+                        true => self.highlight(line),
+                        false => String::from(line),
+                    };
+                    println!("{:>4} | {}", i + 1, line);
+                }
+            }
+            "help" => println!("{}", HELP),
+            _ => eprintln!("unknown command :{}, :help lists the commands", name),
+        }
+    }
+
+    /// An expression parsed where main's body ends, checked when asked to,
+    /// with the program it was parsed in
+    fn expression(&self, text: &str, checked: bool) -> Option<(Program, Expression)> {
+        let candidate = self.candidate(Place::Expression, text);
+        let mut parser = Parser::from_source(&candidate.source, FORMAT);
+        let mut program = match parser.try_parse() {
+            Ok(program) => program,
+            Err(mut error) => {
+                error.span = candidate.clamp(error.span);
+                self.report(&candidate, &[error]);
+                return None;
+            }
+        };
+        let expression = self.probe(&candidate, &mut program)?;
+        if checked {
+            let diagnostics = check(&program);
+            self.report(&candidate, &diagnostics);
+            if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+                return None;
+            }
+        }
+        program.main.body.statements.pop();
+        Some((program, expression))
+    }
+}
+
+/// the checks of the pipeline that make sense for an unfinished program;
+/// findings about values never used wait for inputs that may use them
+fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = semantic::analyze(program);
+    diagnostics.extend(type_checker::check(program));
+    diagnostics.extend(control_flow::analyze(program));
+    diagnostics.extend(const_eval::analyze(program));
+    diagnostics
+}
+
+fn tokens(text: &str) -> Vec<Token> {
+    let mut scanner = Scanner::from_source(text);
+    let mut tokens = Vec::new();
+    while let Some(token) = scanner.get_next_token() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// how many brackets of the text are still open
+fn open_brackets(text: &str) -> i32 {
+    tokens(text)
+        .iter()
+        .map(|token| match token.get_text() {
+            "(" | "[" | "{" => 1,
+            ")" | "]" | "}" => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// the prototype of each function an input defines: its text up to the
+/// body, then `;`
+fn prototypes(tokens: &[Token], candidate: &Candidate, input: &str) -> Vec<String> {
+    let mut prototypes = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+    for token in tokens
+        .iter()
+        .filter(|token| candidate.contains(token.get_span()))
+    {
+        let span = candidate.relative(token.get_span());
+        match token.get_text() {
+            "{" => {
+                if let Some(start) = start.take() {
+                    let header = &input[offset(input, start)..offset(input, span)];
+                    prototypes.push(format!("{};", header.trim_end()));
+                }
+                depth += 1;
+            }
+            "}" => depth -= 1,
+            _ if depth == 0 && start.is_none() => start = Some(span),
+            _ => {}
+        }
+    }
+    prototypes
+}
+
+/// byte offset of a position in a text
+fn offset(text: &str, span: Span) -> usize {
+    let mut line_start = 0;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        if i as i32 + 1 == span.line {
+            return line_start
+                + line
+                    .char_indices()
+                    .nth(span.column as usize - 1)
+                    .map_or(line.len(), |(offset, _)| offset);
+        }
+        line_start += line.len();
+    }
+    text.len()
+}
+
+/// the first return statement among the statements and the blocks in them
+fn find_return(statement: &Statement) -> Option<Span> {
+    match &statement.kind {
+        StatementKind::Return(_) => Some(statement.span),
+        StatementKind::While { body, .. }
+        | StatementKind::For { body, .. }
+        | StatementKind::If { body, .. }
+        | StatementKind::Switch { body, .. }
+        | StatementKind::Block(body) => body.statements.iter().find_map(find_return),
+        _ => None,
+    }
+}
+
+/// the static type of an expression at the end of main's body
+fn type_of(program: &Program, expression: &Expression) -> Option<Type> {
+    let variable = |name: &str| {
+        let local =
+            program
+                .main
+                .body
+                .statements
+                .iter()
+                .rev()
+                .find_map(|statement| match &statement.kind {
+                    StatementKind::Declaration(Declaration::Variable(variable))
+                        if variable.name == name =>
+                    {
+                        Some(variable.data_type.clone())
+                    }
+                    _ => None,
+                });
+        local.or_else(|| {
+            program
+                .declarations
+                .iter()
+                .rev()
+                .find_map(|declaration| match declaration {
+                    Declaration::Variable(variable) if variable.name == name => {
+                        Some(variable.data_type.clone())
+                    }
+                    _ => None,
+                })
+        })
+    };
+    Layout::new(program).type_of(expression, &variable)
+}
+
+/// the syntax tree of an expression, a node per line indented under its
+/// parent, with its position in the expression
+fn tree(expression: &Expression, candidate: &Candidate, depth: usize) -> String {
+    let (label, children): (String, Vec<&Expression>) = match &expression.kind {
+        ExpressionKind::IntConstant(n) => (format!("IntConstant {}", n), vec![]),
+        ExpressionKind::FloatConstant(x) => (format!("FloatConstant {:?}", x), vec![]),
+        ExpressionKind::EnumConstant { name, value } => {
+            (format!("EnumConstant {} = {}", name, value), vec![])
+        }
+        ExpressionKind::StringConstant(text) => (format!("StringConstant {:?}", text), vec![]),
+        ExpressionKind::Variable(name) => (format!("Variable {}", name), vec![]),
+        ExpressionKind::Index { array, index } => (String::from("Index"), vec![array, index]),
+        ExpressionKind::Member { base, member } => (format!("Member .{}", member), vec![base]),
+        ExpressionKind::Call { name, arguments } => {
+            (format!("Call {}", name), arguments.iter().collect())
+        }
+        ExpressionKind::Unary { operator, operand } => {
+            (format!("Unary {}", operator.as_str()), vec![operand])
+        }
+        ExpressionKind::Cast { data_type, operand } => {
+            (format!("Cast ({})", data_type), vec![operand])
+        }
+        ExpressionKind::SizeOfType(data_type) => (format!("SizeOfType {}", data_type), vec![]),
+        ExpressionKind::SizeOfExpression(operand) => {
+            (String::from("SizeOfExpression"), vec![operand])
+        }
+        ExpressionKind::Binary {
+            operator,
+            left,
+            right,
+        } => (format!("Binary {}", operator.as_str()), vec![left, right]),
+    };
+    let span = candidate.relative(expression.span);
+    let mut text = format!(
+        "{}{}  {}:{}\n",
+        "  ".repeat(depth),
+        label,
+        span.line,
+        span.column
+    );
+    for child in children {
+        text.push_str(&tree(child, candidate, depth + 1));
+    }
+    text
+}
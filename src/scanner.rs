@@ -8,6 +8,7 @@ use std::collections::HashMap;
 pub struct Scanner {
     text: CharStream,
     ending_char: Vec<char>,
+    punctuation_char: Vec<char>,
     operator_char: Vec<char>,
     id_map: HashMap<String, TokenType>,
     line_num: i32,
@@ -16,10 +17,20 @@ pub struct Scanner {
 
 impl Scanner {
     pub fn new(f: &str) -> Scanner {
+        Scanner::from_stream(CharStream::new(f))
+    }
+
+    /// scan text that is not in a file
+    pub fn from_source(source: &str) -> Scanner {
+        Scanner::from_stream(CharStream::from_string(String::from(source)))
+    }
+
+    fn from_stream(text: CharStream) -> Scanner {
         Scanner {
-            text: CharStream::new(f),
-            ending_char: vec!['(', ')', '{', '}', ' ', '\n', ';', '\t', ','],
-            operator_char: vec!['=', '+', '-', '*', '/', '<', '>', '!'],
+            text,
+            ending_char: vec![' ', '\n', '\t', '\r'],
+            punctuation_char: vec!['(', ')', '{', '}', '[', ']', ';', ',', '.', ':'],
+            operator_char: vec!['=', '+', '-', '*', '/', '<', '>', '!', '&'],
             id_map: HashMap::from([
                 (String::from("unsigned"), TokenType::KEYWORD),
                 (String::from("char"), TokenType::KEYWORD),
@@ -31,9 +42,20 @@ impl Scanner {
                 (String::from("void"), TokenType::KEYWORD),
                 (String::from("main"), TokenType::KEYWORD),
                 (String::from("while"), TokenType::KEYWORD),
+                (String::from("switch"), TokenType::KEYWORD),
+                (String::from("case"), TokenType::KEYWORD),
+                (String::from("default"), TokenType::KEYWORD),
                 (String::from("for"), TokenType::KEYWORD),
+                (String::from("break"), TokenType::KEYWORD),
                 (String::from("if"), TokenType::KEYWORD),
                 (String::from("return"), TokenType::KEYWORD),
+                (String::from("struct"), TokenType::KEYWORD),
+                (String::from("typedef"), TokenType::KEYWORD),
+                (String::from("sizeof"), TokenType::KEYWORD),
+                (String::from("enum"), TokenType::KEYWORD),
+                (String::from("const"), TokenType::QUALIFIER),
+                (String::from("static"), TokenType::QUALIFIER),
+                (String::from("extern"), TokenType::QUALIFIER),
             ]),
             line_num: 1,
             char_pos: 1,
@@ -41,160 +63,131 @@ impl Scanner {
     }
 
     // skip any white space or next line
-    fn trim(&mut self) -> bool {
-        let mut flag = false;
-        while self.text.more_available() {
-            match self.text.peek_next_char() {
-                None => break,
-                Some(ch) => {
-                    if self.ending_char.contains(&ch) {
-                        if ch == '\n' {
-                            self.line_num += 1;
-                            self.char_pos = 1;
-                        }
-                        if ch == '}' {
-                            flag = true;
-                        }
-                        self.text.get_next_char();
-                    } else {
-                        break;
-                    }
-                }
+    fn trim(&mut self) {
+        while let Some(ch) = self.text.peek_next_char() {
+            if !self.ending_char.contains(&ch) {
+                break;
             }
+            self.advance();
         }
-        flag
     }
 
-    fn lookup(&self, word: String) -> TokenType {
-        match self.id_map.get(&word) {
-            None => TokenType::VARIABLE,
-            Some(&t) => t,
+    // consume one character, keeping track of the line number and char position
+    fn advance(&mut self) -> Option<char> {
+        let ch = self.text.get_next_char()?;
+        if ch == '\n' {
+            self.line_num += 1;
+            self.char_pos = 1;
+        } else {
+            self.char_pos += 1;
         }
+        Some(ch)
     }
 
-    // check is the following operator is an operator
-    fn is_next_operator(&self) -> bool {
-        match self.text.peek_next_char() {
-            None => false,
-            Some(ch) => self.operator_char.contains(&ch),
+    fn lookup(&self, word: &str) -> TokenType {
+        match self.id_map.get(word) {
+            None => TokenType::VARIABLE,
+            Some(&t) => t,
         }
     }
 
-    // check is the following operator is an open parenthesis
+    // check is the next non-blank character is an open parenthesis
     fn is_next_parenthesis(&self) -> bool {
-        match self.text.peek_next_char() {
-            Some('(') => true,
-            _ => false,
+        let mut k = 0;
+        while let Some(ch) = self.text.peek_ahead_char(k) {
+            if !self.ending_char.contains(&ch) {
+                return ch == '(';
+            }
+            k += 1;
         }
+        false
+    }
+
+    fn is_identifier_char(ch: char) -> bool {
+        ch.is_ascii_alphanumeric() || ch == '_'
     }
 
     pub fn get_next_token(&mut self) -> Option<Token> {
-        let mut curr_word = String::from("");
-        let mut curr_type: TokenType = TokenType::NONE;
-        let mut has_found: bool = false;
+        self.trim();
 
-        // use TokenType::NONE to indicate scope
-        let has_end_of_scope = self.trim();
-        if has_end_of_scope {
-            return Some(Token::new(String::from(""), TokenType::NONE, 0, 0));
-        }
-        let cur_char_pos = self.char_pos;
+        let line_num = self.line_num;
+        let char_pos = self.char_pos;
+        let ch = self.text.peek_next_char()?;
+        let mut curr_word = String::new();
+        let mut curr_type: TokenType;
 
-        while self.text.more_available() {
-            match self.text.get_next_char() {
-                None => break,
-                Some(ch) => match self.ending_char.contains(&ch) {
-                    true => {
-                        self.char_pos += 1;
-                        match curr_type {
-                            TokenType::VARIABLE => {
-                                if ch == '(' {
-                                    match self.id_map.get(&curr_word) {
-                                        None => {
-                                            self.id_map
-                                                .insert(curr_word.clone(), TokenType::FUNCTION);
-                                        }
-                                        _ => {}
-                                    }
-                                } else if ch == '\n' {
-                                    self.line_num += 1;
-                                    self.char_pos = 1;
-                                }
-                                break;
-                            }
-                            _ => break,
-                        }
-                    }
-                    false => {
-                        self.char_pos += 1;
-                        match curr_type {
-                            TokenType::INVALID => break,
-                            TokenType::INTCONSTANT => {
-                                if ch.is_digit(10) {
-                                    curr_word.push(ch);
-                                } else if ch == '.' {
-                                    curr_type = TokenType::FLOATCONSTANT;
-                                    curr_word.push(ch);
-                                } else {
-                                    curr_type = TokenType::INVALID;
-                                    break;
-                                }
-                            }
-                            TokenType::FLOATCONSTANT => {
-                                if ch.is_digit(10) {
-                                    curr_word.push(ch);
-                                } else {
-                                    curr_type = TokenType::INVALID;
-                                    break;
-                                }
-                            }
-                            TokenType::VARIABLE => {
-                                curr_word.push(ch);
-                            }
-                            _ => {
-                                has_found = true;
-                                curr_word.push(ch);
-                                if self.operator_char.contains(&ch) {
-                                    curr_type = TokenType::OPERATOR;
-                                    match self.text.peek_next_char() {
-                                        None => break,
-                                        Some(ch) => {
-                                            if ch == '=' {
-                                                curr_word.push(ch);
-                                                self.char_pos += 1;
-                                                self.text.get_next_char();
-                                            }
-                                        }
-                                    }
-                                    break;
-                                } else if ch.is_digit(10) {
-                                    curr_type = TokenType::INTCONSTANT;
-                                } else if ch.is_ascii_alphabetic() {
-                                    curr_type = TokenType::VARIABLE;
-                                } else {
-                                    curr_type = TokenType::INVALID;
-                                    break;
-                                }
-                            }
-                        }
-                        if self.is_next_operator() {
-                            break;
-                        }
-                    }
-                },
+        if ch.is_ascii_alphabetic() || ch == '_' {
+            // Identifier or Keyword
+            while let Some(ch) = self.text.peek_next_char() {
+                if !Scanner::is_identifier_char(ch) {
+                    break;
+                }
+                curr_word.push(ch);
+                self.advance();
             }
-        }
-
-        match has_found {
-            false => None,
-            true => {
-                match curr_type {
-                    TokenType::VARIABLE => curr_type = self.lookup(curr_word.clone()),
-                    _ => {}
+            // an identifier followed by '(' names a function from now on
+            if self.is_next_parenthesis() && !self.id_map.contains_key(&curr_word) {
+                self.id_map.insert(curr_word.clone(), TokenType::FUNCTION);
+            }
+            curr_type = self.lookup(&curr_word);
+        } else if ch.is_ascii_digit() {
+            // IntConstant or FloatConstant
+            curr_type = TokenType::INTCONSTANT;
+            while let Some(ch) = self.text.peek_next_char() {
+                if ch.is_ascii_digit() {
+                    curr_word.push(ch);
+                } else if ch == '.' && curr_type == TokenType::INTCONSTANT {
+                    curr_type = TokenType::FLOATCONSTANT;
+                    curr_word.push(ch);
+                } else if Scanner::is_identifier_char(ch) || ch == '.' {
+                    curr_type = TokenType::INVALID;
+                    curr_word.push(ch);
+                } else {
+                    break;
                 }
-                let res = Token::new(curr_word, curr_type, self.line_num, cur_char_pos);
-                Some(res)
+                self.advance();
             }
+        } else if ch == '"' {
+            // StringConstant, escape sequences are kept as written
+            curr_type = TokenType::INVALID;
+            curr_word.push(ch);
+            self.advance();
+            while let Some(ch) = self.text.peek_next_char() {
+                if ch == '\n' {
+                    break;
+                }
+                curr_word.push(ch);
+                self.advance();
+                if ch == '"' {
+                    curr_type = TokenType::STRINGCONSTANT;
+                    break;
+                }
+                if ch == '\\' {
+                    if let Some(escaped) = self.text.peek_next_char() {
+                        curr_word.push(escaped);
+                        self.advance();
+                    }
+                }
+            }
+        } else if self.operator_char.contains(&ch) {
+            // Operator, optionally followed by '='
+            curr_type = TokenType::OPERATOR;
+            curr_word.push(ch);
+            self.advance();
+            if self.text.peek_next_char() == Some('=') {
+                curr_word.push('=');
+                self.advance();
+            }
+        } else if self.punctuation_char.contains(&ch) {
+            curr_type = TokenType::OPERATOR;
+            curr_word.push(ch);
+            self.advance();
+        } else {
+            curr_type = TokenType::INVALID;
+            curr_word.push(ch);
+            self.advance();
         }
+
+        Some(Token::new(curr_word, curr_type, line_num, char_pos))
     }
 }
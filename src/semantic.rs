@@ -0,0 +1,508 @@
+use crate::ast::*;
+use crate::builtins;
+use crate::diagnostic::Diagnostic;
+use crate::symbol_table::{Symbol, SymbolKind, SymbolTable};
+use crate::token::Span;
+
+use std::collections::{HashMap, HashSet};
+
+/// Check that every identifier of the program refers to a declaration in scope
+///
+/// # Arguments
+///
+/// * `program` - syntax tree from the parser
+///
+pub fn analyze(program: &Program) -> Vec<Diagnostic> {
+    let mut resolver = Resolver::new();
+    resolver.program(program);
+    resolver.diagnostics
+}
+
+/// Scope resolution pass: walks the program in source order, declaring names
+/// as it meets them, the same way the X rules say they become visible
+pub struct Resolver {
+    symbols: SymbolTable,
+    // declarations still ahead in each open scope, to tell a use before the
+    // declaration from a name that is not declared at all
+    later: Vec<HashMap<String, Span>>,
+    // where each function with a body is defined
+    definitions: HashMap<String, Span>,
+    // functions already reported as called but never defined
+    undefined: HashSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            symbols: SymbolTable::new(),
+            later: vec![HashMap::new()],
+            definitions: HashMap::new(),
+            undefined: HashSet::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::error(message, span));
+    }
+
+    fn enter_scope(&mut self, statements: &[Statement]) {
+        self.symbols.enter_scope();
+        self.later.push(HashMap::new());
+        for statement in statements {
+            if let StatementKind::Declaration(declaration) = &statement.kind {
+                self.expect_later(declaration);
+            }
+        }
+    }
+
+    fn exit_scope(&mut self) {
+        self.symbols.exit_scope();
+        self.later.pop();
+    }
+
+    // remember the names a declaration will introduce into the current scope
+    fn expect_later(&mut self, declaration: &Declaration) {
+        let later = self.later.last_mut().unwrap();
+        match declaration {
+            Declaration::Variable(variable) => {
+                later.entry(variable.name.clone()).or_insert(variable.span);
+            }
+            Declaration::Function(function) => {
+                later.entry(function.name.clone()).or_insert(function.span);
+            }
+            Declaration::Typedef(typedef) => {
+                later.entry(typedef.name.clone()).or_insert(typedef.span);
+            }
+            Declaration::Enum(declaration) => {
+                for enumerator in &declaration.enumerators {
+                    later
+                        .entry(enumerator.name.clone())
+                        .or_insert(enumerator.span);
+                }
+            }
+            Declaration::Struct(_) => {}
+        }
+    }
+
+    // a name may be declared again in the same scope only by another
+    // declaration of the same function, or by the definition of an extern variable
+    fn may_redeclare(previous: &Symbol, symbol: &Symbol) -> bool {
+        let is_extern = |s: &Symbol| s.qualifiers.storage == StorageClass::Extern;
+        match (&previous.kind, &symbol.kind) {
+            (SymbolKind::Function(previous_parameters), SymbolKind::Function(parameters)) => {
+                // array parameters are pointers, `int a[]` and `int *a` agree
+                let adjusted = |parameters: &Vec<Type>| -> Vec<Type> {
+                    parameters
+                        .iter()
+                        .map(|p| match p {
+                            Type::Array(element, _) => Type::Pointer(element.clone()),
+                            _ => p.clone(),
+                        })
+                        .collect()
+                };
+                previous.data_type == symbol.data_type
+                    && adjusted(previous_parameters) == adjusted(parameters)
+            }
+            (SymbolKind::Global, SymbolKind::Global) => {
+                previous.data_type == symbol.data_type && (is_extern(previous) || is_extern(symbol))
+            }
+            _ => false,
+        }
+    }
+
+    fn declare(&mut self, symbol: Symbol) {
+        if self.symbols.is_global_scope() && builtins::is_builtin(&symbol.name) {
+            let message = format!(
+                "`{}` is a builtin function and cannot be declared again",
+                symbol.name
+            );
+            self.error(message, symbol.span);
+            return;
+        }
+        if let Some(previous) = self.symbols.lookup_current(&symbol.name) {
+            if !Resolver::may_redeclare(previous, &symbol) {
+                let message = match &previous.kind {
+                    SymbolKind::Function(_) if matches!(symbol.kind, SymbolKind::Function(_)) => {
+                        format!(
+                            "conflicting types for `{}`: `{}` here, but `{}` at line {}",
+                            symbol.name,
+                            symbol.signature(),
+                            previous.signature(),
+                            previous.span.line
+                        )
+                    }
+                    _ => format!(
+                        "duplicate declaration of `{}`, already declared as a {} at line {}",
+                        symbol.name,
+                        previous.kind.as_str(),
+                        previous.span.line
+                    ),
+                };
+                self.error(message, symbol.span);
+                return;
+            }
+        }
+        self.later.last_mut().unwrap().remove(&symbol.name);
+        self.symbols.declare(symbol);
+    }
+
+    fn undeclared(&mut self, name: &str, span: Span) {
+        let declared_later = self.later.iter().rev().find_map(|scope| scope.get(name));
+        let message = match declared_later {
+            Some(declaration) => format!(
+                "`{}` is used before its declaration at line {}",
+                name, declaration.line
+            ),
+            None => format!("undeclared identifier `{}`", name),
+        };
+        self.error(message, span);
+    }
+
+    fn program(&mut self, program: &Program) {
+        for declaration in &program.declarations {
+            self.expect_later(declaration);
+        }
+        let later = self.later.last_mut().unwrap();
+        for function in &program.functions {
+            later
+                .entry(function.declaration.name.clone())
+                .or_insert(function.declaration.span);
+        }
+
+        // a call may come before the definition of the function it calls
+        for function in std::iter::once(&program.main).chain(&program.functions) {
+            let declaration = &function.declaration;
+            if let Some(previous) = self.definitions.get(&declaration.name) {
+                let message = format!(
+                    "function `{}` is defined more than once, first at line {}",
+                    declaration.name, previous.line
+                );
+                self.error(message, declaration.span);
+            } else {
+                self.definitions
+                    .insert(declaration.name.clone(), declaration.span);
+            }
+        }
+
+        for declaration in &program.declarations {
+            self.declaration(declaration);
+        }
+        self.function(&program.main);
+        for function in &program.functions {
+            self.function(function);
+        }
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        match declaration {
+            Declaration::Variable(variable) => {
+                if let Some(initializer) = &variable.initializer {
+                    self.initializer(initializer);
+                }
+                let kind = match self.symbols.is_global_scope() {
+                    true => SymbolKind::Global,
+                    false => SymbolKind::Local,
+                };
+                self.declare(Symbol {
+                    name: variable.name.clone(),
+                    kind,
+                    data_type: variable.data_type.clone(),
+                    qualifiers: variable.qualifiers,
+                    span: variable.span,
+                });
+            }
+            Declaration::Function(function) => self.declare_function(function),
+            Declaration::Typedef(typedef) => self.declare(Symbol {
+                name: typedef.name.clone(),
+                kind: SymbolKind::TypeName,
+                data_type: typedef.data_type.clone(),
+                qualifiers: Qualifiers::default(),
+                span: typedef.span,
+            }),
+            Declaration::Enum(declaration) => {
+                for enumerator in &declaration.enumerators {
+                    self.declare(Symbol {
+                        name: enumerator.name.clone(),
+                        kind: SymbolKind::Enumerator(enumerator.value),
+                        data_type: Type::Integer {
+                            kind: IntKind::Int,
+                            unsigned: false,
+                        },
+                        qualifiers: Qualifiers::default(),
+                        span: enumerator.span,
+                    });
+                }
+            }
+            Declaration::Struct(_) => {}
+        }
+    }
+
+    fn declare_function(&mut self, function: &FunctionDeclaration) {
+        self.declare(Symbol {
+            name: function.name.clone(),
+            kind: SymbolKind::Function(
+                function
+                    .parameters
+                    .iter()
+                    .map(|p| p.data_type.clone())
+                    .collect(),
+            ),
+            data_type: function.return_type.clone(),
+            qualifiers: function.qualifiers,
+            span: function.span,
+        });
+    }
+
+    fn function(&mut self, function: &Function) {
+        // declared before the body is resolved, so that it may call itself
+        self.declare_function(&function.declaration);
+
+        // parameters share their scope with the outermost block of the body
+        self.enter_scope(&function.body.statements);
+        for parameter in &function.declaration.parameters {
+            self.declare(Symbol {
+                name: parameter.name.clone(),
+                kind: SymbolKind::Parameter,
+                data_type: parameter.data_type.clone(),
+                qualifiers: parameter.qualifiers,
+                span: parameter.span,
+            });
+        }
+        for statement in &function.body.statements {
+            self.statement(statement);
+        }
+        self.exit_scope();
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.enter_scope(&block.statements);
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        self.exit_scope();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Assignment { targets, value } => {
+                for target in targets {
+                    self.expression(target);
+                }
+                self.expression(value);
+            }
+            StatementKind::While { condition, body } | StatementKind::If { condition, body } => {
+                self.expression(condition);
+                self.block(body);
+            }
+            StatementKind::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                if let Some(condition) = condition {
+                    self.expression(condition);
+                }
+                if let Some(step) = step {
+                    self.statement(step);
+                }
+                self.block(body);
+            }
+            StatementKind::Switch { value, body, cases } => {
+                self.expression(value);
+                for label in cases.iter().filter_map(|case| case.label.as_ref()) {
+                    self.expression(label);
+                }
+                self.block(body);
+            }
+            StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            StatementKind::Call(call) => self.expression(call),
+            StatementKind::Declaration(declaration) => self.declaration(declaration),
+            StatementKind::Block(block) => self.block(block),
+            StatementKind::Break => {}
+        }
+    }
+
+    fn initializer(&mut self, initializer: &Initializer) {
+        match initializer {
+            Initializer::Expression(expr) => self.expression(expr),
+            Initializer::List(items, _) => {
+                for item in items {
+                    self.initializer(item);
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::IntConstant(_)
+            | ExpressionKind::FloatConstant(_)
+            | ExpressionKind::EnumConstant { .. }
+            | ExpressionKind::StringConstant(_)
+            | ExpressionKind::SizeOfType(_) => {}
+            ExpressionKind::Variable(name) => match self.symbols.lookup(name) {
+                Some(symbol) if symbol.kind.is_variable() => {}
+                Some(symbol) => {
+                    let message =
+                        format!("`{}` is a {}, not a variable", name, symbol.kind.as_str());
+                    self.error(message, expr.span);
+                }
+                None => self.undeclared(name, expr.span),
+            },
+            ExpressionKind::Index { array, index } => {
+                self.expression(array);
+                self.expression(index);
+            }
+            ExpressionKind::Member { base, .. } => self.expression(base),
+            ExpressionKind::Call { name, arguments } => {
+                match self.symbols.lookup(name) {
+                    Some(symbol) if matches!(symbol.kind, SymbolKind::Function(_)) => {
+                        // builtins are provided by the interpreter
+                        if !self.definitions.contains_key(name)
+                            && !builtins::is_builtin(name)
+                            && self.undefined.insert(name.clone())
+                        {
+                            let message = format!(
+                                "function `{}` is called but never defined, declared at line {}",
+                                name, symbol.span.line
+                            );
+                            self.error(message, expr.span);
+                        }
+                    }
+                    Some(symbol) => {
+                        let message =
+                            format!("`{}` is a {}, not a function", name, symbol.kind.as_str());
+                        self.error(message, expr.span);
+                    }
+                    None => self.undeclared(name, expr.span),
+                }
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExpressionKind::Unary { operand, .. }
+            | ExpressionKind::Cast { operand, .. }
+            | ExpressionKind::SizeOfExpression(operand) => self.expression(operand),
+            ExpressionKind::Binary { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    // each diagnostic of the resolver with the line and column it points at
+    fn diagnostics(source: &str) -> Vec<(String, i32, i32)> {
+        let program = Parser::from_source(source, "format.csv").parse();
+        analyze(&program)
+            .into_iter()
+            .map(|diagnostic| {
+                assert!(diagnostic.is_error());
+                (
+                    diagnostic.message,
+                    diagnostic.span.line,
+                    diagnostic.span.column,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn names_resolve_in_their_scope() {
+        let source = "int g;
+            int Twice(int n);
+            void main(){
+                int a;
+                a = Twice(g);
+                { int a; a = 1; }
+            }
+            int Twice(int n){ return n + n; }";
+        assert_eq!(diagnostics(source), vec![]);
+    }
+
+    #[test]
+    fn reports_undeclared_late_and_duplicate_names() {
+        let source = "void main(){
+                int a;
+                a = b;
+                { int c; c = d; int d; }
+                int a;
+            }";
+        let expected = vec![
+            (String::from("undeclared identifier `b`"), 3, 21),
+            (
+                String::from("`d` is used before its declaration at line 4"),
+                4,
+                30,
+            ),
+            (
+                String::from(
+                    "duplicate declaration of `a`, already declared as a local variable at line 2",
+                ),
+                5,
+                21,
+            ),
+        ];
+        assert_eq!(diagnostics(source), expected);
+    }
+
+    #[test]
+    fn prototypes_agree_with_their_definition() {
+        let source = "int Sum(int a[], int n);
+            int Sum(int *a, int n);
+            void main(){ int b[2]; b[0] = Sum(b, 2); }
+            int Sum(int *a, int n){ return a[0] + n; }";
+        assert_eq!(diagnostics(source), vec![]);
+    }
+
+    #[test]
+    fn reports_conflicting_missing_and_repeated_definitions() {
+        let source = "int Add(int a, int b);
+            long Add(int a, int b);
+            int Sub(int a);
+            void main(){ int x; x = Sub(2); }
+            int Add(int a, int b){ return a + b; }
+            int Add(int a, int b){ return a; }";
+        let expected = vec![
+            (
+                String::from("function `Add` is defined more than once, first at line 5"),
+                6,
+                17,
+            ),
+            (
+                String::from(
+                    "conflicting types for `Add`: `long (int, int)` here, but `int (int, int)` at line 1",
+                ),
+                2,
+                18,
+            ),
+            (
+                String::from("function `Sub` is called but never defined, declared at line 3"),
+                4,
+                37,
+            ),
+        ];
+        assert_eq!(diagnostics(source), expected);
+    }
+}
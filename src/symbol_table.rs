@@ -0,0 +1,127 @@
+use crate::ast::{Qualifiers, Type};
+use crate::builtins;
+use crate::token::Span;
+
+use std::collections::HashMap;
+
+/// What kind of entity a name was declared as
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolKind {
+    Global,
+    Parameter,
+    Local,
+    /// parameter types of the function, its return type is the symbol type
+    Function(Vec<Type>),
+    TypeName,
+    Enumerator(i64),
+}
+
+impl SymbolKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymbolKind::Global => "global variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Local => "local variable",
+            SymbolKind::Function(_) => "function",
+            SymbolKind::TypeName => "type name",
+            SymbolKind::Enumerator(_) => "enumerator",
+        }
+    }
+
+    pub fn is_variable(&self) -> bool {
+        matches!(
+            self,
+            SymbolKind::Global | SymbolKind::Parameter | SymbolKind::Local
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub data_type: Type,
+    pub qualifiers: Qualifiers,
+    pub span: Span,
+}
+
+impl Symbol {
+    /// type of a function symbol written like `float (int, char*)`, just the
+    /// type for any other symbol
+    pub fn signature(&self) -> String {
+        match &self.kind {
+            SymbolKind::Function(parameters) => {
+                let parameters: Vec<String> = parameters.iter().map(|p| p.to_string()).collect();
+                format!("{} ({})", self.data_type, parameters.join(", "))
+            }
+            _ => self.data_type.to_string(),
+        }
+    }
+}
+
+/// Names visible at a point of the program, one scope per block with the
+/// global scope first; inner scopes shadow outer ones
+#[derive(Clone, Debug)]
+pub struct SymbolTable {
+    scopes: Vec<HashMap<String, Symbol>>,
+}
+
+impl SymbolTable {
+    /// Create a table whose global scope holds only the builtin functions
+    pub fn new() -> SymbolTable {
+        let mut table = SymbolTable {
+            scopes: vec![HashMap::new()],
+        };
+        for function in builtins::declarations() {
+            table.declare(Symbol {
+                name: function.name,
+                kind: SymbolKind::Function(
+                    function.parameters.into_iter().map(|p| p.data_type).collect(),
+                ),
+                data_type: function.return_type,
+                qualifiers: function.qualifiers,
+                span: function.span,
+            });
+        }
+        table
+    }
+
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn exit_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    pub fn is_global_scope(&self) -> bool {
+        self.scopes.len() == 1
+    }
+
+    /// add a symbol to the innermost scope, replacing and returning a symbol of
+    /// the same name declared in that scope before
+    pub fn declare(&mut self, symbol: Symbol) -> Option<Symbol> {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(symbol.name.clone(), symbol)
+    }
+
+    /// the symbol a name refers to here, searching from the innermost scope out
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// the symbol of that name in the innermost scope only
+    pub fn lookup_current(&self, name: &str) -> Option<&Symbol> {
+        self.scopes.last().unwrap().get(name)
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable::new()
+    }
+}
@@ -1,25 +1,30 @@
-#[derive(Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug)]
 pub enum TokenType {
-    NONE,
     INTCONSTANT,
     FLOATCONSTANT,
+    ENUMCONSTANT,
+    STRINGCONSTANT,
     OPERATOR,
     KEYWORD,
     VARIABLE,
     FUNCTION,
+    QUALIFIER,
     INVALID,
 }
 
 impl TokenType {
     pub fn as_str(&self) -> &'static str {
         match &self {
-            TokenType::NONE => "None",
             TokenType::INTCONSTANT => "IntConstant",
             TokenType::FLOATCONSTANT => "FloatConstant",
+            TokenType::ENUMCONSTANT => "EnumConstant",
+            TokenType::STRINGCONSTANT => "StringConstant",
             TokenType::OPERATOR => "Operator",
             TokenType::KEYWORD => "Keyword",
             TokenType::VARIABLE => "Variable",
             TokenType::FUNCTION => "Function",
+            TokenType::QUALIFIER => "Qualifier",
             TokenType::INVALID => "Invalid",
         }
     }
@@ -31,7 +36,14 @@ impl PartialEq for TokenType {
     }
 }
 
-#[derive(Clone)]
+/// Position of a token in the source file, both counted from 1
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub line: i32,
+    pub column: i32,
+}
+
+#[derive(Clone, Debug)]
 pub struct Token {
     text: String,
     token_type: TokenType,
@@ -57,6 +69,11 @@ impl Token {
         &self.token_type
     }
 
+    /// the scanner cannot tell every category, the parser refines it where it can
+    pub fn set_type(&mut self, t: TokenType) {
+        self.token_type = t;
+    }
+
     pub fn get_line_number(&self) -> i32 {
         self.line_number
     }
@@ -65,25 +82,39 @@ impl Token {
         self.char_position
     }
 
+    pub fn get_span(&self) -> Span {
+        Span {
+            line: self.line_number,
+            column: self.char_position,
+        }
+    }
+
     pub fn is_type(&self) -> bool {
-        let types = vec![
+        let types = [
             "unsigned", "char", "short", "int", "long", "float", "double",
         ];
         types.contains(&self.get_text())
     }
 
     pub fn is_relational_op(&self) -> bool {
-        let relational_ops = vec!["==", "<", ">", "<=", ">=", "!="];
+        let relational_ops = ["==", "<", ">", "<=", ">=", "!="];
         self.token_type == TokenType::OPERATOR && relational_ops.contains(&self.get_text())
     }
 
     pub fn is_add_op(&self) -> bool {
-        let add_ops = vec!["+", "-"];
+        let add_ops = ["+", "-"];
         self.token_type == TokenType::OPERATOR && add_ops.contains(&self.get_text())
     }
 
+    /// `*` is only a multiplication between two operands, in front of an operand
+    /// it is a dereference; the parser decides by position, see `is_unary_op`
     pub fn is_mult_op(&self) -> bool {
-        let mult_ops = vec!["*", "/"];
+        let mult_ops = ["*", "/"];
         self.token_type == TokenType::OPERATOR && mult_ops.contains(&self.get_text())
     }
+
+    pub fn is_unary_op(&self) -> bool {
+        let unary_ops = ["-", "*", "&"];
+        self.token_type == TokenType::OPERATOR && unary_ops.contains(&self.get_text())
+    }
 }
@@ -0,0 +1,752 @@
+use crate::ast::*;
+use crate::builtins::{self, FormatPiece};
+use crate::const_eval::evaluate;
+use crate::diagnostic::Diagnostic;
+use crate::symbol_table::{Symbol, SymbolKind, SymbolTable};
+use crate::token::Span;
+use crate::value::{
+    int_literal_type, integer_bits, integer_range, promote, usual_arithmetic_conversion, Number,
+};
+
+use std::collections::HashMap;
+
+/// Check the types of every expression, assignment and return of the program
+///
+/// # Arguments
+///
+/// * `program` - syntax tree from the parser, names already resolved
+///
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut checker = TypeChecker::new();
+    checker.program(program);
+    checker.diagnostics
+}
+
+const INT: Type = Type::Integer {
+    kind: IntKind::Int,
+    unsigned: false,
+};
+
+/// type of `sizeof`, an unsigned long as size_t is on 64 bit targets
+const SIZE_TYPE: Type = Type::Integer {
+    kind: IntKind::Long,
+    unsigned: true,
+};
+
+fn is_arithmetic(data_type: &Type) -> bool {
+    matches!(data_type, Type::Integer { .. } | Type::Float | Type::Double)
+}
+
+fn is_integer(data_type: &Type) -> bool {
+    matches!(data_type, Type::Integer { .. })
+}
+
+fn is_floating(data_type: &Type) -> bool {
+    matches!(data_type, Type::Float | Type::Double)
+}
+
+/// true when converting a value of type `from` to `to` may change it; constants
+/// are judged by their value instead of their type
+fn is_lossy(from: &Type, to: &Type, value: &Expression) -> bool {
+    match (evaluate(value, &|_| None), to) {
+        (Ok(Number::Integer { value, .. }), Type::Integer { kind, unsigned }) => {
+            let (min, max) = integer_range(*kind, *unsigned);
+            return value < min || value > max;
+        }
+        (Ok(Number::Float(_) | Number::Double(_)), Type::Float | Type::Double) => {
+            return false;
+        }
+        _ => {}
+    }
+    match (from, to) {
+        (Type::Float | Type::Double, Type::Integer { .. }) => true,
+        (Type::Double, Type::Float) => true,
+        (
+            Type::Integer {
+                kind: from_kind,
+                unsigned: from_unsigned,
+            },
+            Type::Integer {
+                kind: to_kind,
+                unsigned: to_unsigned,
+            },
+        ) => {
+            let (from_min, from_max) = integer_range(*from_kind, *from_unsigned);
+            let (to_min, to_max) = integer_range(*to_kind, *to_unsigned);
+            from_min < to_min || from_max > to_max
+        }
+        // a float keeps 24 bits of precision and a double 53
+        (Type::Integer { kind, .. }, Type::Float) => integer_bits(*kind) >= 32,
+        (Type::Integer { kind, .. }, Type::Double) => integer_bits(*kind) >= 64,
+        _ => false,
+    }
+}
+
+fn is_null_constant(value: &Expression) -> bool {
+    matches!(
+        evaluate(value, &|_| None),
+        Ok(Number::Integer { value: 0, .. })
+    )
+}
+
+/// Static type checker: computes the type of every expression and reports
+/// operands, assignments and returns whose types do not fit
+pub struct TypeChecker {
+    symbols: SymbolTable,
+    // struct members by tag, one map per open scope
+    structs: Vec<HashMap<String, Vec<Member>>>,
+    // return type of the function being checked
+    return_type: Type,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        TypeChecker {
+            symbols: SymbolTable::new(),
+            structs: vec![HashMap::new()],
+            return_type: Type::Void,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::error(message, span));
+    }
+
+    fn warning(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::warning(message, span));
+    }
+
+    fn enter_scope(&mut self) {
+        self.symbols.enter_scope();
+        self.structs.push(HashMap::new());
+    }
+
+    fn exit_scope(&mut self) {
+        self.symbols.exit_scope();
+        self.structs.pop();
+    }
+
+    fn lookup_struct(&self, name: &str) -> Option<&Vec<Member>> {
+        self.structs.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn program(&mut self, program: &Program) {
+        for declaration in &program.declarations {
+            self.declaration(declaration);
+        }
+        self.function(&program.main);
+        for function in &program.functions {
+            self.function(function);
+        }
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        match declaration {
+            Declaration::Variable(variable) => {
+                let kind = match self.symbols.is_global_scope() {
+                    true => SymbolKind::Global,
+                    false => SymbolKind::Local,
+                };
+                self.symbols.declare(Symbol {
+                    name: variable.name.clone(),
+                    kind,
+                    data_type: variable.data_type.clone(),
+                    qualifiers: variable.qualifiers,
+                    span: variable.span,
+                });
+                if let Some(initializer) = &variable.initializer {
+                    self.initializer(&variable.data_type, initializer);
+                }
+            }
+            Declaration::Function(function) => self.declare_function(function),
+            Declaration::Struct(declaration) => {
+                self.structs
+                    .last_mut()
+                    .unwrap()
+                    .insert(declaration.name.clone(), declaration.members.clone());
+            }
+            // typedef names and enumerators are already replaced by the parser
+            Declaration::Typedef(_) | Declaration::Enum(_) => {}
+        }
+    }
+
+    fn declare_function(&mut self, function: &FunctionDeclaration) {
+        self.symbols.declare(Symbol {
+            name: function.name.clone(),
+            kind: SymbolKind::Function(
+                function
+                    .parameters
+                    .iter()
+                    .map(|p| p.data_type.clone())
+                    .collect(),
+            ),
+            data_type: function.return_type.clone(),
+            qualifiers: function.qualifiers,
+            span: function.span,
+        });
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.declare_function(&function.declaration);
+        self.return_type = function.declaration.return_type.clone();
+
+        self.enter_scope();
+        for parameter in &function.declaration.parameters {
+            self.symbols.declare(Symbol {
+                name: parameter.name.clone(),
+                kind: SymbolKind::Parameter,
+                data_type: parameter.data_type.clone(),
+                qualifiers: parameter.qualifiers,
+                span: parameter.span,
+            });
+        }
+        for statement in &function.body.statements {
+            self.statement(statement);
+        }
+        self.exit_scope();
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.enter_scope();
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        self.exit_scope();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Assignment { targets, value } => {
+                // `a = b = v` stores v in b first, then the value of b in a
+                let mut source = self.type_of(value);
+                let mut source_expr = value;
+                for target in targets.iter().rev() {
+                    let target_type = self.type_of(target);
+                    if let (Some(to), Some(from)) = (&target_type, &source) {
+                        if to.is_array() {
+                            self.error(format!("cannot assign to array `{}`", to), target.span);
+                        } else {
+                            self.check_conversion(from, to, source_expr, "assignment");
+                        }
+                    }
+                    source = target_type;
+                    source_expr = target;
+                }
+            }
+            StatementKind::While { condition, body } | StatementKind::If { condition, body } => {
+                self.condition(condition);
+                self.block(body);
+            }
+            StatementKind::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                if let Some(init) = init {
+                    self.statement(init);
+                }
+                if let Some(condition) = condition {
+                    self.condition(condition);
+                }
+                if let Some(step) = step {
+                    self.statement(step);
+                }
+                self.block(body);
+            }
+            StatementKind::Switch { value, body, cases } => {
+                if let Some(data_type) = self.type_of(value) {
+                    if !matches!(data_type, Type::Integer { .. }) {
+                        let message =
+                            format!("switch value of type `{}` is not an integer", data_type);
+                        self.error(message, value.span);
+                    }
+                }
+                for label in cases.iter().filter_map(|case| case.label.as_ref()) {
+                    self.type_of(label);
+                }
+                self.block(body);
+            }
+            StatementKind::Return(value) => self.return_statement(value, statement.span),
+            StatementKind::Call(call) => {
+                self.type_of(call);
+            }
+            StatementKind::Declaration(declaration) => self.declaration(declaration),
+            StatementKind::Block(block) => self.block(block),
+            StatementKind::Break => {}
+        }
+    }
+
+    fn condition(&mut self, condition: &Expression) {
+        if let Some(data_type) = self.type_of(condition) {
+            if !data_type.is_scalar() && !data_type.is_array() {
+                let message = format!(
+                    "condition of type `{}` is not a number or pointer",
+                    data_type
+                );
+                self.error(message, condition.span);
+            }
+        }
+    }
+
+    fn return_statement(&mut self, value: &Option<Expression>, span: Span) {
+        let return_type = self.return_type.clone();
+        match value {
+            Some(value) => {
+                let data_type = self.type_of(value);
+                if return_type == Type::Void {
+                    self.error(
+                        String::from("a void function cannot return a value"),
+                        value.span,
+                    );
+                } else if let Some(data_type) = data_type {
+                    self.check_conversion(&data_type, &return_type, value, "return");
+                }
+            }
+            None => {
+                if return_type != Type::Void {
+                    let message = format!("missing return value of type `{}`", return_type);
+                    self.error(message, span);
+                }
+            }
+        }
+    }
+
+    fn initializer(&mut self, data_type: &Type, initializer: &Initializer) {
+        match initializer {
+            Initializer::Expression(value) => {
+                if let Some(from) = self.type_of(value) {
+                    self.check_conversion(&from, data_type, value, "initialization");
+                }
+            }
+            Initializer::List(items, _) => match data_type {
+                Type::Array(element, _) => {
+                    for item in items {
+                        self.initializer(element, item);
+                    }
+                }
+                Type::Struct(name) => {
+                    let members = self.lookup_struct(name).cloned().unwrap_or_default();
+                    for (member, item) in members.iter().zip(items) {
+                        self.initializer(&member.data_type, item);
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    /// report a value of type `from` that cannot be stored in a `to`,
+    /// and warn when storing it may lose data
+    fn check_conversion(&mut self, from: &Type, to: &Type, value: &Expression, context: &str) {
+        let compatible = match (from, to) {
+            _ if is_arithmetic(from) && is_arithmetic(to) => true,
+            (Type::Pointer(_), Type::Pointer(target))
+            | (Type::Array(_, _), Type::Pointer(target)) => {
+                **target == Type::Void
+                    || from.pointee().unwrap() == &**target
+                    || from.pointee().unwrap() == &Type::Void
+            }
+            (Type::Integer { .. }, Type::Pointer(_)) => is_null_constant(value),
+            (Type::Struct(left), Type::Struct(right)) => left == right,
+            _ => false,
+        };
+        if !compatible {
+            let message = format!(
+                "mismatched types in {}: expected `{}`, found `{}`",
+                context, to, from
+            );
+            self.error(message, value.span);
+        } else if is_arithmetic(from) && is_arithmetic(to) && is_lossy(from, to, value) {
+            let message = format!(
+                "implicit conversion from `{}` to `{}` in {} may lose data",
+                from, to, context
+            );
+            self.warning(message, value.span);
+        }
+    }
+
+    /// type of an expression, None when it cannot be known, e.g. for an
+    /// undeclared name already reported by the scope resolution pass
+    fn type_of(&mut self, expr: &Expression) -> Option<Type> {
+        match &expr.kind {
+            ExpressionKind::IntConstant(n) => Some(int_literal_type(*n)),
+            // constants without a suffix are doubles, as in C
+            ExpressionKind::FloatConstant(_) => Some(Type::Double),
+            ExpressionKind::EnumConstant { .. } => Some(INT),
+            ExpressionKind::StringConstant(_) => {
+                self.error(
+                    String::from("a string literal can only be the format of `printf`"),
+                    expr.span,
+                );
+                None
+            }
+            ExpressionKind::Variable(name) => match self.symbols.lookup(name) {
+                Some(symbol) if symbol.kind.is_variable() => Some(symbol.data_type.clone()),
+                _ => None,
+            },
+            ExpressionKind::Index { array, index } => {
+                let array_type = self.type_of(array);
+                let index_type = self.type_of(index);
+                if let Some(index_type) = index_type {
+                    if !is_integer(&index_type) {
+                        let message =
+                            format!("array index of type `{}` is not an integer", index_type);
+                        self.error(message, index.span);
+                    }
+                }
+                match array_type? {
+                    Type::Array(element, _) | Type::Pointer(element) => Some(*element),
+                    data_type => {
+                        let message = format!("cannot index a value of type `{}`", data_type);
+                        self.error(message, array.span);
+                        None
+                    }
+                }
+            }
+            ExpressionKind::Member { base, member } => match self.type_of(base)? {
+                Type::Struct(name) => self
+                    .lookup_struct(&name)?
+                    .iter()
+                    .find(|m| &m.name == member)
+                    .map(|m| m.data_type.clone()),
+                data_type => {
+                    let message =
+                        format!("member `{}` of a non-struct type `{}`", member, data_type);
+                    self.error(message, base.span);
+                    None
+                }
+            },
+            ExpressionKind::Call { name, arguments } => self.call(name, arguments, expr.span),
+            ExpressionKind::Unary { operator, operand } => {
+                let data_type = self.type_of(operand)?;
+                match operator {
+                    UnaryOperator::Negate if is_arithmetic(&data_type) => Some(promote(&data_type)),
+                    UnaryOperator::Negate => {
+                        let message = format!("cannot negate a value of type `{}`", data_type);
+                        self.error(message, expr.span);
+                        None
+                    }
+                    UnaryOperator::Dereference => match data_type.pointee() {
+                        Some(target) if *target != Type::Void => Some(target.clone()),
+                        _ => {
+                            let message =
+                                format!("cannot dereference a value of type `{}`", data_type);
+                            self.error(message, expr.span);
+                            None
+                        }
+                    },
+                    UnaryOperator::AddressOf => Some(Type::Pointer(Box::new(data_type))),
+                }
+            }
+            ExpressionKind::Cast { data_type, operand } => {
+                let from = self.type_of(operand)?;
+                let valid = *data_type == Type::Void
+                    || (data_type.is_scalar() && (from.is_scalar() || from.is_array()));
+                let pointer_float = (data_type.is_pointer() && is_floating(&from))
+                    || (is_floating(data_type) && (from.is_pointer() || from.is_array()));
+                if !valid || pointer_float {
+                    let message = format!("cannot cast `{}` to `{}`", from, data_type);
+                    self.error(message, expr.span);
+                }
+                Some(data_type.clone())
+            }
+            ExpressionKind::SizeOfType(_) => Some(SIZE_TYPE),
+            ExpressionKind::SizeOfExpression(operand) => {
+                self.type_of(operand);
+                Some(SIZE_TYPE)
+            }
+            ExpressionKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let left_type = self.type_of(left)?;
+                let right_type = self.type_of(right)?;
+                self.binary(*operator, &left_type, &right_type, expr.span)
+            }
+        }
+    }
+
+    // check the arguments of a call against the parameters of the function
+    fn call(&mut self, name: &str, arguments: &[Expression], span: Span) -> Option<Type> {
+        if name == builtins::PRINTF {
+            return self.printf(arguments, span);
+        }
+        let argument_types: Vec<Option<Type>> = arguments
+            .iter()
+            .map(|argument| self.type_of(argument))
+            .collect();
+        let symbol = self.symbols.lookup(name)?.clone();
+        let parameters = match &symbol.kind {
+            SymbolKind::Function(parameters) => parameters,
+            _ => return None,
+        };
+
+        if arguments.len() != parameters.len() {
+            let message = format!(
+                "`{}` takes {} argument{} but {} {} given",
+                name,
+                parameters.len(),
+                if parameters.len() == 1 { "" } else { "s" },
+                arguments.len(),
+                if arguments.len() == 1 { "was" } else { "were" }
+            );
+            self.error(message, span);
+        }
+        for (i, (parameter, argument)) in parameters.iter().zip(arguments).enumerate() {
+            // an array parameter receives a pointer to the first element
+            let parameter = match parameter {
+                Type::Array(element, _) => Type::Pointer(element.clone()),
+                _ => parameter.clone(),
+            };
+            if let Some(from) = &argument_types[i] {
+                let context = format!("argument {} of `{}`", i + 1, name);
+                self.check_conversion(from, &parameter, argument, &context);
+            }
+        }
+        Some(symbol.data_type)
+    }
+
+    // the format of printf is a string literal, its conversions give the
+    // number and the types of the arguments after it
+    fn printf(&mut self, arguments: &[Expression], span: Span) -> Option<Type> {
+        let (format, rest) = match arguments.split_first() {
+            Some(split) => split,
+            None => {
+                self.error(String::from("`printf` needs a format"), span);
+                return Some(INT);
+            }
+        };
+        let argument_types: Vec<Option<Type>> =
+            rest.iter().map(|argument| self.type_of(argument)).collect();
+        let pieces = match &format.kind {
+            ExpressionKind::StringConstant(text) => builtins::parse_format(text),
+            _ => Err(String::from(
+                "the format of `printf` must be a string literal",
+            )),
+        };
+        let pieces = match pieces {
+            Ok(pieces) => pieces,
+            Err(message) => {
+                self.error(message, format.span);
+                return Some(INT);
+            }
+        };
+
+        let expected = builtins::conversions(&pieces);
+        if expected != rest.len() {
+            let message = format!(
+                "the format of `printf` takes {} argument{} but {} {} given",
+                expected,
+                if expected == 1 { "" } else { "s" },
+                rest.len(),
+                if rest.len() == 1 { "was" } else { "were" }
+            );
+            self.error(message, span);
+        }
+        let conversions = pieces
+            .iter()
+            .filter(|piece| !matches!(piece, FormatPiece::Text(_)));
+        for (i, (conversion, data_type)) in conversions.zip(argument_types).enumerate() {
+            let data_type = match data_type {
+                Some(data_type) => data_type,
+                None => continue,
+            };
+            let (valid, text, expected) = match conversion {
+                FormatPiece::Integer => (is_integer(&data_type), "%d", "an integer"),
+                FormatPiece::Character => (is_integer(&data_type), "%c", "an integer"),
+                _ => (is_floating(&data_type), "%f", "a floating value"),
+            };
+            if !valid {
+                let message = format!(
+                    "mismatched types in argument {} of `printf`: `{}` expects {}, found `{}`",
+                    i + 2,
+                    text,
+                    expected,
+                    data_type
+                );
+                self.error(message, rest[i].span);
+            }
+        }
+        Some(INT)
+    }
+
+    fn binary(
+        &mut self,
+        operator: BinaryOperator,
+        left: &Type,
+        right: &Type,
+        span: Span,
+    ) -> Option<Type> {
+        // arrays used as values are pointers to their first element
+        let decay = |data_type: &Type| match data_type {
+            Type::Array(element, _) => Type::Pointer(element.clone()),
+            _ => data_type.clone(),
+        };
+        let (left, right) = (decay(left), decay(right));
+        let long = Type::Integer {
+            kind: IntKind::Long,
+            unsigned: false,
+        };
+
+        let result = if is_arithmetic(&left) && is_arithmetic(&right) {
+            match operator.is_relational() {
+                true => Some(INT),
+                false => Some(usual_arithmetic_conversion(&left, &right)),
+            }
+        } else {
+            match (operator, &left, &right) {
+                (BinaryOperator::Add, Type::Pointer(_), Type::Integer { .. })
+                | (BinaryOperator::Subtract, Type::Pointer(_), Type::Integer { .. }) => {
+                    Some(left.clone())
+                }
+                (BinaryOperator::Add, Type::Integer { .. }, Type::Pointer(_)) => {
+                    Some(right.clone())
+                }
+                (BinaryOperator::Subtract, Type::Pointer(a), Type::Pointer(b)) if a == b => {
+                    Some(long)
+                }
+                (_, Type::Pointer(a), Type::Pointer(b)) if operator.is_relational() && a == b => {
+                    Some(INT)
+                }
+                (
+                    BinaryOperator::Equal | BinaryOperator::NotEqual,
+                    Type::Pointer(_),
+                    Type::Integer { .. },
+                )
+                | (
+                    BinaryOperator::Equal | BinaryOperator::NotEqual,
+                    Type::Integer { .. },
+                    Type::Pointer(_),
+                ) => Some(INT),
+                _ => None,
+            }
+        };
+        if result.is_none() {
+            let message = format!(
+                "invalid operands to `{}`: `{}` and `{}`",
+                operator.as_str(),
+                left,
+                right
+            );
+            self.error(message, span);
+        }
+        result
+    }
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        TypeChecker::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    // each diagnostic of the type checker with the line and column it points at
+    fn diagnostics(source: &str) -> Vec<(String, i32, i32)> {
+        let program = Parser::from_source(source, "format.csv").parse();
+        check(&program)
+            .into_iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.message,
+                    diagnostic.span.line,
+                    diagnostic.span.column,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accepts_well_typed_programs() {
+        let source = "struct P { int x; double y; };
+            void main(){
+                struct P p;
+                int b[3];
+                int c;
+                p.x = 1;
+                p.y = p.x * 2.5;
+                c = b[p.x] + 1;
+                b[0] = c;
+            }";
+        assert_eq!(diagnostics(source), vec![]);
+    }
+
+    #[test]
+    fn reports_operands_of_the_wrong_type() {
+        let source = "struct P { int x; };
+            void main(){
+                int a;
+                struct P p;
+                int b[3];
+                char c;
+                a = p;
+                b = 1;
+                a = -p;
+                a = b[p];
+                c = a;
+            }";
+        let expected = vec![
+            (
+                String::from("mismatched types in assignment: expected `int`, found `struct P`"),
+                7,
+                21,
+            ),
+            (String::from("cannot assign to array `int[3]`"), 8, 17),
+            (
+                String::from("cannot negate a value of type `struct P`"),
+                9,
+                21,
+            ),
+            (
+                String::from("array index of type `struct P` is not an integer"),
+                10,
+                23,
+            ),
+            (
+                String::from(
+                    "implicit conversion from `int` to `char` in assignment may lose data",
+                ),
+                11,
+                21,
+            ),
+        ];
+        assert_eq!(diagnostics(source), expected);
+    }
+
+    #[test]
+    fn calls_match_the_parameters() {
+        let source = "struct P { int x; };
+            int Sum(int *a, int n);
+            void main(){
+                struct P p;
+                int b[2];
+                b[0] = Sum(b, 2);
+                b[0] = Sum(b);
+                b[0] = Sum(b, p);
+            }
+            int Sum(int *a, int n){ return a[0] + n; }";
+        let expected = vec![
+            (
+                String::from("`Sum` takes 2 arguments but 1 was given"),
+                7,
+                24,
+            ),
+            (
+                String::from(
+                    "mismatched types in argument 2 of `Sum`: expected `int`, found `struct P`",
+                ),
+                8,
+                31,
+            ),
+        ];
+        assert_eq!(diagnostics(source), expected);
+    }
+}
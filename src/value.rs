@@ -0,0 +1,563 @@
+use crate::ast::*;
+
+use std::fmt;
+
+/// A number held with the type C gives it, computed the way a C compiler
+/// targeting a 64 bit machine computes it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Number {
+    /// always within the range of its type
+    Integer {
+        value: i128,
+        kind: IntKind,
+        unsigned: bool,
+    },
+    Float(f32),
+    Double(f64),
+}
+
+/// Why an operation on numbers has no result
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArithmeticError {
+    DivisionByZero,
+    /// signed result that does not fit its type
+    Overflow(Type),
+    /// floating value converted to an integer type too narrow for it
+    OutOfRange(Type),
+}
+
+const INT: Type = Type::Integer {
+    kind: IntKind::Int,
+    unsigned: false,
+};
+
+fn is_arithmetic(data_type: &Type) -> bool {
+    matches!(data_type, Type::Integer { .. } | Type::Float | Type::Double)
+}
+
+/// number of bits of an integer type
+pub fn integer_bits(kind: IntKind) -> u32 {
+    let data_type = Type::Integer {
+        kind,
+        unsigned: false,
+    };
+    data_type.size(&|_| None).unwrap() as u32 * 8
+}
+
+/// smallest and largest value of an integer type
+pub fn integer_range(kind: IntKind, unsigned: bool) -> (i128, i128) {
+    let bits = integer_bits(kind);
+    match unsigned {
+        true => (0, (1i128 << bits) - 1),
+        false => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+    }
+}
+
+/// reduce a value modulo 2^bits into the range of the type, as a conversion
+/// to an unsigned type does and as two's complement hardware does for signed ones
+pub fn wrap(value: i128, kind: IntKind, unsigned: bool) -> i128 {
+    let modulus = 1i128 << integer_bits(kind);
+    let (min, _) = integer_range(kind, unsigned);
+    (value - min).rem_euclid(modulus) + min
+}
+
+/// type of an integer literal: the first of int, long and unsigned long its value fits
+pub fn int_literal_type(value: u64) -> Type {
+    let (kind, unsigned) = if value <= i32::MAX as u64 {
+        (IntKind::Int, false)
+    } else if value <= i64::MAX as u64 {
+        (IntKind::Long, false)
+    } else {
+        (IntKind::Long, true)
+    };
+    Type::Integer { kind, unsigned }
+}
+
+/// char and short are computed as int
+pub fn promote(data_type: &Type) -> Type {
+    match data_type {
+        Type::Integer {
+            kind: IntKind::Char | IntKind::Short,
+            ..
+        } => INT,
+        _ => data_type.clone(),
+    }
+}
+
+/// C's usual arithmetic conversions: the common type both operands of a
+/// binary operator are converted to
+pub fn usual_arithmetic_conversion(left: &Type, right: &Type) -> Type {
+    if *left == Type::Double || *right == Type::Double {
+        return Type::Double;
+    }
+    if *left == Type::Float || *right == Type::Float {
+        return Type::Float;
+    }
+    match (promote(left), promote(right)) {
+        (
+            Type::Integer {
+                kind: left_kind,
+                unsigned: left_unsigned,
+            },
+            Type::Integer {
+                kind: right_kind,
+                unsigned: right_unsigned,
+            },
+        ) => {
+            let (kind, unsigned) = if left_unsigned == right_unsigned {
+                (left_kind.max(right_kind), left_unsigned)
+            } else {
+                let (signed, unsigned) = match left_unsigned {
+                    true => (right_kind, left_kind),
+                    false => (left_kind, right_kind),
+                };
+                if unsigned >= signed {
+                    (unsigned, true)
+                } else if integer_bits(signed) > integer_bits(unsigned) {
+                    // the signed type holds every value of the unsigned one
+                    (signed, false)
+                } else {
+                    (signed, true)
+                }
+            };
+            Type::Integer { kind, unsigned }
+        }
+        (left, _) => left,
+    }
+}
+
+impl Number {
+    /// an int, the type of small literals and of comparisons
+    pub fn int(value: i128) -> Number {
+        Number::Integer {
+            value,
+            kind: IntKind::Int,
+            unsigned: false,
+        }
+    }
+
+    /// zero of an arithmetic type, None for any other type
+    pub fn zero(data_type: &Type) -> Option<Number> {
+        match is_arithmetic(data_type) {
+            true => Number::int(0).convert(data_type).ok(),
+            false => None,
+        }
+    }
+
+    pub fn data_type(&self) -> Type {
+        match self {
+            Number::Integer { kind, unsigned, .. } => Type::Integer {
+                kind: *kind,
+                unsigned: *unsigned,
+            },
+            Number::Float(_) => Type::Float,
+            Number::Double(_) => Type::Double,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i128> {
+        match self {
+            Number::Integer { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Number::Integer { value, .. } => *value as f64,
+            Number::Float(x) => *x as f64,
+            Number::Double(x) => *x,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.as_f64() == 0.0
+    }
+
+    /// the value converted to an arithmetic type, as an assignment or a cast
+    /// does: integers wrap to the width, floating values truncate toward zero
+    /// and round to the nearest value of the narrower floating type
+    ///
+    /// # Panics
+    ///
+    /// when `to` is not an integer or floating type
+    pub fn convert(&self, to: &Type) -> Result<Number, ArithmeticError> {
+        match to {
+            Type::Integer { kind, unsigned } => {
+                let value = match self {
+                    Number::Integer { value, .. } => *value,
+                    Number::Float(_) | Number::Double(_) => {
+                        let x = self.as_f64();
+                        let (min, max) = integer_range(*kind, *unsigned);
+                        let truncated = x.trunc();
+                        // max + 1 is a power of two and exact as a double,
+                        // where max itself may round up to it
+                        if x.is_nan() || truncated < min as f64 || truncated >= (max + 1) as f64 {
+                            return Err(ArithmeticError::OutOfRange(to.clone()));
+                        }
+                        truncated as i128
+                    }
+                };
+                Ok(Number::Integer {
+                    value: wrap(value, *kind, *unsigned),
+                    kind: *kind,
+                    unsigned: *unsigned,
+                })
+            }
+            // rounded once, straight from the source value
+            Type::Float => Ok(Number::Float(match self {
+                Number::Integer { value, .. } => *value as f32,
+                Number::Float(x) => *x,
+                Number::Double(x) => *x as f32,
+            })),
+            Type::Double => Ok(Number::Double(self.as_f64())),
+            _ => panic!("conversion of a number to `{}`", to),
+        }
+    }
+
+    /// `-x`: integers are promoted first, and negating the smallest signed
+    /// value overflows; floating values only change sign, so -0.0 stays apart
+    /// from 0.0
+    pub fn negate(&self) -> Result<Number, ArithmeticError> {
+        match self {
+            Number::Float(x) => Ok(Number::Float(-x)),
+            Number::Double(x) => Ok(Number::Double(-x)),
+            Number::Integer { .. } => Number::int(0).binary(BinaryOperator::Subtract, *self),
+        }
+    }
+
+    /// apply a binary operator, converting both operands to their common type
+    /// first; relational operators give an int that is 0 or 1
+    pub fn binary(
+        &self,
+        operator: BinaryOperator,
+        right: Number,
+    ) -> Result<Number, ArithmeticError> {
+        let common = usual_arithmetic_conversion(&self.data_type(), &right.data_type());
+        let left = self.convert(&common)?;
+        let right = right.convert(&common)?;
+
+        if operator.is_relational() {
+            let result = match (left, right) {
+                (Number::Integer { value: a, .. }, Number::Integer { value: b, .. }) => {
+                    compare(operator, a.partial_cmp(&b))
+                }
+                _ => compare(operator, left.as_f64().partial_cmp(&right.as_f64())),
+            };
+            return Ok(Number::int(result as i128));
+        }
+
+        match (left, right) {
+            (
+                Number::Integer {
+                    value: a,
+                    kind,
+                    unsigned,
+                },
+                Number::Integer { value: b, .. },
+            ) => {
+                let value = match operator {
+                    BinaryOperator::Add => a + b,
+                    BinaryOperator::Subtract => a - b,
+                    // the exact product may not fit in i128, but its low bits do
+                    BinaryOperator::Multiply if unsigned => {
+                        (a as u128).wrapping_mul(b as u128) as i128
+                    }
+                    BinaryOperator::Multiply => a.checked_mul(b).unwrap_or(i128::MAX),
+                    BinaryOperator::Divide if b == 0 => {
+                        return Err(ArithmeticError::DivisionByZero)
+                    }
+                    // rounds toward zero, as C does
+                    BinaryOperator::Divide => a / b,
+                    _ => unreachable!(),
+                };
+                let (min, max) = integer_range(kind, unsigned);
+                if !unsigned && (value < min || value > max) {
+                    return Err(ArithmeticError::Overflow(common));
+                }
+                Ok(Number::Integer {
+                    value: wrap(value, kind, unsigned),
+                    kind,
+                    unsigned,
+                })
+            }
+            // IEEE arithmetic in the precision of the type, 1.0 / 0.0 is infinity
+            (Number::Float(a), Number::Float(b)) => Ok(Number::Float(match operator {
+                BinaryOperator::Add => a + b,
+                BinaryOperator::Subtract => a - b,
+                BinaryOperator::Multiply => a * b,
+                BinaryOperator::Divide => a / b,
+                _ => unreachable!(),
+            })),
+            (left, right) => {
+                let (a, b) = (left.as_f64(), right.as_f64());
+                Ok(Number::Double(match operator {
+                    BinaryOperator::Add => a + b,
+                    BinaryOperator::Subtract => a - b,
+                    BinaryOperator::Multiply => a * b,
+                    BinaryOperator::Divide => a / b,
+                    _ => unreachable!(),
+                }))
+            }
+        }
+    }
+}
+
+/// outcome of a relational operator; every comparison with NaN is false, but !=
+fn compare(operator: BinaryOperator, ordering: Option<std::cmp::Ordering>) -> bool {
+    use std::cmp::Ordering::*;
+    let ordering = match ordering {
+        Some(ordering) => ordering,
+        None => return operator == BinaryOperator::NotEqual,
+    };
+    match operator {
+        BinaryOperator::Equal => ordering == Equal,
+        BinaryOperator::NotEqual => ordering != Equal,
+        BinaryOperator::Less => ordering == Less,
+        BinaryOperator::Greater => ordering == Greater,
+        BinaryOperator::LessEqual => ordering != Greater,
+        BinaryOperator::GreaterEqual => ordering != Less,
+        _ => unreachable!(),
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Number::Integer { value, .. } => write!(f, "{}", value),
+            Number::Float(x) => write!(f, "{}", x),
+            Number::Double(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    // expected values are what gcc gives on x86-64 for the same C expression
+    fn integer(value: i128, kind: IntKind, unsigned: bool) -> Number {
+        Number::Integer {
+            value,
+            kind,
+            unsigned,
+        }
+    }
+
+    fn int_type(kind: IntKind, unsigned: bool) -> Type {
+        Type::Integer { kind, unsigned }
+    }
+
+    const U64_MAX: i128 = u64::MAX as i128;
+
+    #[test]
+    fn integer_conversions_wrap() {
+        use IntKind::*;
+        let cases = [
+            // (value, from kind, from unsigned, to kind, to unsigned, C result)
+            (200, Int, false, Char, false, -56),
+            (-129, Int, false, Char, false, 127),
+            (-1, Int, false, Char, true, 255),
+            (256, Int, false, Char, true, 0),
+            (40000, Int, false, Short, false, -25536),
+            (-1, Int, false, Short, true, 65535),
+            (3000000000, Long, false, Int, false, -1294967296),
+            (-1, Int, false, Int, true, 4294967295),
+            (U64_MAX, Long, true, Long, false, -1),
+            (-1, Long, false, Long, true, U64_MAX),
+        ];
+        for (value, from, from_unsigned, to, to_unsigned, expected) in cases {
+            let data_type = int_type(to, to_unsigned);
+            let result = integer(value, from, from_unsigned).convert(&data_type);
+            let expected = Ok(integer(expected, to, to_unsigned));
+            assert_eq!(result, expected, "({}) {}", data_type, value);
+        }
+    }
+
+    // what gcc gives for every operator on every pair of arithmetic types,
+    // written by value_table.c
+    const VALUE_TABLE: &str = include_str!("value_table.txt");
+
+    fn table_type(name: &str) -> Type {
+        use IntKind::*;
+        let (kind, unsigned) = match name {
+            "float" => return Type::Float,
+            "double" => return Type::Double,
+            "char" => (Char, false),
+            "uchar" => (Char, true),
+            "short" => (Short, false),
+            "ushort" => (Short, true),
+            "int" => (Int, false),
+            "uint" => (Int, true),
+            "long" => (Long, false),
+            "ulong" => (Long, true),
+            _ => panic!("unknown type `{}` in the value table", name),
+        };
+        int_type(kind, unsigned)
+    }
+
+    fn table_number(text: &str, data_type: &Type) -> Number {
+        match data_type {
+            Type::Float => Number::Float(text.parse().unwrap()),
+            Type::Double => Number::Double(text.parse().unwrap()),
+            Type::Integer { kind, unsigned } => integer(text.parse().unwrap(), *kind, *unsigned),
+            _ => unreachable!(),
+        }
+    }
+
+    // -0.0 differs from 0.0, and any NaN is the same as another
+    fn same(
+        result: &Result<Number, ArithmeticError>,
+        expected: &Result<Number, ArithmeticError>,
+    ) -> bool {
+        match (result, expected) {
+            (Ok(Number::Float(x)), Ok(Number::Float(y))) => {
+                x.to_bits() == y.to_bits() || (x.is_nan() && y.is_nan())
+            }
+            (Ok(Number::Double(x)), Ok(Number::Double(y))) => {
+                x.to_bits() == y.to_bits() || (x.is_nan() && y.is_nan())
+            }
+            _ => result == expected,
+        }
+    }
+
+    #[test]
+    fn binary_operators_agree_with_c() {
+        let mut operands = HashMap::new();
+        let mut lines = 0;
+        for line in VALUE_TABLE.lines() {
+            let mut fields = line.split(' ');
+            let first = fields.next().unwrap();
+            if first == "operands" {
+                let name = fields.next().unwrap();
+                let data_type = table_type(name);
+                let values: Vec<Number> = fields
+                    .map(|value| table_number(value, &data_type))
+                    .collect();
+                operands.insert(name, values);
+                continue;
+            }
+            let left = &operands[first];
+            let right = &operands[fields.next().unwrap()];
+            let operator = BinaryOperator::from_text(fields.next().unwrap()).unwrap();
+            let result_type = table_type(fields.next().unwrap());
+            let results: Vec<&str> = fields.collect();
+            assert_eq!(results.len(), left.len() * right.len(), "{}", line);
+
+            let pairs = left.iter().flat_map(|a| right.iter().map(move |b| (a, b)));
+            for ((a, b), expected) in pairs.zip(results) {
+                let expected = match expected {
+                    "div0" => Err(ArithmeticError::DivisionByZero),
+                    "overflow" => Err(ArithmeticError::Overflow(result_type.clone())),
+                    value => Ok(table_number(value, &result_type)),
+                };
+                let result = a.binary(operator, *b);
+                assert!(
+                    same(&result, &expected),
+                    "({}) {} {:?} ({}) {}: {:?}, C gives {:?}",
+                    a.data_type(),
+                    a,
+                    operator,
+                    b.data_type(),
+                    b,
+                    result,
+                    expected
+                );
+            }
+            lines += 1;
+        }
+        // 10 operators on 10 x 10 type pairs
+        assert_eq!(operands.len(), 10);
+        assert_eq!(lines, 10 * 10 * 10);
+    }
+
+    #[test]
+    fn float_and_double_round_apart() {
+        use BinaryOperator::*;
+        let float_sum = Number::Float(0.1).binary(Add, Number::Float(0.2)).unwrap();
+        assert_eq!(float_sum, Number::Float(0.3));
+        let double_sum = Number::Double(0.1)
+            .binary(Add, Number::Double(0.2))
+            .unwrap();
+        assert_ne!(double_sum, Number::Double(0.3));
+
+        let third = Number::Float(1.0)
+            .binary(Divide, Number::Float(3.0))
+            .unwrap();
+        assert_eq!(third.as_f64(), 0.3333333432674408);
+        let third = Number::Double(1.0)
+            .binary(Divide, Number::Double(3.0))
+            .unwrap();
+        assert_eq!(third.as_f64(), 0.333_333_333_333_333_3);
+        assert_eq!(
+            Number::Double(1.0 / 3.0).convert(&Type::Float),
+            Ok(Number::Float(0.333_333_34))
+        );
+        assert_eq!(
+            Number::int(16777217)
+                .convert(&Type::Float)
+                .unwrap()
+                .as_f64(),
+            16777216.0
+        );
+        assert_eq!(
+            Number::int(16777217)
+                .convert(&Type::Double)
+                .unwrap()
+                .as_f64(),
+            16777217.0
+        );
+        // floating division by zero is infinity, not a fault
+        assert_eq!(
+            Number::Double(1.0).binary(Divide, Number::Double(0.0)),
+            Ok(Number::Double(f64::INFINITY))
+        );
+        assert_eq!(
+            Number::Float(1.0).binary(Divide, Number::Float(0.0)),
+            Ok(Number::Float(f32::INFINITY))
+        );
+    }
+
+    #[test]
+    fn floating_to_integer_at_the_range_edges() {
+        use IntKind::*;
+        let out_of_range =
+            |kind, unsigned| Err(ArithmeticError::OutOfRange(int_type(kind, unsigned)));
+        let cases = [
+            (2147483647.9, Int, false, Ok(2147483647)),
+            (2147483648.0, Int, false, out_of_range(Int, false)),
+            (-2147483648.9, Int, false, Ok(-2147483648)),
+            (-2147483649.0, Int, false, out_of_range(Int, false)),
+            (4294967295.5, Int, true, Ok(4294967295)),
+            (4294967296.0, Int, true, out_of_range(Int, true)),
+            (-0.9, Int, true, Ok(0)),
+            (-1.0, Int, true, out_of_range(Int, true)),
+            (-9223372036854775808.0, Long, false, Ok(i64::MIN as i128)),
+            (
+                9223372036854775808.0,
+                Long,
+                false,
+                out_of_range(Long, false),
+            ),
+            (18446744073709549568.0, Long, true, Ok(18446744073709549568)),
+            (18446744073709551616.0, Long, true, out_of_range(Long, true)),
+            (127.9, Char, false, Ok(127)),
+            (128.0, Char, false, out_of_range(Char, false)),
+            (255.5, Char, true, Ok(255)),
+            (f64::NAN, Int, false, out_of_range(Int, false)),
+        ];
+        for (x, kind, unsigned, expected) in cases {
+            let expected = expected.map(|value| integer(value, kind, unsigned));
+            let data_type = int_type(kind, unsigned);
+            assert_eq!(
+                Number::Double(x).convert(&data_type),
+                expected,
+                "({}) {}",
+                data_type,
+                x
+            );
+        }
+    }
+}
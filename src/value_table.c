@@ -0,0 +1,227 @@
+/*
+ * Reference results for the tests of value.rs: every binary operator of X on
+ * every pair of arithmetic types, with operands at the edges of each type.
+ * Regenerate with
+ *
+ *     gcc -std=gnu11 -o /tmp/value_table src/value_table.c && /tmp/value_table > src/value_table.txt
+ *
+ * on x86-64, where char is signed, long is 64 bits and float arithmetic is
+ * done in float.
+ *
+ * `operands TYPE V...` lists the operands of a type, in order and without
+ * repeats. `LEFT RIGHT OP RESULT_TYPE R...` then gives the result for every
+ * left operand with every right one, the right operand changing fastest:
+ * a number, `overflow` for a signed result out of range or `div0` for an
+ * integer division by zero. Floating values are printed with enough digits
+ * to read back exactly.
+ */
+#include <float.h>
+#include <limits.h>
+#include <math.h>
+#include <stdio.h>
+
+typedef unsigned char uchar;
+typedef unsigned short ushort;
+typedef unsigned int uint;
+typedef unsigned long ulong;
+
+#define MAX_OPERANDS 9
+
+static void put_int(int x) { printf(" %d", x); }
+static void put_uint(uint x) { printf(" %u", x); }
+static void put_long(long x) { printf(" %ld", x); }
+static void put_ulong(ulong x) { printf(" %lu", x); }
+
+static void put_floating(double x, int digits)
+{
+	if (isnan(x))
+		printf(" nan");
+	else
+		printf(" %.*g", digits, x);
+}
+
+static void put_float(float x) { put_floating(x, 9); }
+static void put_double(double x) { put_floating(x, 17); }
+
+#define PUT(x)                                                                 \
+	_Generic((x), int: put_int, uint: put_uint, long: put_long,            \
+		 ulong: put_ulong, float: put_float, double: put_double)(x)
+
+#define SIGNED(T, MIN)                                                         \
+	static void add_##T(T x, T y)                                          \
+	{                                                                      \
+		T r;                                                           \
+		if (__builtin_add_overflow(x, y, &r))                          \
+			printf(" overflow");                                   \
+		else                                                           \
+			PUT(r);                                                \
+	}                                                                      \
+	static void sub_##T(T x, T y)                                          \
+	{                                                                      \
+		T r;                                                           \
+		if (__builtin_sub_overflow(x, y, &r))                          \
+			printf(" overflow");                                   \
+		else                                                           \
+			PUT(r);                                                \
+	}                                                                      \
+	static void mul_##T(T x, T y)                                          \
+	{                                                                      \
+		T r;                                                           \
+		if (__builtin_mul_overflow(x, y, &r))                          \
+			printf(" overflow");                                   \
+		else                                                           \
+			PUT(r);                                                \
+	}                                                                      \
+	static void div_##T(T x, T y)                                          \
+	{                                                                      \
+		if (y == 0)                                                    \
+			printf(" div0");                                       \
+		else if (x == MIN && y == -1)                                  \
+			printf(" overflow");                                   \
+		else                                                           \
+			PUT(x / y);                                            \
+	}
+
+/* unsigned results wrap, floating ones follow IEEE 754 */
+#define WRAPPING(T, CHECK_ZERO)                                                \
+	static void add_##T(T x, T y) { PUT((T)(x + y)); }                     \
+	static void sub_##T(T x, T y) { PUT((T)(x - y)); }                     \
+	static void mul_##T(T x, T y) { PUT((T)(x * y)); }                     \
+	static void div_##T(T x, T y)                                          \
+	{                                                                      \
+		if (CHECK_ZERO && y == 0)                                      \
+			printf(" div0");                                       \
+		else                                                           \
+			PUT((T)(x / y));                                       \
+	}
+
+SIGNED(int, INT_MIN)
+SIGNED(long, LONG_MIN)
+WRAPPING(uint, 1)
+WRAPPING(ulong, 1)
+WRAPPING(float, 0)
+WRAPPING(double, 0)
+
+/* each operand is converted to the type of the parameter, the common type */
+#define ARITHMETIC(OP, x, y)                                                   \
+	_Generic((x) + (y), int: OP##_int, uint: OP##_uint, long: OP##_long,   \
+		 ulong: OP##_ulong, float: OP##_float, double: OP##_double)(x, y)
+
+#define TYPE_NAME(x)                                                           \
+	_Generic((x), int: "int", uint: "uint", long: "long", ulong: "ulong",  \
+		 float: "float", double: "double")
+
+/* the operands of a type: 0, 1, -1, 2, 7, -7, the smallest value, the
+ * largest and the one below it, each converted to the type */
+#define OPERANDS(T, NAME, MIN, MAX, PUT_OPERAND)                               \
+	static int operands_##NAME(T values[])                                 \
+	{                                                                      \
+		T all[] = { 0, 1, -1, 2, 7, -7, MIN, MAX, MAX - 1 };           \
+		int count = 0;                                                 \
+		for (int i = 0; i < MAX_OPERANDS; i++) {                       \
+			int seen = 0;                                          \
+			for (int j = 0; j < count; j++)                        \
+				seen |= values[j] == all[i];                   \
+			if (!seen)                                             \
+				values[count++] = all[i];                      \
+		}                                                              \
+		return count;                                                  \
+	}                                                                      \
+	static void list_##NAME(void)                                          \
+	{                                                                      \
+		T values[MAX_OPERANDS];                                        \
+		int count = operands_##NAME(values);                           \
+		printf("operands %s", #NAME);                                  \
+		for (int i = 0; i < count; i++)                                \
+			PUT_OPERAND(values[i]);                                \
+		printf("\n");                                                  \
+	}
+
+OPERANDS(char, char, CHAR_MIN, CHAR_MAX, put_int)
+OPERANDS(uchar, uchar, 0, UCHAR_MAX, put_uint)
+OPERANDS(short, short, SHRT_MIN, SHRT_MAX, put_int)
+OPERANDS(ushort, ushort, 0, USHRT_MAX, put_uint)
+OPERANDS(int, int, INT_MIN, INT_MAX, put_int)
+OPERANDS(uint, uint, 0, UINT_MAX, put_uint)
+OPERANDS(long, long, LONG_MIN, LONG_MAX, put_long)
+OPERANDS(ulong, ulong, 0, ULONG_MAX, put_ulong)
+OPERANDS(float, float, -FLT_MAX, FLT_MAX, put_float)
+OPERANDS(double, double, -DBL_MAX, DBL_MAX, put_double)
+
+#define LINE(LEFT, RIGHT, OP, TYPE, RESULT)                                    \
+	do {                                                                   \
+		printf("%s %s %s %s", #LEFT, #RIGHT, OP, TYPE_NAME(TYPE));     \
+		for (int i = 0; i < left_count; i++)                           \
+			for (int j = 0; j < right_count; j++) {                \
+				LEFT x = left[i];                              \
+				RIGHT y = right[j];                            \
+				(void)x;                                       \
+				(void)y;                                       \
+				RESULT;                                        \
+			}                                                      \
+		printf("\n");                                                  \
+	} while (0)
+
+#define COMPARE(LEFT, RIGHT, OP)                                               \
+	LINE(LEFT, RIGHT, #OP, left[0] OP right[0], put_int(x OP y))
+
+#define PAIR(LEFT, RIGHT)                                                      \
+	do {                                                                   \
+		LEFT left[MAX_OPERANDS];                                       \
+		RIGHT right[MAX_OPERANDS];                                     \
+		int left_count = operands_##LEFT(left);                        \
+		int right_count = operands_##RIGHT(right);                     \
+		LINE(LEFT, RIGHT, "+", left[0] + right[0],                 \
+		     ARITHMETIC(add, x, y));                                   \
+		LINE(LEFT, RIGHT, "-", left[0] - right[0],                 \
+		     ARITHMETIC(sub, x, y));                                   \
+		LINE(LEFT, RIGHT, "*", left[0] * right[0],                 \
+		     ARITHMETIC(mul, x, y));                                   \
+		LINE(LEFT, RIGHT, "/", left[0] / right[0],                 \
+		     ARITHMETIC(div, x, y));                                   \
+		COMPARE(LEFT, RIGHT, ==);                                      \
+		COMPARE(LEFT, RIGHT, !=);                                      \
+		COMPARE(LEFT, RIGHT, <);                                       \
+		COMPARE(LEFT, RIGHT, >);                                       \
+		COMPARE(LEFT, RIGHT, <=);                                      \
+		COMPARE(LEFT, RIGHT, >=);                                      \
+	} while (0)
+
+#define WITH_EACH_RIGHT(LEFT)                                                  \
+	do {                                                                   \
+		PAIR(LEFT, char);                                              \
+		PAIR(LEFT, uchar);                                             \
+		PAIR(LEFT, short);                                             \
+		PAIR(LEFT, ushort);                                            \
+		PAIR(LEFT, int);                                               \
+		PAIR(LEFT, uint);                                              \
+		PAIR(LEFT, long);                                              \
+		PAIR(LEFT, ulong);                                             \
+		PAIR(LEFT, float);                                             \
+		PAIR(LEFT, double);                                            \
+	} while (0)
+
+int main(void)
+{
+	list_char();
+	list_uchar();
+	list_short();
+	list_ushort();
+	list_int();
+	list_uint();
+	list_long();
+	list_ulong();
+	list_float();
+	list_double();
+	WITH_EACH_RIGHT(char);
+	WITH_EACH_RIGHT(uchar);
+	WITH_EACH_RIGHT(short);
+	WITH_EACH_RIGHT(ushort);
+	WITH_EACH_RIGHT(int);
+	WITH_EACH_RIGHT(uint);
+	WITH_EACH_RIGHT(long);
+	WITH_EACH_RIGHT(ulong);
+	WITH_EACH_RIGHT(float);
+	WITH_EACH_RIGHT(double);
+	return 0;
+}
@@ -0,0 +1,614 @@
+use crate::ast::*;
+use crate::builtins::{self, Io};
+use crate::bytecode::{Constant, Instruction, Location, Module, Scalar, Variable};
+use crate::interpreter::{Call, Limit, Limits, RuntimeError};
+use crate::layout::Layout;
+use crate::value::{ArithmeticError, Number};
+
+#[cfg(test)]
+use std::io::{BufRead, Write};
+
+/// What a memory cell or an entry of the operand stack holds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Slot {
+    Number(Number),
+    /// index of a memory cell, 0 for the null pointer
+    Address(usize),
+    /// cell that was never assigned
+    Unassigned,
+}
+
+impl Slot {
+    fn from_constant(constant: &Constant) -> Slot {
+        match constant {
+            Constant::Number(number) => Slot::Number(*number),
+            Constant::Null => Slot::Address(0),
+            Constant::Text(_) => Slot::Unassigned,
+        }
+    }
+}
+
+/// An active call
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    function: usize,
+    /// next instruction to run
+    ip: usize,
+    /// address of the first cell of the frame
+    base: usize,
+}
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Runs a compiled module on a stack machine
+///
+/// Memory has the layout the interpreter gives it: cell 0 is the null
+/// pointer, the data segment follows, and each call adds a frame holding its
+/// parameters and every local of its body. Faults stop the program with the
+/// message the interpreter gives for them, at the token the faulting
+/// instruction was compiled from. A run stops with an error when it goes
+/// beyond its `Limits`, each instruction using one step of fuel.
+pub struct Vm<'a> {
+    module: &'a Module,
+    layout: Layout,
+    memory: Vec<Slot>,
+    stack: Vec<Slot>,
+    frames: Vec<Frame>,
+    /// `type name = value` for the variables of main when it returned
+    main_variables: Vec<String>,
+    io: Io<'a>,
+    limits: Limits,
+    /// instructions run so far, the fuel used
+    steps: u64,
+}
+
+impl<'a> Vm<'a> {
+    /// Prepare to run a module with the standard streams
+    ///
+    /// # Arguments
+    ///
+    /// * `module` - output of the compiler
+    ///
+    pub fn new(module: &'a Module) -> Vm<'a> {
+        Vm::with(module, Io::standard())
+    }
+
+    /// Prepare to run a module reading and writing through the given streams
+    ///
+    /// # Arguments
+    ///
+    /// * `module` - output of the compiler
+    /// * `input` - where read_int reads from
+    /// * `output` - where the print functions and printf write to
+    ///
+    #[cfg(test)]
+    pub fn with_io(
+        module: &'a Module,
+        input: Box<dyn BufRead + 'a>,
+        output: Box<dyn Write + 'a>,
+    ) -> Vm<'a> {
+        Vm::with(module, Io::new(input, output))
+    }
+
+    fn with(module: &'a Module, io: Io<'a>) -> Vm<'a> {
+        Vm {
+            module,
+            layout: Layout::from_structs(module.structs.clone()),
+            memory: Vec::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            main_variables: Vec::new(),
+            io,
+            limits: Limits::default(),
+            steps: 0,
+        }
+    }
+
+    /// Bound the resources of the next run, instead of the default limits
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Run the entry function of the module, which initialises the globals
+    /// and calls main
+    pub fn run(&mut self) -> Result<()> {
+        let span = self.module.functions[self.module.main]
+            .spans
+            .first()
+            .copied()
+            .unwrap_or_default();
+        self.limits
+            .check(Limit::Memory, self.module.data.len() as u64, span)?;
+        self.memory = self.module.data.iter().map(Slot::from_constant).collect();
+        self.stack.clear();
+        self.frames.clear();
+        self.steps = 0;
+        let result = self.enter(self.module.entry).and_then(|_| self.execute());
+        // what was printed before a fault is still shown
+        self.io
+            .flush()
+            .map_err(|message| RuntimeError::new(message, span))?;
+        result
+    }
+
+    /// `type name = value` for each variable of main's outermost block, as it
+    /// was when main returned
+    pub fn main_variables(&self) -> Vec<String> {
+        self.main_variables.clone()
+    }
+
+    /// push a frame for a call, its arguments taken from the operand stack
+    fn enter(&mut self, function: usize) -> Result<()> {
+        let code = &self.module.functions[function];
+        let base = self.memory.len();
+        if let Some(caller) = self.frames.last() {
+            let span = self.module.functions[caller.function].spans[caller.ip - 1];
+            // the entry function is not a call of the program
+            self.limits
+                .check(Limit::CallDepth, self.frames.len() as u64, span)?;
+            self.limits
+                .check(Limit::Memory, (base + code.frame) as u64, span)?;
+        }
+        let arguments = self.stack.split_off(self.stack.len() - code.parameters);
+        self.memory.extend(arguments);
+        self.memory.resize(base + code.frame, Slot::Unassigned);
+        self.frames.push(Frame {
+            function,
+            ip: 0,
+            base,
+        });
+        Ok(())
+    }
+
+    fn execute(&mut self) -> Result<()> {
+        while let Some(frame) = self.frames.last_mut() {
+            let function = &self.module.functions[frame.function];
+            let instruction = function.code[frame.ip];
+            frame.ip += 1;
+            self.steps += 1;
+            self.limits
+                .check(Limit::Fuel, self.steps, function.spans[frame.ip - 1])
+                .and_then(|_| self.step(instruction))
+                .map_err(|mut error| {
+                    error.stack = self.stack_trace();
+                    error
+                })?;
+        }
+        Ok(())
+    }
+
+    /// the calls of the program that are active, innermost first, each with
+    /// the instruction of its caller that made it
+    fn stack_trace(&self) -> Vec<Call> {
+        self.frames
+            .windows(2)
+            .rev()
+            .map(|pair| {
+                let caller = &self.module.functions[pair[0].function];
+                Call {
+                    function: self.module.functions[pair[1].function].name.clone(),
+                    span: caller.spans[pair[0].ip - 1],
+                }
+            })
+            .collect()
+    }
+
+    fn error(&self, message: String) -> RuntimeError {
+        let frame = self.frames.last().unwrap();
+        let span = self.module.functions[frame.function].spans[frame.ip - 1];
+        RuntimeError::new(message, span)
+    }
+
+    fn pop(&mut self) -> Slot {
+        self.stack.pop().unwrap()
+    }
+
+    fn pop_number(&mut self, what: &str) -> Result<Number> {
+        match self.pop() {
+            Slot::Number(number) => Ok(number),
+            _ => Err(self.error(format!("{} is not a number", what))),
+        }
+    }
+
+    fn pop_address(&mut self) -> Result<usize> {
+        match self.pop() {
+            Slot::Address(address) => Ok(address),
+            _ => Err(self.error(String::from("invalid memory access"))),
+        }
+    }
+
+    fn check_address(&self, address: usize, size: usize) -> Result<()> {
+        if address == 0 {
+            return Err(self.error(String::from("null pointer dereference")));
+        }
+        if address + size > self.memory.len() {
+            return Err(self.error(String::from("invalid memory access")));
+        }
+        Ok(())
+    }
+
+    fn arithmetic(&self, result: std::result::Result<Number, ArithmeticError>) -> Result<Slot> {
+        result.map(Slot::Number).map_err(|error| {
+            let message = match error {
+                ArithmeticError::DivisionByZero => String::from("division by zero"),
+                ArithmeticError::Overflow(data_type) => {
+                    format!("integer overflow in `{}` arithmetic", data_type)
+                }
+                ArithmeticError::OutOfRange(data_type) => {
+                    format!("value does not fit in `{}`", data_type)
+                }
+            };
+            self.error(message)
+        })
+    }
+
+    fn step(&mut self, instruction: Instruction) -> Result<()> {
+        let base = self.frames.last().unwrap().base;
+        match instruction {
+            Instruction::Push(index) => {
+                let slot = Slot::from_constant(&self.module.constants[index]);
+                self.stack.push(slot);
+            }
+            Instruction::Global(address) => self.stack.push(Slot::Address(address)),
+            Instruction::Local(offset) => self.stack.push(Slot::Address(base + offset)),
+            Instruction::Load => {
+                let address = self.pop_address()?;
+                self.check_address(address, 1)?;
+                match self.memory[address] {
+                    Slot::Unassigned => return Err(self.unassigned(address)),
+                    slot => self.stack.push(slot),
+                }
+            }
+            Instruction::Copy(cells) => {
+                let address = self.pop_address()?;
+                self.check_address(address, cells)?;
+                self.stack
+                    .extend_from_slice(&self.memory[address..address + cells]);
+            }
+            Instruction::Store(cells) => {
+                let address = self.pop_address()?;
+                self.check_address(address, cells)?;
+                let values = self.stack.split_off(self.stack.len() - cells);
+                self.memory[address..address + cells].copy_from_slice(&values);
+            }
+            Instruction::Clear(cells) => {
+                let address = self.pop_address()?;
+                self.check_address(address, cells)?;
+                self.memory[address..address + cells].fill(Slot::Unassigned);
+            }
+            Instruction::Dup(cells) => {
+                let start = self.stack.len() - cells;
+                self.stack.extend_from_within(start..);
+            }
+            Instruction::Pop(cells) => self.stack.truncate(self.stack.len() - cells),
+            Instruction::Convert(scalar) => {
+                let slot = self.pop();
+                let slot = self.convert(slot, scalar)?;
+                self.stack.push(slot);
+            }
+            Instruction::Negate => {
+                let number = self.pop_number("negated value")?;
+                let slot = self.arithmetic(number.negate())?;
+                self.stack.push(slot);
+            }
+            Instruction::Binary(operator) => {
+                let right = self.pop();
+                let left = self.pop();
+                let slot = self.binary(operator, left, right)?;
+                self.stack.push(slot);
+            }
+            Instruction::PointerAdd(stride) | Instruction::PointerSubtract(stride) => {
+                let right = self.pop();
+                let left = self.pop();
+                let (address, n) = match (left, right) {
+                    (Slot::Address(address), Slot::Number(n))
+                    | (Slot::Number(n), Slot::Address(address)) => (
+                        address as i128,
+                        n.as_integer().unwrap_or(0) * stride as i128,
+                    ),
+                    _ => {
+                        return Err(
+                            self.error(String::from("invalid operands to pointer arithmetic"))
+                        )
+                    }
+                };
+                let address = match instruction {
+                    Instruction::PointerAdd(_) => address + n,
+                    _ => address - n,
+                };
+                self.stack.push(Slot::Address(address.max(0) as usize));
+            }
+            Instruction::PointerDifference(stride) => {
+                let right = self.pop_address()?;
+                let left = self.pop_address()?;
+                self.stack.push(Slot::Number(Number::Integer {
+                    value: (left as i128 - right as i128) / stride as i128,
+                    kind: IntKind::Long,
+                    unsigned: false,
+                }));
+            }
+            Instruction::Index(stride) => {
+                let position = match self.pop() {
+                    Slot::Number(constant) => constant.as_integer().unwrap_or(0),
+                    _ => 0,
+                };
+                let address = match self.pop() {
+                    Slot::Address(address) => address as i128 + position * stride as i128,
+                    _ => {
+                        return Err(
+                            self.error(String::from("indexed value is not an array or a pointer"))
+                        )
+                    }
+                };
+                if address <= 0 {
+                    return Err(self.error(String::from("invalid memory access")));
+                }
+                self.stack.push(Slot::Address(address as usize));
+            }
+            Instruction::Bounds(size) => {
+                if let Some(Slot::Number(constant)) = self.stack.last() {
+                    let position = constant.as_integer().unwrap_or(0);
+                    if position < 0 || position >= size as i128 {
+                        return Err(self.error(format!(
+                            "index {} is out of bounds for an array of {} elements",
+                            position, size
+                        )));
+                    }
+                }
+            }
+            Instruction::Offset(offset) => {
+                let address = self.pop_address()?;
+                self.stack.push(Slot::Address(address + offset));
+            }
+            Instruction::Jump(target) => self.frames.last_mut().unwrap().ip = target,
+            Instruction::JumpIfFalse(target) => {
+                let truth = match self.pop() {
+                    Slot::Number(constant) => !constant.is_zero(),
+                    Slot::Address(address) => address != 0,
+                    Slot::Unassigned => {
+                        return Err(
+                            self.error(String::from("condition is not a number or a pointer"))
+                        )
+                    }
+                };
+                if !truth {
+                    self.frames.last_mut().unwrap().ip = target;
+                }
+            }
+            Instruction::Call(function) => self.enter(function)?,
+            Instruction::Builtin(index) => {
+                let declaration = &builtins::declarations()[index];
+                let mut numbers = Vec::new();
+                for _ in &declaration.parameters {
+                    numbers.push(self.pop_number(&format!("argument of `{}`", declaration.name))?);
+                }
+                numbers.reverse();
+                match self.io.call(&declaration.name, &numbers) {
+                    Ok(Some(number)) => self.stack.push(Slot::Number(number)),
+                    Ok(None) => {}
+                    Err(message) => return Err(self.error(message)),
+                }
+            }
+            Instruction::Printf { format, arguments } => {
+                let mut numbers = Vec::new();
+                for _ in 0..arguments {
+                    numbers.push(self.pop_number("argument of `printf`")?);
+                }
+                numbers.reverse();
+                let pieces = match &self.module.constants[format] {
+                    Constant::Text(format) => builtins::parse_format(format),
+                    _ => Err(String::from(
+                        "the format of `printf` must be a string literal",
+                    )),
+                };
+                let result = pieces.and_then(|pieces| self.io.printf(&pieces, &numbers));
+                match result {
+                    Ok(number) => self.stack.push(Slot::Number(number)),
+                    Err(message) => return Err(self.error(message)),
+                }
+            }
+            Instruction::Return => self.leave(),
+            Instruction::NoReturn => {
+                let function = self.frames.last().unwrap().function;
+                return Err(self.error(format!(
+                    "`{}` ended without returning a value",
+                    self.module.functions[function].name
+                )));
+            }
+            Instruction::Fail(message) => {
+                let message = match &self.module.constants[message] {
+                    Constant::Text(message) => message.clone(),
+                    constant => constant.to_string(),
+                };
+                return Err(self.error(message));
+            }
+        }
+        Ok(())
+    }
+
+    /// pop the frame of the current call, its result stays on the operand stack
+    fn leave(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        if frame.function == self.module.main && self.frames.len() == 1 {
+            let function = &self.module.functions[frame.function];
+            self.main_variables = function
+                .variables
+                .iter()
+                .filter(|variable| variable.depth == 0 && variable.scope.0 < frame.ip)
+                .map(|variable| {
+                    let address = match variable.location {
+                        Location::Frame(offset) => frame.base + offset,
+                        Location::Global(address) => address,
+                    };
+                    format!(
+                        "{} {} = {}",
+                        variable.data_type,
+                        variable.name,
+                        self.describe(address, &variable.data_type)
+                    )
+                })
+                .collect();
+        }
+        self.memory.truncate(frame.base);
+    }
+
+    /// the conversion of an assignment or a cast
+    fn convert(&self, slot: Slot, to: Scalar) -> Result<Slot> {
+        match (slot, to.arithmetic()) {
+            (Slot::Number(constant), None) => {
+                Ok(Slot::Address(constant.as_integer().unwrap_or(0) as usize))
+            }
+            (Slot::Number(constant), Some(to)) => self.arithmetic(constant.convert(&to)),
+            (Slot::Address(address), Some(to @ Type::Integer { .. })) => self.arithmetic(
+                Number::Integer {
+                    value: address as i128,
+                    kind: IntKind::Long,
+                    unsigned: true,
+                }
+                .convert(&to),
+            ),
+            (slot, _) => Ok(slot),
+        }
+    }
+
+    fn binary(&self, operator: BinaryOperator, left: Slot, right: Slot) -> Result<Slot> {
+        match (left, right) {
+            (Slot::Number(a), Slot::Number(b)) => self.arithmetic(a.binary(operator, b)),
+            (left, right) if operator.is_relational() => {
+                // pointers compare by address, a null pointer constant is 0
+                let long = |slot: Slot| Number::Integer {
+                    value: match slot {
+                        Slot::Address(address) => address as i128,
+                        Slot::Number(constant) => constant.as_integer().unwrap_or(0),
+                        Slot::Unassigned => 0,
+                    },
+                    kind: IntKind::Long,
+                    unsigned: false,
+                };
+                self.arithmetic(long(left).binary(operator, long(right)))
+            }
+            _ => Err(self.error(format!("invalid operands to `{}`", operator.as_str()))),
+        }
+    }
+
+    /// a read of a cell never assigned, naming the variable when the cell is
+    /// a scalar variable in scope
+    fn unassigned(&self, address: usize) -> RuntimeError {
+        let frame = self.frames.last().unwrap();
+        let ip = frame.ip - 1;
+        let named = |variable: &&Variable| {
+            let at = match variable.location {
+                Location::Frame(offset) => frame.base + offset,
+                Location::Global(address) => address,
+            };
+            at == address && variable.data_type.is_scalar()
+        };
+        let local = self.module.functions[frame.function]
+            .variables
+            .iter()
+            .filter(|variable| variable.scope.0 <= ip && ip < variable.scope.1)
+            .rev()
+            .find(named);
+        match local.or_else(|| self.module.globals.iter().find(named)) {
+            Some(variable) => {
+                self.error(format!("`{}` is used before it is assigned", variable.name))
+            }
+            None => self.error(String::from("read of uninitialised memory")),
+        }
+    }
+
+    /// text of the value stored at an address, `?` for unassigned cells
+    fn describe(&self, address: usize, data_type: &Type) -> String {
+        let cell = |address: usize| match self.memory.get(address) {
+            Some(Slot::Number(constant)) => constant.to_string(),
+            Some(Slot::Address(0)) => String::from("NULL"),
+            Some(Slot::Address(address)) => format!("0x{:x}", address),
+            _ => String::from("?"),
+        };
+        self.layout.describe(address, data_type, &cell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::parser::Parser;
+
+    // compile and run a program on the given input within the limits
+    fn run(source: &str, input: &str, limits: Limits) -> (String, Result<()>) {
+        let program = Parser::from_source(source, "format.csv").parse();
+        let module = compiler::compile(&program);
+        let mut output = Vec::new();
+        let result = {
+            let mut vm = Vm::with_io(&module, Box::new(input.as_bytes()), Box::new(&mut output));
+            vm.set_limits(limits);
+            vm.run()
+        };
+        (String::from_utf8(output).unwrap(), result)
+    }
+
+    const RECURSION: &str = "int Down(int n);
+        void main(){ print_int(Down(read_int())); }
+        int Down(int n){
+            if (n == 0) { return 0; }
+            return 1 + Down(n - 1);
+        }";
+
+    #[test]
+    fn each_limit_stops_the_run() {
+        let limits = Limits {
+            fuel: None,
+            call_depth: None,
+            memory: None,
+        };
+        let cases = [
+            (
+                "void main(){ while (1) { } }",
+                Limits {
+                    fuel: Some(1000),
+                    ..limits
+                },
+                Limit::Fuel,
+            ),
+            (
+                RECURSION,
+                Limits {
+                    call_depth: Some(20),
+                    ..limits
+                },
+                Limit::CallDepth,
+            ),
+            (
+                "void main(){ int a[100]; a[0] = 1; }",
+                Limits {
+                    memory: Some(50),
+                    ..limits
+                },
+                Limit::Memory,
+            ),
+        ];
+        for (source, limits, limit) in cases {
+            let (_, result) = run(source, "100", limits);
+            assert_eq!(result.unwrap_err().limit, Some(limit), "{}", source);
+        }
+    }
+
+    #[test]
+    fn default_limits_stop_an_endless_loop() {
+        let (_, result) = run("void main(){ while (1 == 1) { } }", "", Limits::default());
+        let error = result.unwrap_err();
+        assert_eq!(error.limit, Some(Limit::Fuel));
+        assert_eq!(error.message, "fuel limit of 10000000 steps exceeded");
+    }
+
+    #[test]
+    fn calls_do_not_use_the_native_stack() {
+        let limits = Limits {
+            call_depth: None,
+            ..Limits::default()
+        };
+        let (output, result) = run(RECURSION, "100000", limits);
+        assert_eq!((output.as_str(), result), ("100000", Ok(())));
+    }
+}